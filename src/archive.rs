@@ -0,0 +1,115 @@
+//! A single archive of every file that an installation is about to replace, so that the state from
+//! before the installation can be restored at once.
+
+use crate::{
+    planner::{InstallAction, PlannedAction},
+    Result,
+};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File},
+    path::{Component, Path, PathBuf},
+};
+
+/// Write every file in the way of a link in `plan` to a gzipped tar archive at `path`. Each file is
+/// stored at its absolute path without the leading `/`, e.g. `home/me/.zshrc`, so the archive can
+/// be restored with `tar -xzf <path> -C /`. Directories are stored with their contents, and
+/// symlinks are stored as symlinks.
+///
+/// Links that are already installed, and files that will be kept in place, are left out.
+///
+/// Returns the paths of the files that were archived.
+///
+/// # Errors
+/// + [`Error::IoError`](crate::Error::IoError) if the archive cannot be written, or a file cannot
+///   be read.
+pub fn write(path: &Path, plan: &[PlannedAction]) -> Result<Vec<PathBuf>> {
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    builder.follow_symlinks(false);
+    let mut archived = vec![];
+    for planned in plan {
+        let replaced = matches!(
+            planned.action,
+            InstallAction::BackupAndLink
+                | InstallAction::OverwriteAndLink
+                | InstallAction::ReplaceIdenticalAndLink
+                | InstallAction::DecryptAndCompare
+        );
+        if !replaced || archived.contains(&planned.link) {
+            continue;
+        }
+        let Ok(metadata) = fs::symlink_metadata(&planned.link) else {
+            continue;
+        };
+        let name: PathBuf = planned
+            .link
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        if metadata.is_dir() {
+            builder.append_dir_all(&name, &planned.link)?;
+        } else {
+            builder.append_path_with_name(&planned.link, &name)?;
+        }
+        archived.push(planned.link.clone());
+    }
+    builder.into_inner()?.finish()?;
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SymlinkList,
+        planner::{plan, PlanOptions},
+    };
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn only_files_that_will_be_replaced_are_archived() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        let home = dir.path().join("home");
+        fs::create_dir_all(dotfiles_dir.join("nvim")).unwrap();
+        fs::create_dir_all(home.join(".config/nvim")).unwrap();
+        for origin in ["zshrc", "vimrc"] {
+            fs::write(dotfiles_dir.join(origin), "").unwrap();
+        }
+        fs::write(home.join(".zshrc"), "export EDITOR=vim\n").unwrap();
+        fs::write(home.join(".config/nvim/init.lua"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}/.zshrc, origin: zshrc}}\n  - {{path: {0}/.vimrc, origin: \
+             vimrc}}\n  - {{path: {0}/.config/nvim, origin: nvim}}\n",
+            home.display()
+        ))
+        .unwrap();
+        let plan = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+
+        let archive = dir.path().join("before.tar.gz");
+        let archived = write(&archive, &plan).unwrap();
+        assert_eq!(archived, [home.join(".zshrc"), home.join(".config/nvim")]);
+
+        let relative = |path: &str| -> PathBuf {
+            home.join(path)
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect()
+        };
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(&archive).unwrap()));
+        let names: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                relative(".zshrc"),
+                relative(".config/nvim"),
+                relative(".config/nvim/init.lua")
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Moving files that are in the way of links aside, and finding them again.
+
+use crate::{
+    executor::run_step,
+    link_error,
+    log::{self, Status},
+    manifest::Operation,
+    planner::{missing_dirs, same_filesystem},
+    trash::Trash,
+    Error, LinkFailure, Result,
+};
+use chrono::TimeZone;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// Rename a file to `<filename>-backup-<date>`, or if `backup_dir` is set, move it into the
+/// [`Trash`] there and record where it came from.
+///
+/// # Params
+/// + `origin` - The origin of the link that the file is in the way of.
+/// + `path` - The file to back up, in a directory that has been canonicalized.
+/// + `operations` - The backup, and any directories created in the trash for it, are appended to
+///   this.
+///
+/// # Errors
+/// + [Error::LinkError] if the renaming fails for some reason.
+/// + [Error::IoError] if `backup_dir` is set and it or its index cannot be written.
+pub(crate) fn backup(
+    origin: &Path,
+    path: &Path,
+    backup_dir: Option<&Path>,
+    log: &mut log::Buffer,
+    operations: &mut Vec<Operation>,
+) -> Result<()> {
+    let path = path.to_owned();
+    let trash = backup_trash(&path, backup_dir);
+    let backup = match &trash {
+        Some(trash) => {
+            let backup = trash.path_for(&path);
+            let backup_dir = backup.parent().unwrap_or(&backup);
+            let missing = missing_dirs(backup_dir);
+            fs::create_dir_all(backup_dir)?;
+            operations.extend(
+                missing
+                    .into_iter()
+                    .map(|path| Operation::CreateDir { path }),
+            );
+            backup
+        }
+        None => backup_path(&path),
+    };
+    let label = format!(
+        "{}{} {} {} {}...",
+        log::symbol(Status::Backup),
+        Paint::yellow("Backing up"),
+        path.display(),
+        Paint::yellow("->"),
+        backup.display()
+    );
+    match run_step(log, label, || fs::rename(&path, &backup)) {
+        Ok(_) => {
+            if let Some(trash) = trash {
+                trash.record(&path, &backup)?;
+            }
+            operations.push(Operation::Backup {
+                from: path,
+                to: backup,
+            });
+            Ok(())
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Err(Error::PermissionDenied(path)),
+        Err(e) => Err(link_error(origin, &path, LinkFailure::BackupFailed(e))),
+    }
+}
+
+/// Returns the [`Trash`] in `backup_dir` that the file at `path` is moved into when it is backed
+/// up, or `None` if `backup_dir` isn't set or is on a different filesystem from `path`, since
+/// moving the file there would mean copying it. Then it is renamed in place instead.
+pub(crate) fn backup_trash(path: &Path, backup_dir: Option<&Path>) -> Option<Trash> {
+    let trash = Trash::at(backup_dir?.to_owned());
+    same_filesystem(path, &trash.path_for(path)).then_some(trash)
+}
+
+/// Returns the path a file will be moved to when it is backed up: `<filename>-backup-<date>`, in
+/// the same directory.
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push("-backup-");
+    backup.push(chrono::Local::now().format(BACKUP_DATE_FORMAT).to_string());
+    backup.into()
+}
+
+/// The format of the date at the end of a backup's filename.
+pub(crate) const BACKUP_DATE_FORMAT: &str = "%Y-%m-%d-%H-%M-%S";
+
+/// Returns the time at which the file at `path` was backed up, parsed from the
+/// `<filename>-backup-<date>` form of its filename, or `None` if it is not named like a backup.
+pub fn backup_time<P>(path: &P) -> Option<chrono::DateTime<chrono::Local>>
+where
+    P: AsRef<Path>,
+{
+    let file_name = path.as_ref().file_name()?.to_str()?;
+    let (_, date) = file_name.rsplit_once("-backup-")?;
+    let date = chrono::NaiveDateTime::parse_from_str(date, BACKUP_DATE_FORMAT).ok()?;
+    chrono::Local.from_local_datetime(&date).earliest()
+}
+
+/// Print every backup in `backups`, oldest first, with where it came from. If `since` is set, only
+/// the backups made within it are printed.
+///
+/// # Errors
+/// See [`Trash::entries`].
+pub fn list(backups: &Trash, since: Option<chrono::Duration>) -> Result<()> {
+    let entries = match since {
+        Some(since) => backups.entries_since(chrono::Local::now() - since)?,
+        None => backups.entries()?,
+    };
+    if entries.is_empty() {
+        log::info(format!(
+            "There are no backups in {}.",
+            backups.dir.display()
+        ));
+    }
+    for entry in entries {
+        println!(
+            "{}  {} {} {}",
+            entry.date,
+            entry.original.display(),
+            Paint::blue("<-"),
+            entry.trashed.display()
+        );
+    }
+    Ok(())
+}
+
+/// Delete all but the `keep` most recent backups of each file in `backups`, and report each one
+/// that is deleted. If `older_than` is set, only the backups made longer ago than it are deleted.
+///
+/// # Errors
+/// See [`Trash::prune`].
+pub fn prune(backups: &Trash, keep: usize, older_than: Option<chrono::Duration>) -> Result<()> {
+    let pruned = backups.prune(keep, older_than.map(|age| chrono::Local::now() - age))?;
+    for entry in &pruned {
+        log::info(format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Remove),
+            Paint::green("Deleted"),
+            entry.trashed.display(),
+            Paint::green("from"),
+            entry.date
+        ));
+    }
+    log::info(format!("Deleted {}.", log::count(pruned.len(), "backup")));
+    Ok(())
+}
+
+/// Move the most recent backup in `backups` of the file that was at `original` back in its place.
+///
+/// # Errors
+/// + [`Error::NoBackup`] if there is no backup of the file.
+/// + Any error from [`Trash::restore`].
+pub fn restore(backups: &Trash, original: &Path) -> Result<()> {
+    let entry = backups
+        .latest(original)?
+        .ok_or_else(|| Error::NoBackup(original.to_owned()))?;
+    backups.restore(&entry)?;
+    log::info(format!(
+        "{}{} {} {} {}",
+        log::symbol(Status::Backup),
+        Paint::green("Restored"),
+        entry.original.display(),
+        Paint::green("from"),
+        entry.trashed.display()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_times_are_parsed_from_backup_filenames() {
+        let time = backup_time(&"/home/me/.zshrc-backup-2022-08-01-13-45-07").unwrap();
+        assert_eq!(
+            time.naive_local(),
+            chrono::NaiveDate::from_ymd_opt(2022, 8, 1)
+                .unwrap()
+                .and_hms_opt(13, 45, 7)
+                .unwrap()
+        );
+        assert_eq!(backup_time(&"/home/me/.zshrc"), None);
+        assert_eq!(backup_time(&"/home/me/.zshrc-backup-yesterday"), None);
+    }
+}
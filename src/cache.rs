@@ -0,0 +1,116 @@
+//! A record of the links that were installed by the last run, so that links whose origin and link
+//! haven't changed since can be skipped without checking where they point.
+
+use crate::{platform, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The name of the cache file in the directory the origins are in.
+pub const FILE_NAME: &str = ".dotconfig-cache.json";
+
+/// The links that were installed, by the path they were installed at.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cache {
+    links: BTreeMap<PathBuf, Stamp>,
+}
+
+/// What an installed link and its origin looked like when the link was recorded. If either is
+/// replaced or modified, its inode or modification time changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Stamp {
+    origin: PathBuf,
+    /// The inode and modification time of the origin, in seconds and nanoseconds.
+    origin_file: (u64, i64, i64),
+    /// The inode and modification time of the link itself, not what it points to.
+    link_file: (u64, i64, i64),
+}
+
+impl Stamp {
+    /// Returns the stamp of `link` and `origin` as they are now, or `None` if either doesn't exist.
+    fn now(origin: &Path, link: &Path) -> Option<Self> {
+        let file = |metadata: fs::Metadata| platform::stamp(&metadata);
+        Some(Stamp {
+            origin: origin.to_owned(),
+            origin_file: file(fs::metadata(origin).ok()?),
+            link_file: file(fs::symlink_metadata(link).ok()?),
+        })
+    }
+}
+
+impl Cache {
+    /// Read the cache from a file. A cache that is missing or can't be read is empty, so that
+    /// every link is checked.
+    pub fn from_file<P>(path: &P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the cache to a file, replacing the file if it exists.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the file cannot be written.
+    pub fn write<P>(&self, path: &P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(fs::write(path, serde_json::to_string(self)?)?)
+    }
+
+    /// Returns whether `link` was recorded as installed to `origin`, and neither has changed since.
+    pub fn is_installed(&self, origin: &Path, link: &Path) -> bool {
+        self.links.get(link).is_some_and(|stamp| {
+            stamp.origin == origin && Stamp::now(origin, link).as_ref() == Some(stamp)
+        })
+    }
+
+    /// Record that `link` is installed to `origin`, as they are now.
+    pub fn record(&mut self, origin: &Path, link: &Path) {
+        if let Some(stamp) = Stamp::now(origin, link) {
+            self.links.insert(link.to_owned(), stamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::symlink;
+    use std::time::SystemTime;
+
+    #[test]
+    fn links_are_cached_until_they_or_their_origin_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("zshrc");
+        let link = dir.path().join(".zshrc");
+        fs::write(&origin, "").unwrap();
+        symlink(&origin, &link).unwrap();
+        let mut cache = Cache::default();
+        cache.record(&origin, &link);
+        let path = dir.path().join(FILE_NAME);
+        cache.write(&path).unwrap();
+
+        let mut cache = Cache::from_file(&path);
+        assert!(cache.is_installed(&origin, &link));
+        assert!(!cache.is_installed(&dir.path().join("bashrc"), &link));
+        fs::File::options()
+            .write(true)
+            .open(&origin)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert!(!cache.is_installed(&origin, &link));
+        cache.record(&origin, &link);
+        assert!(cache.is_installed(&origin, &link));
+        fs::remove_file(&link).unwrap();
+        assert!(!cache.is_installed(&origin, &link));
+    }
+}
@@ -0,0 +1,1670 @@
+//! Reading symlink lists, and the links in them.
+
+use crate::{
+    expand_path, link_error, log,
+    planner::{LinkMode, OnConflict},
+    Error, LinkFailure, Result,
+};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io, mem,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// The contents of a symlink list file (`symlinks.yml` by default).
+///
+/// In YAML, links are listed under `links:`, under `groups:` as a map from group name to a list of
+/// links, or both. Links in a group are listed after those under `links:`, in the order the groups
+/// appear in, and have their [`Link::group`] set. Fields under `defaults:` are set on every link
+/// that doesn't set them itself.
+///
+/// Links under `common:` are read like those under `links:`. Links under `hosts:`, a map from
+/// hostname to a list of links, only apply on that host, and have their [`Link::host`] set; see
+/// [`SymlinkList::select_host`].
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "SymlinkListFile")]
+pub struct SymlinkList {
+    pub links: Vec<Link>,
+    /// The directory the origins are in, if not the dotfiles directory. When read from a file,
+    /// this is as written; [`SymlinkList::from_files`] resolves it.
+    pub origin_base: Option<String>,
+    /// The variables that templates can use, by name (see [`template`](crate::template)).
+    pub vars: BTreeMap<String, String>,
+    /// Commands to run before and after an installation.
+    pub hooks: Hooks,
+}
+
+/// The schema of a symlink list is that of the YAML it is read from.
+impl JsonSchema for SymlinkList {
+    fn schema_name() -> String {
+        "SymlinkList".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        SymlinkListFile::json_schema(gen)
+    }
+}
+
+/// Groups of links, by name, in the order they are written in.
+type Groups = Vec<(String, Vec<Link>)>;
+
+/// A symlink list as it is written in YAML.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SymlinkListFile {
+    /// Links that are not part of a group.
+    links: Option<Vec<Link>>,
+    /// Links that apply on every host, for symlink lists that also have `hosts:`. The same as
+    /// `links:`.
+    common: Option<Vec<Link>>,
+    /// Links grouped by name, e.g. by the tool they configure.
+    #[serde(default, deserialize_with = "ordered_groups")]
+    #[schemars(with = "Option<BTreeMap<String, Vec<Link>>>")]
+    groups: Option<Groups>,
+    /// Links that only apply on some hosts, by hostname, e.g. `laptop:`. A link for this host
+    /// replaces a link for every host with the same path.
+    #[serde(default, deserialize_with = "ordered_groups")]
+    #[schemars(with = "Option<BTreeMap<String, Vec<Link>>>")]
+    hosts: Option<Groups>,
+    /// Fields to set on every link that doesn't set them itself.
+    #[serde(default)]
+    defaults: Defaults,
+    /// The oldest version of dotconfig that can read this file, e.g. "0.2.0".
+    version: Option<String>,
+    /// The directory the origins are in, if not the dotfiles directory, e.g. when the symlink list
+    /// is kept in a different repository from the files it links. Relative to the directory the
+    /// symlink list is in. Shell variables and `~` are expanded.
+    origin_base: Option<String>,
+    /// Variables that the origins of links with `template: true` can use, e.g. `email:
+    /// me@example.com`.
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+    /// Shell commands to run before and after an installation.
+    #[serde(default)]
+    hooks: Hooks,
+}
+
+/// Shell commands under `hooks:` in a symlink list. They are only run by an installation that
+/// changes something, in the directory the origins are in, with `$DOTCONFIG_DIR` set to that
+/// directory. A hook that fails is reported, but doesn't stop the installation.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    /// A command to run before anything is installed, once the installation is confirmed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_install: Option<String>,
+    /// A command to run after everything is installed, and the post-link hooks and reload
+    /// commands have run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<String>,
+}
+
+impl Hooks {
+    /// Returns whether no hook is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Hooks::default()
+    }
+}
+
+/// Fields under `defaults:` in a symlink list.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+struct Defaults {
+    /// Whether to back up files that are in the way of links.
+    backup: Option<bool>,
+    /// Whether to leave links out, rather than fail, if their origin doesn't exist.
+    optional: Option<bool>,
+    /// The group of links that are not listed under `groups:`.
+    group: Option<String>,
+    /// How to link origins that are directories.
+    mode: Option<DirMode>,
+    /// How links are created.
+    strategy: Option<Strategy>,
+    /// The tags of links that don't list their own.
+    tags: Option<Vec<String>>,
+    /// The operating systems to install links on.
+    os: Option<Vec<String>>,
+    /// The CPU architectures to install links on.
+    arch: Option<Vec<String>>,
+}
+
+impl TryFrom<SymlinkListFile> for SymlinkList {
+    type Error = String;
+
+    fn try_from(file: SymlinkListFile) -> Result<Self, String> {
+        if file.links.is_none()
+            && file.common.is_none()
+            && file.groups.is_none()
+            && file.hosts.is_none()
+        {
+            return Err("missing field `links`, `common`, `groups` or `hosts`".to_owned());
+        }
+        if let Some(version) = file
+            .version
+            .as_deref()
+            .filter(|v| parse_version(v).is_none())
+        {
+            return Err(format!(
+                "invalid version '{}', expected a version like \"{}\"",
+                version,
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        let defaults = file.defaults;
+        let mut links: Vec<_> = file
+            .links
+            .unwrap_or_default()
+            .into_iter()
+            .chain(file.common.unwrap_or_default())
+            .map(|link| Link {
+                group: defaults.group.clone(),
+                ..link
+            })
+            .collect();
+        for (group, group_links) in file.groups.unwrap_or_default() {
+            links.extend(group_links.into_iter().map(|link| Link {
+                group: Some(group.clone()),
+                ..link
+            }));
+        }
+        for (host, host_links) in file.hosts.unwrap_or_default() {
+            links.extend(host_links.into_iter().map(|link| Link {
+                group: defaults.group.clone(),
+                host: Some(host.clone()),
+                ..link
+            }));
+        }
+        for link in &mut links {
+            link.backup = link.backup.or(defaults.backup);
+            link.optional = link.optional.or(defaults.optional);
+            link.mode = link.mode.or(defaults.mode);
+            link.strategy = link.strategy.or(defaults.strategy);
+            link.tags = link.tags.take().or_else(|| defaults.tags.clone());
+            link.os = link.os.take().or_else(|| defaults.os.clone());
+            link.arch = link.arch.take().or_else(|| defaults.arch.clone());
+        }
+        Ok(SymlinkList {
+            links,
+            origin_base: file.origin_base,
+            vars: file.vars,
+            hooks: file.hooks,
+        })
+    }
+}
+
+/// Returns the numbers in a version like "0.2.0", or `None` if it isn't one.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Check that the version of dotconfig that a symlink list requires, if it sets one, is no newer
+/// than this one. This is done before the symlink list is parsed, since a newer symlink list may
+/// use fields that this version doesn't know about.
+///
+/// # Errors
+/// + [`Error::UnsupportedVersion`] if the symlink list requires a newer version.
+fn check_version(path: &Path, format: ConfigFormat, contents: &str) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Required {
+        version: Option<String>,
+    }
+
+    let required = match format {
+        ConfigFormat::Yaml => serde_yaml::from_str::<Required>(contents).ok(),
+        ConfigFormat::Toml => toml::from_str::<Required>(contents).ok(),
+        ConfigFormat::Json => serde_json::from_str::<Required>(contents).ok(),
+    }
+    .and_then(|required| required.version);
+    let Some(required) = required else {
+        return Ok(());
+    };
+    match (
+        parse_version(&required),
+        parse_version(env!("CARGO_PKG_VERSION")),
+    ) {
+        (Some(required_version), Some(version)) if required_version > version => {
+            Err(Error::UnsupportedVersion {
+                file: path.to_owned(),
+                required,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Deserializes a map from group name to a list of links, keeping the groups in the order they are
+/// written in.
+fn ordered_groups<'de, D>(deserializer: D) -> Result<Option<Groups>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct GroupsVisitor;
+
+    impl<'de> Visitor<'de> for GroupsVisitor {
+        type Value = Groups;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map from group name to a list of links")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut groups = vec![];
+            while let Some(group) = map.next_entry()? {
+                groups.push(group);
+            }
+            Ok(groups)
+        }
+    }
+
+    deserializer.deserialize_map(GroupsVisitor).map(Some)
+}
+
+/// A file format that a symlink list can be written in.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Returns the format suggested by the extension of `path`, if it is a known one.
+    pub fn from_extension<P>(path: &P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match path.as_ref().extension()?.to_str()? {
+            "yml" | "yaml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Json => "JSON",
+        })
+    }
+}
+
+/// Where the symlink lists are, and how to read them, for the commands that read them without
+/// installing.
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    /// The directory that holds the config files.
+    pub dotfiles_dir: PathBuf,
+    /// The full paths of the symlink lists. Links in later lists override links in earlier lists.
+    pub symlink_list_paths: Vec<PathBuf>,
+    /// Read the symlink lists in this format, whatever their extension.
+    pub config_format: Option<ConfigFormat>,
+    /// What the origins in each symlink list are relative to.
+    pub origins_relative_to: OriginBase,
+    /// Look for origins in this directory, whatever the symlink lists set.
+    pub origin_base: Option<PathBuf>,
+    /// Only read the links for this host (see [`SymlinkList::select_host`]), or if `None`, the
+    /// links for every host.
+    pub host: Option<String>,
+}
+
+impl Source {
+    /// Read the symlink lists, returning them with the directory the origins are in.
+    ///
+    /// # Errors
+    /// + [`Error::MissingDotfilesDir`] if the dotfiles directory doesn't exist.
+    /// + Any error from [`SymlinkList::from_files`].
+    pub fn read(&self) -> Result<(SymlinkList, PathBuf)> {
+        if !self.dotfiles_dir.exists() {
+            return Err(Error::MissingDotfilesDir(self.dotfiles_dir.clone()));
+        }
+        let symlink_list = SymlinkList::from_files(
+            &self.symlink_list_paths,
+            self.config_format,
+            self.origins_relative_to,
+            self.host.as_deref(),
+        )?;
+        let origin_dir = crate::install::origin_dir(
+            &self.dotfiles_dir,
+            self.origin_base.as_deref(),
+            &symlink_list,
+        );
+        Ok((symlink_list, origin_dir))
+    }
+
+    /// Returns the format the symlink list at `path` is read in: [`Source::config_format`], or
+    /// the format suggested by its extension, or YAML.
+    pub fn format_of(&self, path: &Path) -> ConfigFormat {
+        self.config_format
+            .or_else(|| ConfigFormat::from_extension(&path))
+            .unwrap_or(ConfigFormat::Yaml)
+    }
+}
+
+impl SymlinkList {
+    /// Read and parse a symlink list from a file, in the format suggested by its extension, or
+    /// YAML if the extension is not a known one.
+    ///
+    /// # Errors
+    /// See [`SymlinkList::from_file_as`].
+    pub fn from_file<P>(path: &P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        SymlinkList::from_file_as(path, None)
+    }
+
+    /// Read and parse a symlink list from a file in `format`, or if `format` is `None`, in the
+    /// format suggested by its extension, falling back to YAML.
+    ///
+    /// # Errors
+    /// + [`Error::MissingSymlinkListFile`] if `path` does not exist.
+    /// + [`Error::IoError`] if the file cannot be read.
+    /// + [`Error::UnsupportedVersion`] if the file requires a newer version of dotconfig.
+    /// + [`Error::UnknownField`] if a YAML file contains a field that is not part of a symlink
+    ///   list.
+    /// + [`Error::YamlError`], [`Error::TomlError`] or [`Error::JsonError`] if the file is not a
+    ///   valid symlink list.
+    pub fn from_file_as<P>(path: &P, format: Option<ConfigFormat>) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Err(Error::MissingSymlinkListFile(path.to_owned()));
+        }
+        let detected = ConfigFormat::from_extension(&path);
+        let format = match (format, detected) {
+            (Some(format), Some(detected)) if format != detected => {
+                log::debug(format!(
+                    "{} {} {} {}{}",
+                    Paint::blue("Reading"),
+                    path.display(),
+                    Paint::blue("as"),
+                    format,
+                    Paint::blue(format!(", although its extension suggests {}", detected))
+                ));
+                format
+            }
+            (format, detected) => format.or(detected).unwrap_or(ConfigFormat::Yaml),
+        };
+        let contents = fs::read_to_string(path)?;
+        check_version(path, format, &contents)?;
+        match format {
+            ConfigFormat::Yaml => {
+                serde_yaml::from_str(&contents).map_err(|e| unknown_field_error(path, &contents, e))
+            }
+            ConfigFormat::Toml => Ok(toml::from_str(&contents)?),
+            ConfigFormat::Json => Ok(serde_json::from_str(&contents)?),
+        }
+    }
+
+    /// Read several symlink lists and combine their links, in order. Each file is read as by
+    /// [`SymlinkList::from_file_as`].
+    ///
+    /// A link in a later file overrides any link in an earlier file with the same `path`, so that
+    /// a file of machine-local links can be layered on top of a shared one. An entry with several
+    /// paths only loses the paths that are overridden.
+    ///
+    /// If `origins` is [`OriginBase::SymlinkList`], the origins of each file's links are made
+    /// absolute, by joining them onto the directory the file is really in, once symlinks are
+    /// resolved. Otherwise they are left relative to the dotfiles directory.
+    ///
+    /// A file's [`SymlinkList::origin_base`] is made absolute the same way, and the one from the
+    /// last file that sets it is kept. [`SymlinkList::vars`] and [`SymlinkList::hooks`] are
+    /// combined, with those in later files taking precedence.
+    ///
+    /// If `host` is set, each file's links are narrowed down to those for `host` (see
+    /// [`SymlinkList::select_host`]) before it is combined with the others. Otherwise, the links
+    /// for every host are kept.
+    ///
+    /// # Errors
+    /// + Any error from [`SymlinkList::from_file_as`].
+    /// + [`Error::IoError`] if the real location of a file can't be found.
+    /// + [`Error::ShellexpandLookupError`] if an `origin_base` references an unset shell variable.
+    pub fn from_files<P>(
+        paths: &[P],
+        format: Option<ConfigFormat>,
+        origins: OriginBase,
+        host: Option<&str>,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut combined = SymlinkList {
+            links: Vec::new(),
+            origin_base: None,
+            vars: BTreeMap::new(),
+            hooks: Hooks::default(),
+        };
+        for path in paths {
+            let mut symlink_list = SymlinkList::from_file_as(path, format)?;
+            if let Some(host) = host {
+                symlink_list.select_host(host);
+            }
+            let real_path = fs::canonicalize(path)?;
+            let dir = real_path.parent().unwrap_or(Path::new("/"));
+            if origins == OriginBase::SymlinkList {
+                for link in &mut symlink_list.links {
+                    link.origin = dir.join(&link.origin).to_string_lossy().into_owned();
+                }
+            }
+            if let Some(origin_base) = symlink_list.origin_base.take() {
+                let origin_base = dir.join(expand_path(&origin_base)?);
+                combined.origin_base = Some(origin_base.to_string_lossy().into_owned());
+            }
+            combined.vars.append(&mut symlink_list.vars);
+            let hooks = mem::take(&mut symlink_list.hooks);
+            combined.hooks = Hooks {
+                pre_install: hooks.pre_install.or(combined.hooks.pre_install.take()),
+                post_install: hooks.post_install.or(combined.hooks.post_install.take()),
+            };
+            combined.override_with(symlink_list);
+        }
+        Ok(combined)
+    }
+
+    /// Append the links in `other`, removing the paths it links from the links already here.
+    /// Paths are compared with shell variables and `~` expanded, where possible.
+    fn override_with(&mut self, other: SymlinkList) {
+        let expand = |path: &String| expand_path(path).unwrap_or_else(|_| PathBuf::from(path));
+        let overridden: Vec<_> = other
+            .links
+            .iter()
+            .flat_map(|l| &l.path)
+            .map(expand)
+            .collect();
+        for link in &mut self.links {
+            link.path.retain(|path| !overridden.contains(&expand(path)));
+        }
+        self.links.retain(|link| !link.path.is_empty());
+        self.links.extend(other.links);
+    }
+
+    /// Returns the links as a symlink list in `format`, with those in a group listed under
+    /// `groups:` and the others under `links:`. Fields set under `defaults:` are set on each link,
+    /// and paths and origins are left as they are written.
+    ///
+    /// # Errors
+    /// + [`Error::YamlError`], [`Error::TomlSerializeError`] or [`Error::JsonError`] if the links
+    ///   can't be written in `format`.
+    pub fn to_string_as(&self, format: ConfigFormat) -> Result<String> {
+        #[derive(Serialize)]
+        struct File<'a> {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            origin_base: Option<&'a str>,
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            vars: &'a BTreeMap<String, String>,
+            #[serde(skip_serializing_if = "Hooks::is_empty")]
+            hooks: &'a Hooks,
+            links: Vec<&'a Link>,
+            #[serde(skip_serializing_if = "Vec::is_empty", serialize_with = "ordered_map")]
+            groups: Vec<(&'a str, Vec<&'a Link>)>,
+        }
+
+        fn ordered_map<S>(groups: &[(&str, Vec<&Link>)], serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_map(groups.iter().map(|(name, links)| (name, links)))
+        }
+
+        let mut file = File {
+            origin_base: self.origin_base.as_deref(),
+            vars: &self.vars,
+            hooks: &self.hooks,
+            links: vec![],
+            groups: vec![],
+        };
+        for link in &self.links {
+            match &link.group {
+                None => file.links.push(link),
+                Some(group) => match file.groups.iter_mut().find(|(name, _)| name == group) {
+                    Some((_, links)) => links.push(link),
+                    None => file.groups.push((group, vec![link])),
+                },
+            }
+        }
+        Ok(match format {
+            ConfigFormat::Yaml => serde_yaml::to_string(&file)?,
+            ConfigFormat::Toml => toml::to_string(&file)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(&file)? + "\n",
+        })
+    }
+
+    /// Returns the links, with each link whose origin is a glob replaced by a link for each file
+    /// that matches it, and each link that is [`DirMode::Recurse`] and whose origin is a directory
+    /// replaced by a link for each file inside of it, at the same place under the link's path.
+    /// Files in directories inside of the origin are included, and symlinks in it are linked like
+    /// files. Other links, and globs that match nothing, are kept as they are.
+    ///
+    /// # Params
+    /// + `dotfiles_dir` - The directory the origins are in.
+    ///
+    /// # Errors
+    /// + [`Error::LinkError`] if a link whose origin is a glob sets `rename`.
+    /// + [`Error::IoError`] if an origin directory cannot be read.
+    pub fn expand_origins<P>(&self, dotfiles_dir: &P) -> Result<SymlinkList>
+    where
+        P: AsRef<Path>,
+    {
+        let mut globbed = vec![];
+        for entry in &self.links {
+            if !is_glob(&entry.origin) {
+                globbed.push(entry.clone());
+                continue;
+            }
+            let matches = glob(dotfiles_dir.as_ref(), &entry.origin)?;
+            if matches.is_empty() {
+                globbed.push(entry.clone());
+                continue;
+            }
+            if entry.rename.is_some() {
+                return Err(link_error(
+                    &dotfiles_dir.as_ref().join(&entry.origin),
+                    Path::new(&entry.path[0]),
+                    LinkFailure::RenamedGlob,
+                ));
+            }
+            // Each path is the directory the matches are linked in
+            let path: Vec<_> = entry
+                .path
+                .iter()
+                .map(|path| format!("{}/", path.trim_end_matches('/')))
+                .collect();
+            for origin in matches {
+                globbed.push(Link {
+                    path: path.clone(),
+                    origin,
+                    ..entry.clone()
+                });
+            }
+        }
+
+        let mut links = vec![];
+        for entry in globbed {
+            let origin = dotfiles_dir.as_ref().join(&entry.origin);
+            if entry.mode != Some(DirMode::Recurse) || !origin.is_dir() {
+                links.push(entry);
+                continue;
+            }
+            let mut files = vec![];
+            files_in(&origin, Path::new(""), &mut files)?;
+            for file in files {
+                let path = entry
+                    .path
+                    .iter()
+                    .map(|path| {
+                        // The path of the directory the link would be, as `resolve_link` finds it
+                        let dir = match &entry.rename {
+                            Some(rename) => format!("{}/{}", path.trim_end_matches('/'), rename),
+                            None if path.ends_with('/') => format!(
+                                "{}{}",
+                                path,
+                                origin.file_name().unwrap_or_default().to_string_lossy()
+                            ),
+                            None => path.clone(),
+                        };
+                        format!("{}/{}", dir, file.display())
+                    })
+                    .collect();
+                links.push(Link {
+                    path,
+                    origin: Path::new(&entry.origin)
+                        .join(&file)
+                        .to_string_lossy()
+                        .into_owned(),
+                    rename: None,
+                    mode: None,
+                    ..entry.clone()
+                });
+            }
+        }
+        Ok(SymlinkList {
+            links,
+            origin_base: self.origin_base.clone(),
+            vars: self.vars.clone(),
+            hooks: self.hooks.clone(),
+        })
+    }
+
+    /// Remove the links for hosts other than `host`, and the paths of the links for `host` from the
+    /// links for every host, so that a link for `host` replaces a link for every host with the same
+    /// path. The links for `host` are moved to the end.
+    pub fn select_host(&mut self, host: &str) {
+        let (for_host, common): (Vec<_>, Vec<_>) = mem::take(&mut self.links)
+            .into_iter()
+            .filter(|link| link.is_for_host(host))
+            .partition(|link| link.host.is_some());
+        self.links = common;
+        self.override_with(SymlinkList {
+            links: for_host,
+            origin_base: None,
+            vars: BTreeMap::new(),
+            hooks: Hooks::default(),
+        });
+    }
+
+    /// Keep only the links that have one of the tags in `only`, if it isn't empty, and remove those
+    /// that have one of the tags in `exclude`. Links without tags are always kept. Returns the tags
+    /// in `only` and `exclude` that no link has, which are likely misspelled.
+    pub fn select_tags<'a>(&mut self, only: &'a [String], exclude: &'a [String]) -> Vec<&'a str> {
+        let unknown = only
+            .iter()
+            .chain(exclude)
+            .filter(|tag| !self.links.iter().any(|link| link.has_tag(&[tag])))
+            .map(String::as_str)
+            .collect();
+        self.links.retain(|link| {
+            link.tags.is_none()
+                || ((only.is_empty() || link.has_tag(only)) && !link.has_tag(exclude))
+        });
+        unknown
+    }
+
+    /// Remove every link that is in one of `groups`. Returns the names in `groups` that no link is
+    /// in, which are likely misspelled.
+    pub fn exclude_groups<'a>(&mut self, groups: &'a [String]) -> Vec<&'a str> {
+        let unknown = groups
+            .iter()
+            .filter(|group| !self.links.iter().any(|link| link.is_in(&[group])))
+            .map(String::as_str)
+            .collect();
+        self.links.retain(|link| !link.is_in(groups));
+        unknown
+    }
+}
+
+/// A single entry in a [`SymlinkList`].
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+#[schemars(description = "A single entry in a symlink list.")]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    /// The paths the symlink will be created at, one symlink per path. Shell variables and `~` are
+    /// expanded. In YAML, this is either a single string or a list of strings.
+    #[serde(deserialize_with = "one_or_more_paths")]
+    #[schemars(with = "OneOrMorePaths")]
+    pub path: Vec<String>,
+    /// The path of the file the symlink will point to, relative to the dotfiles directory. If it
+    /// contains `*`, `?` or `[...]`, it is a glob, and a symlink is created for each file that
+    /// matches it, in the directory `path`.
+    pub origin: String,
+    /// Whether to back up a file that is in the way of the symlink. If `false`, the file is
+    /// deleted instead. Unset means `true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<bool>,
+    /// The filename of the symlink, if `path` is the directory it will be created in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+    /// How to link an origin that is a directory: as a whole (`link`), or by creating real
+    /// directories at `path` and linking each file inside (`recurse`). Unset means `link`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<DirMode>,
+    /// How the link is created: as a symlink, a copy or a hard link to the origin, for programs
+    /// that refuse to follow symlinks. Unset means `--mode`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<Strategy>,
+    /// Whether to leave the link out, rather than fail, if its origin doesn't exist. Useful for
+    /// files that only exist on some machines. Unset means `false`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optional: Option<bool>,
+    /// Labels to select the link by with `--only` and `--exclude`, e.g. `[work, gui]`. A link
+    /// without tags is always installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Only install the link on these operating systems, e.g. `[linux, macos]`. The names are
+    /// those Rust uses, such as `linux`, `macos` and `freebsd`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<Vec<String>>,
+    /// Only install the link on these CPU architectures, e.g. `[x86_64, aarch64]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<Vec<String>>,
+    /// Only install the link if this path exists, e.g. `~/.local/share/nvim` for a plugin's
+    /// config. Shell variables and `~` are expanded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub if_exists: Option<String>,
+    /// Only install the link if this path doesn't exist. Shell variables and `~` are expanded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unless_exists: Option<String>,
+    /// Whether the origin is encrypted with age (if its name ends in `.age`) or gpg. Instead of
+    /// being linked, it is decrypted into a new file at the path that only its owner can read.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    /// Whether the origin is a template. Each `{{ name }}` in it is replaced by a variable from
+    /// `vars:`, a built-in like `hostname` or `os`, or `env.NAME` for an environment variable, and
+    /// the link points to the rendered file (see [`template`](crate::template)).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub template: bool,
+    /// A note about why the link exists. It has no effect on how the link is installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// A shell command that generates the origin, e.g. from a template. It is run in the dotfiles
+    /// directory before installing, if the origin doesn't exist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// A shell command to run after the link is created or changed, e.g. to make a program reload
+    /// its config. It is not run if the link was already installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reload: Option<String>,
+    /// A shell command to run after each of the link's paths is created or changed, e.g.
+    /// `fc-cache` for fonts, with `$DOTCONFIG_LINK` set to the path and `$DOTCONFIG_ORIGIN` to the
+    /// origin. Unlike `reload`, it is run once per path, and is a hook like those under `hooks:`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_link: Option<String>,
+    /// The empty `link:` key that may start an entry, as in the example in the README. It has no
+    /// effect.
+    #[serde(default, rename = "link", skip_serializing)]
+    #[schemars(with = "Option<()>")]
+    pub marker: Option<IgnoredAny>,
+    /// The group the link is listed under in the symlink list, if any. This is not a field in
+    /// YAML; it is set from the `groups:` map, or from `defaults:` for links under `links:`.
+    #[serde(skip)]
+    pub group: Option<String>,
+    /// The host the link is listed under in the symlink list, if it is only for one host. This is
+    /// not a field in YAML; it is set from the `hosts:` map.
+    #[serde(skip)]
+    pub host: Option<String>,
+}
+
+impl Link {
+    /// Returns whether the link is listed under one of `groups`.
+    pub fn is_in<S>(&self, groups: &[S]) -> bool
+    where
+        S: AsRef<str>,
+    {
+        self.group
+            .as_deref()
+            .is_some_and(|group| groups.iter().any(|g| g.as_ref() == group))
+    }
+
+    /// Returns whether the link has one of `tags`.
+    pub fn has_tag<S>(&self, tags: &[S]) -> bool
+    where
+        S: AsRef<str>,
+    {
+        self.tags
+            .iter()
+            .flatten()
+            .any(|tag| tags.iter().any(|t| t.as_ref() == tag))
+    }
+
+    /// Returns whether the link applies on `host`: it is for every host, or for one whose name is
+    /// `host`, or `host` without its domain. Hostnames are compared without regard to case.
+    pub fn is_for_host(&self, host: &str) -> bool {
+        let short = host.split('.').next().unwrap_or(host);
+        self.host
+            .as_deref()
+            .is_none_or(|name| name.eq_ignore_ascii_case(host) || name.eq_ignore_ascii_case(short))
+    }
+
+    /// Returns what to do with a file that is in the way of the link. Setting `backup` on the link
+    /// or in the symlink list's defaults chooses [`OnConflict::Backup`] or
+    /// [`OnConflict::Overwrite`]; otherwise it is `default`.
+    pub fn on_conflict(&self, default: OnConflict) -> OnConflict {
+        match self.backup {
+            Some(true) => OnConflict::Backup,
+            Some(false) => OnConflict::Overwrite,
+            None => default,
+        }
+    }
+
+    /// Returns whether to leave the link out if its origin doesn't exist, which is only the case
+    /// if `optional: true` is set on the link or in the symlink list's defaults.
+    pub fn optional(&self) -> bool {
+        self.optional.unwrap_or(false)
+    }
+
+    /// Returns how the link is created: [`LinkMode::Decrypt`] if it is [`Link::encrypted`], its
+    /// [`Link::strategy`] if it has one, and `default` otherwise.
+    pub fn link_mode(&self, default: LinkMode) -> LinkMode {
+        match (self.encrypted, self.strategy) {
+            (true, _) => LinkMode::Decrypt,
+            (false, Some(strategy)) => strategy.into(),
+            (false, None) => default,
+        }
+    }
+}
+
+/// The schema of [`Link::path`], which is deserialized by [`one_or_more_paths`].
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[schemars(description = "A path, or a non-empty list of paths.")]
+enum OneOrMorePaths {
+    One(String),
+    More(#[schemars(length(min = 1))] Vec<String>),
+}
+
+/// Deserializes either a single path or a non-empty list of paths into a list of paths.
+fn one_or_more_paths<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Paths;
+
+    impl<'de> Visitor<'de> for Paths {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a path or a list of paths")
+        }
+
+        fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+            Ok(vec![path.to_owned()])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut paths = vec![];
+            while let Some(path) = seq.next_element()? {
+                paths.push(path);
+            }
+            if paths.is_empty() {
+                return Err(de::Error::invalid_length(0, &self));
+            }
+            Ok(paths)
+        }
+    }
+
+    deserializer.deserialize_any(Paths)
+}
+
+/// Turns an "unknown field" error from parsing a symlink list into an [`Error::UnknownField`] that
+/// names the entry the field appeared in. Other errors are returned as they are.
+///
+/// # Params
+/// + `file` - The path of the symlink list.
+/// + `contents` - The contents of the symlink list, used to find the entry's `path`.
+/// + `error` - The error returned by the parser.
+fn unknown_field_error(file: &Path, contents: &str, error: serde_yaml::Error) -> Error {
+    // The message looks like "links[1]: unknown field `origins`, expected one of `path`, ... at
+    // line 5 column 5".
+    let message = error.to_string();
+    let Some((prefix, rest)) = message.split_once("unknown field `") else {
+        return error.into();
+    };
+    let Some((field, rest)) = rest.split_once('`') else {
+        return error.into();
+    };
+    let expected = rest.trim_start_matches(", expected ");
+    let expected = expected.split(" at line ").next().unwrap_or(expected);
+
+    // The prefix is "<list>[<index>]: " for a link under `links:` or `common:`, or
+    // "<list>.<name>[<index>]: " for a link in a group or for a host.
+    let location = prefix.trim_end_matches(": ");
+    let link = ["links", "common", "groups", "hosts"]
+        .into_iter()
+        .find_map(|list| {
+            let (name, index) = location
+                .strip_prefix(list)?
+                .strip_suffix(']')?
+                .rsplit_once('[')?;
+            let name = match name.strip_prefix('.') {
+                Some(name) => Some(name),
+                None if name.is_empty() => None,
+                None => return None,
+            };
+            Some((list, name, index.parse::<usize>().ok()?))
+        });
+    let mut entry = match link {
+        Some((list, name, index)) => {
+            let path = serde_yaml::from_str::<serde_yaml::Value>(contents)
+                .ok()
+                .and_then(|file| {
+                    let links = match name {
+                        Some(name) => file.get(list)?.get(name)?,
+                        None => file.get(list)?,
+                    };
+                    links.get(index)?.get("path")?.as_str().map(str::to_owned)
+                });
+            let mut entry = format!("link #{}", index + 1);
+            match (list, name) {
+                ("common", _) => entry.push_str(" under common"),
+                ("groups", Some(name)) => entry.push_str(&format!(" in group {}", name)),
+                ("hosts", Some(name)) => entry.push_str(&format!(" for host {}", name)),
+                _ => {}
+            }
+            if let Some(path) = path {
+                entry.push_str(&format!(" (path: {})", path));
+            }
+            entry
+        }
+        None => "the top level".to_owned(),
+    };
+    if let Some(location) = error.location() {
+        entry.push_str(&format!(" on line {}", location.line()));
+    }
+    Error::UnknownField {
+        file: file.to_owned(),
+        entry,
+        field: field.to_owned(),
+        expected: expected.to_owned(),
+    }
+}
+
+/// How a link whose origin is a directory is created.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DirMode {
+    /// A single link to the directory.
+    #[default]
+    Link,
+    /// Real directories in place of the directory and each directory inside of it, with a link
+    /// to each file, so that other files can be added next to the links without ending up in the
+    /// dotfiles directory.
+    Recurse,
+}
+
+/// How a single link is created, as chosen by its [`Link::strategy`].
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// See [`LinkMode::Symlink`].
+    #[default]
+    Symlink,
+    /// See [`LinkMode::Copy`].
+    Copy,
+    /// See [`LinkMode::Hardlink`].
+    Hardlink,
+}
+
+impl From<Strategy> for LinkMode {
+    fn from(strategy: Strategy) -> Self {
+        match strategy {
+            Strategy::Symlink => LinkMode::Symlink,
+            Strategy::Copy => LinkMode::Copy,
+            Strategy::Hardlink => LinkMode::Hardlink,
+        }
+    }
+}
+
+/// What the origins in a symlink list are relative to.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OriginBase {
+    /// The dotfiles directory
+    #[default]
+    DotfilesDir,
+    /// The directory the symlink list is in, once symlinks are resolved, e.g. when the symlink
+    /// list is a symlink to a file shared by several dotfiles directories
+    SymlinkList,
+}
+
+/// Append the path of every file in `dir`, and in the directories inside of it, relative to `dir`
+/// and prefixed with `prefix`, to `files`, in order by name. Symlinks are not followed.
+fn files_in(dir: &Path, prefix: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let relative = prefix.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            files_in(&entry.path(), &relative, files)?;
+        } else {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `origin` is a glob, rather than the path of a single file.
+pub(crate) fn is_glob(origin: &str) -> bool {
+    origin.contains(['*', '?', '['])
+}
+
+/// Returns the paths of the files that `pattern` matches, in the form it is written in, sorted by
+/// name. Each component of `pattern` that is a glob only matches the names in a single directory,
+/// and `*` and `?` only match a leading `.` if the component starts with one, as in the shell.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory `pattern` is relative to.
+/// + `pattern` - A [`Link::origin`] that [`is_glob`].
+///
+/// # Errors
+/// + [`Error::IoError`] if a directory that is matched against cannot be read.
+fn glob(dotfiles_dir: &Path, pattern: &str) -> Result<Vec<String>> {
+    let mut matches = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let component = component.as_os_str();
+        let name = component.to_string_lossy();
+        if !is_glob(&name) {
+            matches = matches
+                .into_iter()
+                .map(|path| path.join(component))
+                .collect();
+            continue;
+        }
+        let mut next = vec![];
+        for path in matches {
+            let Ok(entries) = fs::read_dir(dotfiles_dir.join(&path)) else {
+                continue;
+            };
+            let mut names = entries
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+            next.extend(
+                names
+                    .into_iter()
+                    .filter(|file_name| wildcard_match(&name, &file_name.to_string_lossy()))
+                    .map(|file_name| path.join(file_name)),
+            );
+        }
+        matches = next;
+    }
+    Ok(matches
+        .into_iter()
+        .filter(|path| fs::symlink_metadata(dotfiles_dir.join(path)).is_ok())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Returns whether `name` matches the glob `pattern`, in which `*` matches any characters, `?` a
+/// single character, and `[...]` a single character in the brackets, or not in them if they start
+/// with `!`. Ranges like `[a-z]` are allowed.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some(('[', rest)) => {
+                let Some(close) = rest.iter().skip(1).position(|&c| c == ']') else {
+                    return name.first() == Some(&'[') && matches(rest, &name[1..]);
+                };
+                let (negated, set) = match &rest[..close + 1] {
+                    ['!', set @ ..] => (true, set),
+                    set => (false, set),
+                };
+                let Some(&c) = name.first() else {
+                    return false;
+                };
+                let mut found = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        found |= set[i] <= c && c <= set[i + 2];
+                        i += 3;
+                    } else {
+                        found |= set[i] == c;
+                        i += 1;
+                    }
+                }
+                found != negated && matches(&rest[close + 2..], &name[1..])
+            }
+            Some((&c, rest)) => name.first() == Some(&c) && matches(rest, &name[1..]),
+        }
+    }
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+/// Search `start` and its ancestors for a directory containing `symlink_list`, the way git finds
+/// the root of a repository. The search stops after the home directory or the filesystem root.
+///
+/// # Params
+/// + `start` - The directory to start searching from, usually the current directory.
+/// + `symlink_list` - The path of the symlink list file, relative to the dotfiles directory.
+pub fn find_dotfiles_dir<P, Q>(start: &P, symlink_list: &Q) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let home = dirs_next::home_dir();
+    for dir in start.as_ref().ancestors() {
+        if dir.join(symlink_list).is_file() {
+            return Some(dir.to_owned());
+        }
+        if Some(dir) == home.as_deref() {
+            break;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        planner::{plan, InstallAction, PlanOptions},
+        platform,
+    };
+
+    #[test]
+    fn unknown_fields_name_the_entry_they_appear_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("symlinks.yml");
+        fs::write(
+            &file,
+            "links:\n  - link:\n    path: ~/.zshrc\n    origin: zshrc\n  - path: ~/.vimrc\n    origins: vimrc\n",
+        )
+        .unwrap();
+
+        match SymlinkList::from_file(&file) {
+            Err(Error::UnknownField { entry, field, .. }) => {
+                assert_eq!(entry, "link #2 (path: ~/.vimrc) on line 6");
+                assert_eq!(field, "origins");
+            }
+            other => panic!("expected an unknown field error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_fields_in_common_links_name_their_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("symlinks.yml");
+        fs::write(
+            &file,
+            "common:\n  - path: ~/.zshrc\n    origin: zshrc\n  - path: ~/.vimrc\n    origins: vimrc\n",
+        )
+        .unwrap();
+
+        match SymlinkList::from_file(&file) {
+            Err(Error::UnknownField { entry, field, .. }) => {
+                assert_eq!(entry, "link #2 under common (path: ~/.vimrc) on line 5");
+                assert_eq!(field, "origins");
+            }
+            other => panic!("expected an unknown field error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_fields_in_host_links_name_their_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("symlinks.yml");
+        fs::write(
+            &file,
+            "hosts:\n  laptop:\n    - path: ~/.zshrc\n      origin: zshrc\n      hots: laptop\n",
+        )
+        .unwrap();
+
+        match SymlinkList::from_file(&file) {
+            Err(Error::UnknownField { entry, field, .. }) => {
+                assert_eq!(entry, "link #1 for host laptop (path: ~/.zshrc) on line 5");
+                assert_eq!(field, "hots");
+            }
+            other => panic!("expected an unknown field error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn newer_symlink_lists_ask_for_an_upgrade() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("symlinks.yml");
+        // The version is checked before fields this version doesn't know about.
+        fs::write(
+            &file,
+            "version: 99.0.0
+links:
+  - path: ~/.zshrc
+    new-field: 1
+",
+        )
+        .unwrap();
+        match SymlinkList::from_file(&file) {
+            Err(Error::UnsupportedVersion { required, .. }) => assert_eq!(required, "99.0.0"),
+            other => panic!("expected an unsupported version error, got {:?}", other),
+        }
+
+        let file = dir.path().join("symlinks.json");
+        fs::write(
+            &file,
+            format!(
+                r#"{{"version": "{}", "links": [{{"path": "~/.zshrc", "origin": "zshrc"}}]}}"#,
+                env!("CARGO_PKG_VERSION")
+            ),
+        )
+        .unwrap();
+        assert!(SymlinkList::from_file(&file).is_ok());
+    }
+
+    #[test]
+    fn a_list_of_paths_creates_one_link_per_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("theme"), "").unwrap();
+        fs::write(
+            dotfiles_dir.join("symlinks.yml"),
+            format!(
+                "links:\n  - path: [{0}/a/theme, {0}/b/theme]\n    origin: theme\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+        let symlink_list = SymlinkList::from_file(&dotfiles_dir.join("symlinks.yml")).unwrap();
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        let links: Vec<_> = planned.iter().map(|p| p.link.clone()).collect();
+        assert_eq!(
+            links,
+            [dir.path().join("a/theme"), dir.path().join("b/theme")]
+        );
+        assert!(planned.iter().all(|p| p.origin == planned[0].origin));
+    }
+
+    #[test]
+    fn groups_are_flattened_in_order() {
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "links:\n  - {path: ~/.profile, origin: profile}\ngroups:\n  zsh:\n    - {path: \
+             ~/.zshrc, origin: zshrc}\n  git:\n    - {path: ~/.gitconfig, origin: gitconfig}\n",
+        )
+        .unwrap();
+
+        let links: Vec<_> = symlink_list
+            .links
+            .iter()
+            .map(|link| (link.origin.as_str(), link.group.as_deref()))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("profile", None),
+                ("zshrc", Some("zsh")),
+                ("gitconfig", Some("git"))
+            ]
+        );
+    }
+
+    #[test]
+    fn origins_can_be_relative_to_a_symlinked_symlink_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("shared")).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        fs::write(dotfiles_dir.join("shared/zshrc"), "").unwrap();
+        fs::write(
+            dotfiles_dir.join("shared/symlinks.yml"),
+            format!(
+                "links:\n  - {{path: {}, origin: zshrc}}\n",
+                dir.path().join(".zshrc").display()
+            ),
+        )
+        .unwrap();
+        let symlink_list_path = dotfiles_dir.join("symlinks.yml");
+        platform::symlink("shared/symlinks.yml", &symlink_list_path).unwrap();
+        let origin = |origins| {
+            let symlink_list =
+                SymlinkList::from_files(&[&symlink_list_path], None, origins, None).unwrap();
+            let plan = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+            plan[0].origin.clone()
+        };
+
+        let dotfiles_dir = fs::canonicalize(&dotfiles_dir).unwrap();
+        assert_eq!(origin(OriginBase::DotfilesDir), dotfiles_dir.join("zshrc"));
+        assert_eq!(
+            origin(OriginBase::SymlinkList),
+            dotfiles_dir.join("shared/zshrc")
+        );
+    }
+
+    #[test]
+    fn origin_bases_are_relative_to_their_symlink_list() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("config")).unwrap();
+        let shared = dir.path().join("config/shared.yml");
+        let local = dir.path().join("config/local.yml");
+        fs::write(&shared, "origin_base: ../dotfiles\nlinks: []\n").unwrap();
+        fs::write(&local, "links: []\n").unwrap();
+
+        let symlink_list =
+            SymlinkList::from_files(&[&shared, &local], None, OriginBase::DotfilesDir, None)
+                .unwrap();
+        let config_dir = fs::canonicalize(dir.path().join("config")).unwrap();
+        assert_eq!(
+            symlink_list.origin_base.map(PathBuf::from),
+            Some(config_dir.join("../dotfiles"))
+        );
+    }
+
+    #[test]
+    fn symlink_lists_are_written_with_defaults_applied() {
+        let mut symlink_list: SymlinkList = serde_yaml::from_str(
+            "defaults:\n  optional: true\nlinks:\n  - link:\n    path: ~/.profile\n    origin: \
+             profile\ngroups:\n  zsh:\n    - {path: ~/.zshrc, origin: zshrc, optional: false}\n  \
+             git:\n    - {path: [~/.gitconfig, ~/.config/git/config], origin: gitconfig}\n",
+        )
+        .unwrap();
+        symlink_list.exclude_groups(&["git".to_owned()]);
+
+        let written = symlink_list.to_string_as(ConfigFormat::Yaml).unwrap();
+        assert_eq!(
+            written,
+            "links:\n- path:\n  - ~/.profile\n  origin: profile\n  optional: true\ngroups:\n  \
+             zsh:\n  - path:\n    - ~/.zshrc\n    origin: zshrc\n    optional: false\n"
+        );
+        for format in [ConfigFormat::Toml, ConfigFormat::Json] {
+            let written = symlink_list.to_string_as(format).unwrap();
+            let read: SymlinkList = match format {
+                ConfigFormat::Toml => toml::from_str(&written).unwrap(),
+                _ => serde_json::from_str(&written).unwrap(),
+            };
+            assert_eq!(read.links.len(), 2);
+            assert_eq!(read.links[1].group.as_deref(), Some("zsh"));
+        }
+    }
+
+    #[test]
+    fn later_symlink_lists_override_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.yml");
+        let local = dir.path().join("local.yml");
+        fs::write(
+            &base,
+            "links:\n  - {path: [~/.zshrc, ~/.bashrc], origin: shellrc}\n  - {path: ~/.gitconfig, \
+             origin: gitconfig}\n  - {path: ~/.vimrc, origin: vimrc}\n",
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            "links:\n  - {path: ~/.bashrc, origin: bashrc-local}\n  - {path: $HOME/.vimrc, \
+             origin: vimrc-local}\n",
+        )
+        .unwrap();
+
+        let symlink_list =
+            SymlinkList::from_files(&[&base, &local], None, OriginBase::DotfilesDir, None).unwrap();
+        let links: Vec<_> = symlink_list
+            .links
+            .iter()
+            .map(|link| (link.path.join(" "), link.origin.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("~/.zshrc".to_owned(), "shellrc"),
+                ("~/.gitconfig".to_owned(), "gitconfig"),
+                ("~/.bashrc".to_owned(), "bashrc-local"),
+                ("$HOME/.vimrc".to_owned(), "vimrc-local"),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_for_other_hosts_are_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("symlinks.yml");
+        fs::write(
+            &list,
+            "common:\n  - {path: [~/.zshrc, ~/.gitconfig], origin: zshrc}\nhosts:\n  laptop:\n    \
+             - {path: ~/.gitconfig, origin: gitconfig-work}\n    - {path: ~/.xinitrc, origin: \
+             xinitrc}\n  server:\n    - {path: ~/.tmux.conf, origin: tmux.conf}\n",
+        )
+        .unwrap();
+        let links = |host| {
+            SymlinkList::from_files(&[&list], None, OriginBase::DotfilesDir, host)
+                .unwrap()
+                .links
+                .into_iter()
+                .map(|link| (link.path.join(" "), link.origin))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            links(Some("Laptop.example.com")),
+            [
+                ("~/.zshrc".to_owned(), "zshrc".to_owned()),
+                ("~/.gitconfig".to_owned(), "gitconfig-work".to_owned()),
+                ("~/.xinitrc".to_owned(), "xinitrc".to_owned()),
+            ]
+        );
+        assert_eq!(
+            links(Some("desktop")),
+            [("~/.zshrc ~/.gitconfig".to_owned(), "zshrc".to_owned())]
+        );
+        assert_eq!(links(None).len(), 4);
+    }
+
+    #[test]
+    fn links_are_selected_by_tag() {
+        let symlink_list = || -> SymlinkList {
+            serde_yaml::from_str(
+                "links:\n  - {path: ~/.profile, origin: profile}\n  - {path: ~/.xinitrc, origin: \
+                 xinitrc, tags: [gui]}\n  - {path: ~/.ssh/config, origin: ssh-config, tags: [work, \
+                 gui]}\n  - {path: ~/.tmux.conf, origin: tmux.conf, tags: [server]}\n",
+            )
+            .unwrap()
+        };
+        let origins = |only: &[&str], exclude: &[&str]| {
+            let mut symlink_list = symlink_list();
+            let only: Vec<_> = only.iter().map(|tag| tag.to_string()).collect();
+            let exclude: Vec<_> = exclude.iter().map(|tag| tag.to_string()).collect();
+            let unknown: Vec<String> = symlink_list
+                .select_tags(&only, &exclude)
+                .into_iter()
+                .map(str::to_owned)
+                .collect();
+            let origins: Vec<_> = symlink_list
+                .links
+                .into_iter()
+                .map(|link| link.origin)
+                .collect();
+            (origins, unknown)
+        };
+
+        assert_eq!(
+            origins(&["gui"], &[]),
+            (
+                vec!["profile".into(), "xinitrc".into(), "ssh-config".into()],
+                vec![]
+            )
+        );
+        assert_eq!(
+            origins(&["gui"], &["work", "wrok"]),
+            (
+                vec!["profile".into(), "xinitrc".into()],
+                vec!["wrok".into()]
+            )
+        );
+        assert_eq!(
+            origins(&[], &["gui"]),
+            (vec!["profile".into(), "tmux.conf".into()], vec![])
+        );
+    }
+
+    #[test]
+    fn excluded_groups_are_removed() {
+        let mut symlink_list: SymlinkList = serde_yaml::from_str(
+            "links:\n  - {path: ~/.profile, origin: profile}\ngroups:\n  zsh:\n    - {path: \
+             ~/.zshrc, origin: zshrc}\n  gui:\n    - {path: ~/.config/alacritty.yml, origin: \
+             alacritty.yml}\n",
+        )
+        .unwrap();
+
+        let excluded = ["gui".to_owned(), "guis".to_owned()];
+        let unknown = symlink_list.exclude_groups(&excluded);
+        assert_eq!(unknown, ["guis"]);
+        let origins: Vec<_> = symlink_list
+            .links
+            .iter()
+            .map(|l| l.origin.as_str())
+            .collect();
+        assert_eq!(origins, ["profile", "zshrc"]);
+    }
+
+    #[test]
+    fn link_settings_can_be_defaulted() {
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "defaults:\n  mode: recurse\n  strategy: copy\n  tags: [work]\n  os: [linux]\n  \
+             arch: [x86_64]\nlinks:\n  - {path: ~/.profile, origin: profile}\n  - {path: \
+             ~/.zshrc, origin: zshrc, strategy: symlink, tags: [home], os: [macos]}\n",
+        )
+        .unwrap();
+
+        let [profile, zshrc] = &symlink_list.links[..] else {
+            panic!("expected two links, got {:?}", symlink_list.links);
+        };
+        assert_eq!(profile.mode, Some(DirMode::Recurse));
+        assert_eq!(profile.strategy, Some(Strategy::Copy));
+        assert_eq!(profile.tags.as_deref(), Some(&["work".to_owned()][..]));
+        assert_eq!(profile.os.as_deref(), Some(&["linux".to_owned()][..]));
+        assert_eq!(profile.arch.as_deref(), Some(&["x86_64".to_owned()][..]));
+        assert_eq!(zshrc.mode, Some(DirMode::Recurse));
+        assert_eq!(zshrc.strategy, Some(Strategy::Symlink));
+        assert_eq!(zshrc.tags.as_deref(), Some(&["home".to_owned()][..]));
+        assert_eq!(zshrc.os.as_deref(), Some(&["macos".to_owned()][..]));
+    }
+
+    #[test]
+    fn defaults_apply_to_links_that_dont_override_them() {
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "defaults:\n  backup: false\n  optional: true\n  group: shell\nlinks:\n  - {path: \
+             ~/.profile, origin: profile}\n  - {path: ~/.zshrc, origin: zshrc, backup: true}\n\
+             groups:\n  gui:\n    - {path: ~/.xinitrc, origin: xinitrc, optional: false}\n",
+        )
+        .unwrap();
+
+        let fields: Vec<_> = symlink_list
+            .links
+            .iter()
+            .map(|l| {
+                (
+                    l.on_conflict(OnConflict::Skip),
+                    l.optional(),
+                    l.group.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (OnConflict::Overwrite, true, Some("shell")),
+                (OnConflict::Backup, true, Some("shell")),
+                (OnConflict::Overwrite, false, Some("gui")),
+            ]
+        );
+    }
+
+    #[test]
+    fn symlink_lists_are_read_in_the_format_of_their_extension_unless_overridden() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = dir.path().join("symlinks.toml");
+        let json = dir.path().join("symlinks.json");
+        let txt = dir.path().join("symlinks.txt");
+        fs::write(
+            &toml,
+            "[[links]]\npath = \"~/.zshrc\"\norigin = \"zshrc\"\n",
+        )
+        .unwrap();
+        fs::write(
+            &json,
+            r#"{"links": [{"path": "~/.zshrc", "origin": "zshrc"}]}"#,
+        )
+        .unwrap();
+        fs::write(&txt, "[[links]]\npath = \"~/.zshrc\"\norigin = \"zshrc\"\n").unwrap();
+
+        for symlink_list in [
+            SymlinkList::from_file(&toml),
+            SymlinkList::from_file(&json),
+            SymlinkList::from_file_as(&txt, Some(ConfigFormat::Toml)),
+        ] {
+            let links = symlink_list.unwrap().links;
+            assert_eq!(links.len(), 1);
+            assert_eq!(links[0].path, ["~/.zshrc"]);
+            assert_eq!(links[0].origin, "zshrc");
+        }
+        assert!(SymlinkList::from_file(&txt).is_err());
+        assert!(matches!(
+            SymlinkList::from_file_as(&toml, Some(ConfigFormat::Json)),
+            Err(Error::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn recursive_links_link_each_file_in_their_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("app/themes")).unwrap();
+        fs::write(dotfiles_dir.join("app/config.toml"), "").unwrap();
+        fs::write(dotfiles_dir.join("app/themes/dark.toml"), "").unwrap();
+        let config = dir.path().join(".config");
+        fs::create_dir_all(config.join("app")).unwrap();
+        fs::write(config.join("app/state.json"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}/app, origin: app, mode: recurse}}\n  - {{path: {0}/, origin: \
+             app, rename: other, mode: recurse}}\n",
+            config.display()
+        ))
+        .unwrap();
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        let actions: Vec<_> = planned
+            .iter()
+            .map(|planned| {
+                (
+                    planned.link.strip_prefix(&config).unwrap().to_owned(),
+                    planned.action,
+                )
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                (PathBuf::from("app/config.toml"), InstallAction::Link),
+                (
+                    PathBuf::from("app/themes/dark.toml"),
+                    InstallAction::CreateDirAndLink
+                ),
+                (
+                    PathBuf::from("other/config.toml"),
+                    InstallAction::CreateDirAndLink
+                ),
+                (
+                    PathBuf::from("other/themes/dark.toml"),
+                    InstallAction::CreateDirAndLink
+                ),
+            ]
+        );
+        assert_eq!(
+            planned[1].origin,
+            fs::canonicalize(dotfiles_dir.join("app/themes/dark.toml")).unwrap()
+        );
+    }
+
+    #[test]
+    fn glob_origins_link_each_file_they_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("zsh")).unwrap();
+        for file in ["b.zsh", "a.zsh", ".hidden.zsh", "c.sh"] {
+            fs::write(dotfiles_dir.join("zsh").join(file), "").unwrap();
+        }
+        let zsh = dir.path().join(".zsh");
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}, origin: 'zsh/*.zsh'}}\n  - {{path: {0}, origin: 'zsh/[!a]?sh', \
+             optional: true}}\n  - {{path: {0}, origin: 'zsh/*.fish', optional: true}}\n",
+            zsh.display()
+        ))
+        .unwrap();
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        let links: Vec<_> = planned
+            .iter()
+            .map(|planned| planned.link.strip_prefix(&zsh).unwrap().to_owned())
+            .collect();
+        assert_eq!(links, ["a.zsh", "b.zsh", "c.sh"].map(PathBuf::from));
+        assert!(wildcard_match("[a-c].sh", "c.sh"));
+        assert!(!wildcard_match("[!a-c].sh", "c.sh"));
+
+        let renamed: SymlinkList =
+            serde_yaml::from_str("links:\n  - {path: ~/, origin: 'zsh/*', rename: zshrc}\n")
+                .unwrap();
+        assert!(matches!(
+            renamed.expand_origins(&dotfiles_dir),
+            Err(Error::LinkError {
+                cause: LinkFailure::RenamedGlob,
+                ..
+            })
+        ));
+    }
+}
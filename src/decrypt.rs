@@ -0,0 +1,170 @@
+//! Origins that are kept encrypted in the dotfiles directory, and are decrypted into place with
+//! `age` or `gpg` rather than linked.
+
+use crate::platform;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// The decrypted contents of every origin decrypted so far, with the length and modification time
+/// the origin had, so that each one is only decrypted (and its passphrase asked for) once until
+/// it is changed, e.g. while watching.
+static DECRYPTED: Mutex<BTreeMap<PathBuf, (Stamp, Vec<u8>)>> = Mutex::new(BTreeMap::new());
+
+/// The length and modification time of an origin.
+type Stamp = Option<(u64, SystemTime)>;
+
+/// Returns the [`Stamp`] of `origin` as it is now, or `None` if it can't be read.
+fn stamp(origin: &Path) -> Stamp {
+    let metadata = fs::metadata(origin).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Returns the program that decrypts `origin`, and the arguments it needs before `--output` and the
+/// input file: `age` for files ending in `.age`, and `gpg` for anything else.
+pub fn program(origin: &Path) -> (&'static str, &'static [&'static str]) {
+    if origin.extension().is_some_and(|ext| ext == "age") {
+        ("age", &["--decrypt"])
+    } else {
+        ("gpg", &["--quiet", "--decrypt"])
+    }
+}
+
+/// Returns the decrypted contents of `origin`. The decrypting program may ask for a passphrase on
+/// the terminal.
+///
+/// # Errors
+/// + [`io::Error`] if the program is not installed, or fails to decrypt `origin`.
+pub fn decrypt(origin: &Path) -> io::Result<Vec<u8>> {
+    if let Some(contents) = cached(origin) {
+        return Ok(contents);
+    }
+    let (program, args) = program(origin);
+    decrypt_with(origin, Path::new(program), args)
+}
+
+/// Returns the contents of `origin` decrypted by `program`, which is given `args` and then
+/// `origin`, and remember them for [`cached`].
+///
+/// # Errors
+/// + [`io::Error`] if `program` can't be run, or fails to decrypt `origin`.
+fn decrypt_with(origin: &Path, program: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let stamp = stamp(origin);
+    let output = Command::new(program)
+        .args(args)
+        .arg(origin)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => {
+                io::Error::other(format!("{} is not installed", program.display()))
+            }
+            _ => e,
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {}",
+            program.display(),
+            output.status
+        )));
+    }
+    DECRYPTED
+        .lock()
+        .unwrap()
+        .insert(origin.to_owned(), (stamp, output.stdout.clone()));
+    Ok(output.stdout)
+}
+
+/// Returns the decrypted contents of `origin` if it has been decrypted since it was last changed,
+/// without running the decrypting program.
+pub fn cached(origin: &Path) -> Option<Vec<u8>> {
+    let stamp = stamp(origin)?;
+    match DECRYPTED.lock().unwrap().get(origin) {
+        Some((decrypted, contents)) if *decrypted == Some(stamp) => Some(contents.clone()),
+        _ => None,
+    }
+}
+
+/// Decrypt `origin` into a new file at `link` that only its owner can read and write.
+///
+/// # Errors
+/// + [`io::Error`] if `origin` cannot be decrypted, or `link` cannot be created.
+pub fn write(origin: &Path, link: &Path) -> io::Result<()> {
+    let contents = decrypt(origin)?;
+    platform::create_private(link)?.write_all(&contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SymlinkList,
+        executor::{apply, ApplyOptions},
+        planner::{plan, InstallAction, PlanOptions},
+    };
+
+    #[test]
+    #[cfg(unix)]
+    fn encrypted_origins_are_decrypted_into_place() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // A stand-in for gpg that "decrypts" its last argument by upper-casing it. Installing
+        // finds the origin already decrypted by it, so the real gpg is never run.
+        let gpg = dir.path().join("gpg");
+        fs::write(
+            &gpg,
+            "#!/bin/sh\nfor arg; do file=$arg; done\ntr a-z A-Z < \"$file\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&gpg, fs::Permissions::from_mode(0o755)).unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        let origin = dotfiles_dir.join("token.gpg");
+        fs::write(&origin, "secret\n").unwrap();
+        let origin = fs::canonicalize(origin).unwrap();
+        decrypt_with(&origin, &gpg, &[]).unwrap();
+        let link = dir.path().join("token");
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}, origin: token.gpg, encrypted: true}}\n",
+            link.display()
+        ))
+        .unwrap();
+        let options = ApplyOptions {
+            verify: true,
+            ..ApplyOptions::default()
+        };
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        assert_eq!(planned[0].action, InstallAction::Link);
+        for result in apply(planned, &options) {
+            result.result.unwrap();
+        }
+        assert_eq!(fs::read_to_string(&link).unwrap(), "SECRET\n");
+        let metadata = fs::symlink_metadata(&link).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+        // The origin was decrypted when it was installed, so planning can compare it.
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        assert_eq!(planned[0].action, InstallAction::Skip);
+
+        // An origin that is changed is only decrypted again when it is installed, e.g. by the next
+        // installation when watching.
+        fs::write(&origin, "new secret\n").unwrap();
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        assert_eq!(planned[0].action, InstallAction::DecryptAndCompare);
+        decrypt_with(&origin, &gpg, &[]).unwrap();
+        for result in apply(planned, &options) {
+            result.result.unwrap();
+        }
+        assert_eq!(fs::read_to_string(&link).unwrap(), "NEW SECRET\n");
+    }
+}
@@ -0,0 +1,633 @@
+//! Finding broken links, links that can't be installed, and leftover links and backups, and fixing
+//! those that can be fixed without losing anything.
+
+use crate::{
+    backup::backup_time,
+    config::{is_glob, ConfigFormat, Source, SymlinkList},
+    expand_link_file, expand_path, format,
+    log::{self, count, Status},
+    normalize,
+    planner::resolve_link,
+    platform::{self, Linked},
+    prompt::confirm,
+    state::{self, State},
+    Result, TargetRoot,
+};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// Where dotfiles are commonly linked to, which are checked for dangling symlinks and stale backups
+/// along with the directories that the symlink list links into.
+pub const COMMON_DIRS: &[&str] = &["~", "~/.config", "~/.local/bin"];
+
+/// A problem found by [`diagnose`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// A symlink at `link` points into the dotfiles directory, at `target`, and nothing is there,
+    /// e.g. because its origin was renamed or its link was removed from the symlink list.
+    Dangling { link: PathBuf, target: PathBuf },
+    /// The origin of a link doesn't exist, so the link can't be installed. `path` and `origin` are
+    /// as written in the symlink list.
+    MissingOrigin { path: String, origin: String },
+    /// More than one link in the symlink list is installed at `link`, so each installation
+    /// replaces the others.
+    DuplicateLink { link: PathBuf, origins: Vec<String> },
+    /// A symlink to `origin` that was installed at `link` is still there, but its link has since
+    /// been removed from the symlink list.
+    Orphan { link: PathBuf, origin: PathBuf },
+    /// `backup` is a `<filename>-backup-<date>` of the same file as `latest`, which is more
+    /// recent.
+    StaleBackup { backup: PathBuf, latest: PathBuf },
+}
+
+impl Problem {
+    /// Returns what can be done about the problem.
+    pub fn suggestion(&self) -> String {
+        match self {
+            Problem::Dangling { .. } => "Remove the symlink.".to_owned(),
+            Problem::MissingOrigin { .. } => {
+                "Restore the origin, or remove the link from the symlink list.".to_owned()
+            }
+            Problem::DuplicateLink { .. } => {
+                "Keep only one of the links in the symlink list.".to_owned()
+            }
+            Problem::Orphan { .. } => {
+                "Remove the symlink, or add the link back to the symlink list.".to_owned()
+            }
+            Problem::StaleBackup { .. } => "Delete the backup.".to_owned(),
+        }
+    }
+
+    /// Returns whether [`fix`] can fix the problem. Which of several links at the same path is
+    /// the right one can't be told, so duplicate links can't be fixed.
+    pub fn is_fixable(&self) -> bool {
+        !matches!(self, Problem::DuplicateLink { .. })
+    }
+}
+
+/// What [`fix`] did about a [`Problem`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fix {
+    /// The problem was fixed.
+    Fixed,
+    /// The problem can't be fixed automatically (see [`Problem::is_fixable`]).
+    Unfixable,
+    /// The link can't be removed from the symlink list, since none of the symlink lists are YAML.
+    NotYaml,
+    /// The link wasn't found as it is written in any of the YAML symlink lists, e.g. because it
+    /// comes from a list in another format.
+    NotFound,
+}
+
+/// Returns the problems with `symlink_list` and the links installed from it: links whose origin
+/// doesn't exist, then links installed at the same path, then the dangling symlinks into the
+/// dotfiles directory and backups older than another backup of the same file in each directory,
+/// then the symlinks that `state` records but that are no longer in `symlink_list`. Links that are
+/// optional, have a build command or whose origin is a glob aren't reported for missing origins.
+///
+/// Dangling symlinks and stale backups are looked for directly in the directories that links are
+/// created in, and in [`COMMON_DIRS`].
+///
+/// # Params
+/// + `dotfiles_dir` - The directory the origins are in.
+/// + `root` - The directory the links are installed inside of, if any (see [`TargetRoot`]).
+/// + `state` - The symlinks that were installed, if known.
+///
+/// # Errors
+/// + [`Error::LinkError`](crate::Error::LinkError) if a link's `path` and `rename` don't specify
+///   exactly one filename.
+/// + [`Error::ShellexpandLookupError`](crate::Error::ShellexpandLookupError) if a path references
+///   an unset shell variable.
+pub fn diagnose(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    root: Option<&TargetRoot>,
+    state: Option<&State>,
+) -> Result<Vec<Problem>> {
+    let mut problems = vec![];
+    for entry in &symlink_list.links {
+        if entry.optional()
+            || entry.build.is_some()
+            || is_glob(&entry.origin)
+            || dotfiles_dir.join(&entry.origin).exists()
+        {
+            continue;
+        }
+        for path in &entry.path {
+            problems.push(Problem::MissingOrigin {
+                path: path.clone(),
+                origin: entry.origin.clone(),
+            });
+        }
+    }
+
+    let mut links: Vec<(PathBuf, Vec<String>)> = vec![];
+    let mut dirs = vec![];
+    for dir in COMMON_DIRS {
+        dirs.push(match root {
+            Some(root) => root.expand(dir)?,
+            None => expand_path(dir)?,
+        });
+    }
+    for entry in &symlink_list.expand_origins(&dotfiles_dir)?.links {
+        let origin = dotfiles_dir.join(&entry.origin);
+        for path in &entry.path {
+            let link = resolve_link(path, entry.rename.as_deref(), &origin, root)?;
+            if let Some(parent) = link.parent() {
+                if !dirs.iter().any(|dir| dir == parent) {
+                    dirs.push(parent.to_owned());
+                }
+            }
+            match links.iter_mut().find(|(other, _)| *other == link) {
+                Some((_, origins)) => origins.push(entry.origin.clone()),
+                None => links.push((link, vec![entry.origin.clone()])),
+            }
+        }
+    }
+    let paths: Vec<_> = links.iter().map(|(link, _)| link.clone()).collect();
+    problems.extend(
+        links
+            .into_iter()
+            .filter(|(_, origins)| origins.len() > 1)
+            .map(|(link, origins)| Problem::DuplicateLink { link, origins }),
+    );
+
+    let canonical_dir = fs::canonicalize(dotfiles_dir).unwrap_or_else(|_| dotfiles_dir.to_owned());
+    for dir in &dirs {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut paths: Vec<_> = read_dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        paths.sort();
+        let mut backups: Vec<(String, Vec<PathBuf>)> = vec![];
+        for path in paths {
+            if let Ok(target) = fs::read_link(&path) {
+                let absolute = normalize(&dir.join(&target));
+                let is_inside =
+                    absolute.starts_with(dotfiles_dir) || absolute.starts_with(&canonical_dir);
+                if is_inside && fs::metadata(&absolute).is_err() {
+                    problems.push(Problem::Dangling { link: path, target });
+                    continue;
+                }
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let Some((original, _)) = name.rsplit_once("-backup-") else {
+                continue;
+            };
+            if backup_time(&path).is_none() {
+                continue;
+            }
+            let original = original.to_owned();
+            match backups.iter_mut().find(|(other, _)| *other == original) {
+                Some((_, paths)) => paths.push(path),
+                None => backups.push((original, vec![path])),
+            }
+        }
+        for (_, mut paths) in backups {
+            paths.sort_by_key(backup_time);
+            let Some(latest) = paths.pop() else {
+                continue;
+            };
+            problems.extend(paths.into_iter().map(|backup| Problem::StaleBackup {
+                backup,
+                latest: latest.clone(),
+            }));
+        }
+    }
+
+    for installed in state.map_or(vec![], |state| state.orphans(dotfiles_dir, &paths)) {
+        let is_dangling = problems.iter().any(
+            |problem| matches!(problem, Problem::Dangling { link, .. } if *link == installed.link),
+        );
+        if !is_dangling {
+            problems.push(Problem::Orphan {
+                link: installed.link.clone(),
+                origin: installed.origin.clone(),
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Fix `problem`: remove a dangling or leftover symlink, remove a link whose origin doesn't exist
+/// from the last YAML symlink list that has it, or delete a stale backup.
+///
+/// Returns what was done. Duplicate links, and links that aren't in a YAML symlink list as they are
+/// written, aren't fixed.
+///
+/// # Params
+/// + `symlink_list_paths` - The symlink lists the links were read from.
+/// + `config_format` - The format to read the symlink lists in, rather than the one their extension
+///   implies.
+///
+/// # Errors
+/// + [`Error::IoError`](crate::Error::IoError) if a file cannot be removed, or a symlink list
+///   cannot be read or written.
+/// + [`Error::YamlError`](crate::Error::YamlError) if a symlink list is not valid YAML.
+pub fn fix(
+    problem: &Problem,
+    symlink_list_paths: &[PathBuf],
+    config_format: Option<ConfigFormat>,
+) -> Result<Fix> {
+    match problem {
+        Problem::Dangling { link: path, .. }
+        | Problem::Orphan { link: path, .. }
+        | Problem::StaleBackup { backup: path, .. } => {
+            fs::remove_file(path)?;
+            Ok(Fix::Fixed)
+        }
+        Problem::MissingOrigin { path, origin } => {
+            // Later lists override earlier ones, so the link was read from the last one that has it
+            let mut fix = Fix::NotYaml;
+            for symlink_list_path in symlink_list_paths.iter().rev() {
+                let format = config_format
+                    .or_else(|| ConfigFormat::from_extension(symlink_list_path))
+                    .unwrap_or(ConfigFormat::Yaml);
+                if format != ConfigFormat::Yaml {
+                    continue;
+                }
+                fix = Fix::NotFound;
+                let contents = fs::read_to_string(symlink_list_path)?;
+                if let Some(removed) = format::remove_link(&contents, path, origin)? {
+                    fs::write(symlink_list_path, removed)?;
+                    return Ok(Fix::Fixed);
+                }
+            }
+            Ok(fix)
+        }
+        Problem::DuplicateLink { .. } => Ok(Fix::Unfixable),
+    }
+}
+
+/// Settings for [`doctor`].
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Fix the problems that can be fixed, once the user confirms.
+    pub fix: bool,
+    /// Fix without asking for confirmation.
+    pub yes: bool,
+    /// Fix nothing if the confirmation prompt is answered with an empty line.
+    pub default_no: bool,
+    /// The state file that records installed symlinks, rather than the default one.
+    pub state: Option<PathBuf>,
+    /// The directory the links are installed inside of, if any.
+    pub root: Option<TargetRoot>,
+}
+
+/// How [`doctor`] ended, if it didn't fail with an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// No problems were found, or all of them were fixed.
+    Healthy,
+    /// Some problems were found and not fixed.
+    ProblemsLeft,
+    /// Fixing was cancelled at the confirmation prompt.
+    Cancelled,
+}
+
+/// Report every [`Problem`] with the symlink lists from `source` and the links installed from
+/// them, with a suggestion for each, and with [`Options::fix`], fix those that can be fixed once
+/// the user confirms. Orphans that are removed are forgotten by the state file.
+///
+/// # Errors
+/// + Any error from [`Source::read`].
+/// + Any error from [`diagnose`].
+/// + [`Error::NotATerminal`](crate::Error::NotATerminal) if confirmation is needed and stdin is not
+///   a terminal.
+pub fn doctor(source: &Source, options: &Options) -> Result<Outcome> {
+    let (symlink_list, origin_dir) = source.read()?;
+    let mut state = state::read(options.state.as_deref());
+    let problems = diagnose(
+        &origin_dir,
+        &symlink_list,
+        options.root.as_ref(),
+        state.as_ref().map(|(_, state)| state),
+    )?;
+
+    for problem in &problems {
+        log::warn(describe(problem));
+        log::info(format!(
+            "                {}",
+            Paint::blue(problem.suggestion())
+        ));
+    }
+    if problems.is_empty() {
+        log::info(Paint::green("No problems found."));
+        return Ok(Outcome::Healthy);
+    }
+    let fixable = problems
+        .iter()
+        .filter(|problem| problem.is_fixable())
+        .count();
+    log::info(format!(
+        "Found {}, {} of which can be fixed with --fix.",
+        count(problems.len(), "problem"),
+        fixable
+    ));
+    if !options.fix || fixable == 0 {
+        return Ok(Outcome::ProblemsLeft);
+    }
+    if !options.yes
+        && !confirm(
+            &format!("Fix {}?", count(fixable, "problem")),
+            !options.default_no,
+        )?
+    {
+        log::info("Nothing was fixed.");
+        return Ok(Outcome::Cancelled);
+    }
+
+    let mut unfixed = problems.len() - fixable;
+    for problem in problems.iter().filter(|problem| problem.is_fixable()) {
+        let what = match problem {
+            Problem::Dangling { link: path, .. }
+            | Problem::Orphan { link: path, .. }
+            | Problem::StaleBackup { backup: path, .. } => path.display().to_string(),
+            Problem::MissingOrigin { path, .. } => format!("{} from the symlink list", path),
+            Problem::DuplicateLink { link, .. } => link.display().to_string(),
+        };
+        match fix(problem, &source.symlink_list_paths, source.config_format) {
+            Ok(Fix::Fixed) => {
+                if let (Problem::Orphan { link, .. }, Some((_, state))) = (problem, &mut state) {
+                    state.forget(link);
+                }
+                log::info(format!(
+                    "{}{} {}",
+                    log::symbol(Status::Remove),
+                    Paint::green("Removed"),
+                    what
+                ));
+            }
+            Ok(fix) => {
+                let reason = match fix {
+                    Fix::NotYaml => "because only YAML can be removed from.",
+                    Fix::NotFound => {
+                        "because it isn't in a YAML symlink list as it is written. Remove it by hand."
+                    }
+                    Fix::Fixed | Fix::Unfixable => "automatically.",
+                };
+                log::warn(format!(
+                    "{} {} {}",
+                    Paint::yellow("Can't remove"),
+                    what,
+                    Paint::yellow(reason)
+                ));
+                unfixed += 1;
+            }
+            Err(e) => {
+                log::error(format!("{} {}: {}", Paint::red("Failed to fix"), what, e));
+                unfixed += 1;
+            }
+        }
+    }
+    if let Some((path, state)) = &state {
+        state::write(state, path);
+    }
+    Ok(if unfixed == 0 {
+        Outcome::Healthy
+    } else {
+        Outcome::ProblemsLeft
+    })
+}
+
+/// Returns the line that reports `problem`.
+fn describe(problem: &Problem) -> String {
+    match problem {
+        Problem::Dangling { link, target } => format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Remove),
+            Paint::red("Dangling:      "),
+            link.display(),
+            Paint::red("->"),
+            target.display()
+        ),
+        Problem::MissingOrigin { path, origin } => format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Link),
+            Paint::yellow("Missing origin:"),
+            path,
+            Paint::yellow("->"),
+            origin
+        ),
+        Problem::DuplicateLink { link, origins } => format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Backup),
+            Paint::yellow("Duplicate:     "),
+            link.display(),
+            Paint::yellow("is linked to"),
+            origins.join(", ")
+        ),
+        Problem::Orphan { link, origin } => format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Remove),
+            Paint::yellow("Leftover:      "),
+            link.display(),
+            Paint::yellow("->"),
+            origin.display()
+        ),
+        Problem::StaleBackup { backup, latest } => format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Keep),
+            Paint::yellow("Old backup:    "),
+            backup.display(),
+            Paint::yellow("is older than"),
+            latest.display()
+        ),
+    }
+}
+
+/// Check that symlinks can be created, that the dotfiles directory is readable, that the symlink
+/// lists parse and that every shell variable used by a link is set. Each check is reported as it
+/// completes.
+///
+/// Returns whether every check passed.
+///
+/// # Params
+/// + `paths` - The dotfiles directory and the full paths of the symlink lists, or the error that
+///   locating them failed with.
+/// + `config_format` - The format to read the symlink lists in, rather than the one their extension
+///   implies.
+pub fn self_check(
+    paths: Result<(PathBuf, Vec<PathBuf>)>,
+    config_format: Option<ConfigFormat>,
+) -> bool {
+    let mut failed = false;
+    let mut report = |what: String, result: Result<(), String>| match result {
+        Ok(()) => log::info(format!("{}  {}", Paint::green("pass"), what)),
+        Err(e) => {
+            log::error(format!("{}  {}: {}", Paint::red("fail"), what, e));
+            failed = true;
+        }
+    };
+
+    report(
+        "Symlinks can be created".to_owned(),
+        check_symlinks().map_err(|e| e.to_string()),
+    );
+    let (dotfiles_dir, symlink_list_paths) = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            report(
+                "Locating the dotfiles directory".to_owned(),
+                Err(e.to_string()),
+            );
+            return false;
+        }
+    };
+    report(
+        format!("Dotfiles directory {} is readable", dotfiles_dir.display()),
+        fs::read_dir(&dotfiles_dir)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    );
+    for symlink_list_path in &symlink_list_paths {
+        let symlink_list = SymlinkList::from_file_as(symlink_list_path, config_format);
+        report(
+            format!("Symlink list {} is valid", symlink_list_path.display()),
+            symlink_list.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+        );
+        if let Ok(symlink_list) = symlink_list {
+            for path in symlink_list.links.iter().flat_map(|link| &link.path) {
+                report(
+                    format!("Variables in {} are set", path),
+                    expand_link_file(path)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string()),
+                );
+            }
+        }
+    }
+    !failed
+}
+
+/// Create a symlink in a new temporary directory, then remove both. Fails if only a junction could
+/// be created instead, since files would then be copied rather than linked.
+fn check_symlinks() -> io::Result<()> {
+    let dir = env::temp_dir().join(format!("dotconfig-self-check-{}", std::process::id()));
+    fs::create_dir(&dir)?;
+    let result = platform::symlink(&dir, dir.join("link"));
+    fs::remove_dir_all(&dir)?;
+    match result? {
+        Linked::Symlink => Ok(()),
+        Linked::Junction | Linked::Copy => Err(io::Error::other(
+            "only junctions can be created, so files will be copied instead of linked. Enable \
+             Developer Mode to create symlinks",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::symlink;
+
+    #[test]
+    fn problems_are_found_and_fixed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        let root = TargetRoot {
+            dir: dir.path().join("root"),
+            home: PathBuf::from("/home/me"),
+        };
+        let home = root.dir.join("home/me");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::create_dir_all(home.join(".config")).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        fs::create_dir(dotfiles_dir.join("bin")).unwrap();
+        fs::write(dotfiles_dir.join("bin/tool"), "").unwrap();
+        symlink(dotfiles_dir.join("gone"), home.join(".gone")).unwrap();
+        symlink(dir.path().join("elsewhere"), home.join(".elsewhere")).unwrap();
+        for date in [
+            "2024-01-01-00-00-00",
+            "2025-01-01-00-00-00",
+            "2023-01-01-00-00-00",
+        ] {
+            fs::write(home.join(format!(".zshrc-backup-{}", date)), "").unwrap();
+        }
+        fs::write(home.join(".config/only-backup-2024-01-01-00-00-00"), "").unwrap();
+        let symlink_list_path = dotfiles_dir.join("symlinks.yml");
+        fs::write(
+            &symlink_list_path,
+            "links:\n  \
+             - {path: ~/.zshrc, origin: zshrc}\n  \
+             - {path: $HOME/.zshrc, origin: bashrc}\n  \
+             - {path: ~/.tmux.conf, origin: tmux.conf, optional: true}\n  \
+             - {path: ~/bin/, origin: \"bin/*\"}\n  \
+             - {path: ~/.local/bin/, origin: \"scripts/*\"}\n",
+        )
+        .unwrap();
+        let symlink_list = SymlinkList::from_file(&symlink_list_path).unwrap();
+        fs::write(dotfiles_dir.join("inputrc"), "").unwrap();
+        symlink(dotfiles_dir.join("inputrc"), home.join(".inputrc")).unwrap();
+        let mut state = State::default();
+        state.record(&dotfiles_dir.join("inputrc"), &home.join(".inputrc"));
+        state.record(&dotfiles_dir.join("gone"), &home.join(".gone"));
+
+        let problems = diagnose(&dotfiles_dir, &symlink_list, Some(&root), Some(&state)).unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                Problem::MissingOrigin {
+                    path: "$HOME/.zshrc".to_owned(),
+                    origin: "bashrc".to_owned()
+                },
+                Problem::DuplicateLink {
+                    link: home.join(".zshrc"),
+                    origins: vec!["zshrc".to_owned(), "bashrc".to_owned()]
+                },
+                Problem::Dangling {
+                    link: home.join(".gone"),
+                    target: dotfiles_dir.join("gone")
+                },
+                Problem::StaleBackup {
+                    backup: home.join(".zshrc-backup-2023-01-01-00-00-00"),
+                    latest: home.join(".zshrc-backup-2025-01-01-00-00-00")
+                },
+                Problem::StaleBackup {
+                    backup: home.join(".zshrc-backup-2024-01-01-00-00-00"),
+                    latest: home.join(".zshrc-backup-2025-01-01-00-00-00")
+                },
+                Problem::Orphan {
+                    link: home.join(".inputrc"),
+                    origin: dotfiles_dir.join("inputrc")
+                },
+            ]
+        );
+
+        let paths = [symlink_list_path.clone()];
+        let fixed: Vec<_> = problems
+            .iter()
+            .map(|problem| fix(problem, &paths, None).unwrap())
+            .collect();
+        use Fix::*;
+        assert_eq!(fixed, vec![Fixed, Unfixable, Fixed, Fixed, Fixed, Fixed]);
+        let symlink_list = SymlinkList::from_file(&symlink_list_path).unwrap();
+        assert_eq!(
+            diagnose(&dotfiles_dir, &symlink_list, Some(&root), Some(&state)).unwrap(),
+            vec![]
+        );
+        assert!(home.join(".zshrc-backup-2025-01-01-00-00-00").exists());
+        let contents = fs::read_to_string(&symlink_list_path).unwrap();
+        assert!(contents.contains("bin/*") && contents.contains("scripts/*"));
+
+        let missing = Problem::MissingOrigin {
+            path: "~/.profile".to_owned(),
+            origin: "profile".to_owned(),
+        };
+        fs::write(
+            &symlink_list_path,
+            "links:\n  - {path: $HOME/.profile, origin: profile}\n",
+        )
+        .unwrap();
+        assert_eq!(fix(&missing, &paths, None).unwrap(), NotFound);
+        assert_eq!(
+            fix(&missing, &paths, Some(ConfigFormat::Toml)).unwrap(),
+            NotYaml
+        );
+    }
+}
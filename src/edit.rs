@@ -0,0 +1,309 @@
+//! Changing symlink lists from the command line: writing a starter one, adding files to one, and
+//! removing links from one.
+
+use crate::{
+    config::{ConfigFormat, Hooks, Link, Source, SymlinkList},
+    format, git,
+    log::{self, Status},
+    planner::LinkMode,
+    platform,
+    status::{self, Health, LinkStatus},
+    uninstall, Error, Result,
+};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// The symlink list written by [`init`].
+const STARTER_SYMLINK_LIST: &str = "\
+# Each link creates a symlink at `path` that points to `origin`, which is relative to this
+# directory. See https://github.com/mfdorst/dotconfig for the other settings a link accepts.
+#
+# For example, to link ~/.zshrc to the file zshrc in this directory, replace `[]` below with:
+#
+#   - path: ~/.zshrc
+#     origin: zshrc
+links: []
+";
+
+/// Write a starter symlink list to `symlink_list_path`, creating the directories it is in, and if
+/// `git` is set and `dotfiles_dir` isn't already in a git repository, initialize one there.
+///
+/// # Errors
+/// + [`Error::SymlinkListExists`] if the symlink list exists and `force` is not set.
+/// + [`Error::GitError`] if `git` is set and `git init` fails.
+/// + [`Error::IoError`] if the symlink list cannot be written.
+pub fn init(dotfiles_dir: &Path, symlink_list_path: &Path, force: bool, git: bool) -> Result<()> {
+    if symlink_list_path.exists() && !force {
+        return Err(Error::SymlinkListExists(symlink_list_path.to_owned()));
+    }
+    if let Some(parent) = symlink_list_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(symlink_list_path, STARTER_SYMLINK_LIST)?;
+    log::info(format!(
+        "{} {}",
+        Paint::green("Wrote a starter symlink list to"),
+        symlink_list_path.display()
+    ));
+    if git && !git::is_work_tree(dotfiles_dir) {
+        git::init(dotfiles_dir)?;
+        log::info(format!(
+            "{} {}",
+            Paint::green("Initialized a git repository in"),
+            dotfiles_dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Move `file` into the directory the origins are in as `origin`, add a link from where it was to
+/// the first symlink list from `source`, and create the link. Returns where the file was moved to.
+///
+/// `origin` defaults to the file's name without a leading `.`. The link's path is written relative
+/// to `~` if `file` is in the home directory. The symlink list is written first, so that a file is
+/// never moved without a link that tracks it. If the file can't be moved or linked, the symlink
+/// list is restored, and the file is moved back.
+///
+/// # Errors
+/// + [`Error::MissingDotfilesDir`] if the dotfiles directory doesn't exist.
+/// + [`Error::NotYaml`] if the first symlink list isn't YAML.
+/// + [`Error::MissingSymlinkListFile`] if the symlink list doesn't exist.
+/// + [`Error::MissingFile`] if `file` doesn't exist.
+/// + [`Error::AddingSymlink`] if `file` is a symlink.
+/// + [`Error::LinkInsideDotfilesDir`] if `file` is inside of the dotfiles directory.
+/// + [`Error::OriginExists`] if something is already at `origin`.
+/// + [`Error::IoError`] if the file cannot be moved, e.g. to another filesystem, or the link or the
+///   symlink list cannot be written.
+pub fn add(source: &Source, file: &Path, origin: Option<&str>) -> Result<PathBuf> {
+    if !source.dotfiles_dir.exists() {
+        return Err(Error::MissingDotfilesDir(source.dotfiles_dir.clone()));
+    }
+    let symlink_list_path = &source.symlink_list_paths[0];
+    if source.format_of(symlink_list_path) != ConfigFormat::Yaml {
+        return Err(Error::NotYaml(symlink_list_path.clone()));
+    }
+    let (_, origin_dir) = source.read()?;
+
+    match fs::symlink_metadata(file) {
+        Err(_) => return Err(Error::MissingFile(file.to_owned())),
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            return Err(Error::AddingSymlink(file.to_owned()))
+        }
+        Ok(_) => {}
+    }
+    let canonical_dir = fs::canonicalize(&origin_dir)?;
+    if fs::canonicalize(file)?.starts_with(&canonical_dir) {
+        return Err(Error::LinkInsideDotfilesDir(file.to_owned()));
+    }
+    let origin = match origin {
+        Some(origin) => origin.to_owned(),
+        None => {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            name.strip_prefix('.').unwrap_or(&name).to_owned()
+        }
+    };
+    let origin_path = canonical_dir.join(&origin);
+    if fs::symlink_metadata(&origin_path).is_ok() {
+        return Err(Error::OriginExists(origin_path));
+    }
+    let path = match dirs_next::home_dir().and_then(|home| file.strip_prefix(home).ok()) {
+        Some(relative) => format!("~/{}", relative.display()),
+        None => file.display().to_string(),
+    };
+    let original = fs::read_to_string(symlink_list_path)?;
+    let contents = format::append_link(&original, &path, &origin)?;
+
+    fs::write(symlink_list_path, contents)?;
+    if let Err(e) = move_and_link(file, &origin_path) {
+        fs::write(symlink_list_path, original)?;
+        return Err(e.into());
+    }
+    log::info(format!(
+        "{}{} {} {} {}",
+        log::symbol(Status::Link),
+        Paint::green("Added"),
+        file.display(),
+        Paint::green("as"),
+        origin_path.display()
+    ));
+    Ok(origin_path)
+}
+
+/// Move `file` to `origin`, creating its directory if needed, and link `file` to it. If the link
+/// can't be created, the file is moved back.
+///
+/// # Errors
+/// + [`io::Error`] if the file cannot be moved, e.g. to another filesystem, or the link cannot be
+///   created.
+fn move_and_link(file: &Path, origin: &Path) -> io::Result<()> {
+    if let Some(parent) = origin.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(file, origin)?;
+    if let Err(e) = platform::symlink(origin, file) {
+        fs::rename(origin, file)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Replace the link at `file` with a copy of its origin, and remove it from the symlink list from
+/// `source` that it was read from. The origin is left in the dotfiles directory.
+///
+/// # Errors
+/// + [`Error::MissingDotfilesDir`] if the dotfiles directory doesn't exist.
+/// + [`Error::NotLinked`] if no link in the symlink lists is at `file`, or something other than a
+///   symlink to its origin is at `file`.
+/// + [`Error::NotInYaml`] if the link isn't in a YAML symlink list as it is written.
+/// + [`Error::IoError`] if the origin cannot be copied, or the symlink list cannot be written.
+pub fn remove(source: &Source, file: &Path) -> Result<()> {
+    let (symlink_list, origin_dir) = source.read()?;
+
+    // The path of the link as written, its link, and its status
+    let mut found = None;
+    for entry in &symlink_list.links {
+        for path in &entry.path {
+            let single = SymlinkList {
+                links: vec![Link {
+                    path: vec![path.clone()],
+                    ..entry.clone()
+                }],
+                origin_base: None,
+                vars: BTreeMap::new(),
+                hooks: Hooks::default(),
+            };
+            let statuses = status::status(&origin_dir, &single, None, LinkMode::Symlink)?;
+            if let Some(status) = statuses.into_iter().find(|status| status.link == file) {
+                found = Some((path, entry, status));
+            }
+        }
+    }
+    let Some((path, entry, LinkStatus { origin, health, .. })) = found else {
+        return Err(Error::NotLinked(file.to_owned()));
+    };
+    if !matches!(health, Health::Linked | Health::Missing) {
+        return Err(Error::NotLinked(file.to_owned()));
+    }
+
+    // Find the symlink list the link was read from, which is the last one that has it, since later
+    // lists override earlier ones
+    let mut edit = None;
+    for symlink_list_path in source.symlink_list_paths.iter().rev() {
+        if source.format_of(symlink_list_path) != ConfigFormat::Yaml {
+            continue;
+        }
+        let contents = fs::read_to_string(symlink_list_path)?;
+        if let Some(removed) = format::remove_link(&contents, path, &entry.origin)? {
+            edit = Some((symlink_list_path, removed));
+            break;
+        }
+    }
+    let Some((symlink_list_path, removed)) = edit else {
+        return Err(Error::NotInYaml(file.to_owned()));
+    };
+
+    uninstall::restore_origin(&origin, file)?;
+    fs::write(symlink_list_path, removed)?;
+    log::info(format!(
+        "{}{} {} {} {}",
+        log::symbol(Status::Remove),
+        Paint::green("Removed"),
+        file.display(),
+        Paint::green("from"),
+        symlink_list_path.display()
+    ));
+    log::info(format!(
+        "{} {} {}",
+        Paint::blue("Its origin is still at"),
+        origin.display(),
+        Paint::blue("and can be deleted.")
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a dotfiles directory with an empty symlink list in `dir`, and its [`Source`].
+    fn dotfiles(dir: &Path) -> (PathBuf, Source) {
+        let dotfiles_dir = dir.join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        let symlink_list_path = dotfiles_dir.join("symlinks.yml");
+        fs::write(&symlink_list_path, "links: []\n").unwrap();
+        let source = Source {
+            dotfiles_dir: dotfiles_dir.clone(),
+            symlink_list_paths: vec![symlink_list_path],
+            ..Source::default()
+        };
+        (dotfiles_dir, source)
+    }
+
+    #[test]
+    fn added_files_are_moved_linked_and_removed_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let (dotfiles_dir, source) = dotfiles(dir.path());
+        let file = dir.path().join(".zshrc");
+        fs::write(&file, "zsh").unwrap();
+
+        let origin = add(&source, &file, None).unwrap();
+        assert_eq!(
+            origin,
+            fs::canonicalize(&dotfiles_dir).unwrap().join("zshrc")
+        );
+        assert_eq!(fs::read_link(&file).unwrap(), origin);
+        let (symlink_list, _) = source.read().unwrap();
+        assert_eq!(symlink_list.links[0].origin, "zshrc");
+        assert_eq!(symlink_list.links[0].path, [file.display().to_string()]);
+
+        remove(&source, &file).unwrap();
+        assert!(!fs::symlink_metadata(&file)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "zsh");
+        assert!(source.read().unwrap().0.links.is_empty());
+        assert!(matches!(remove(&source, &file), Err(Error::NotLinked(_))));
+    }
+
+    #[test]
+    fn symlink_list_is_restored_if_the_file_cannot_be_moved() {
+        let dir = tempfile::tempdir().unwrap();
+        let (dotfiles_dir, source) = dotfiles(dir.path());
+        // The origin's directory can't be created where a file is
+        fs::write(dotfiles_dir.join("zsh"), "").unwrap();
+        let file = dir.path().join(".zshrc");
+        fs::write(&file, "zsh").unwrap();
+
+        assert!(matches!(
+            add(&source, &file, Some("zsh/zshrc")),
+            Err(Error::IoError(_))
+        ));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "zsh");
+        assert_eq!(
+            fs::read_to_string(&source.symlink_list_paths[0]).unwrap(),
+            "links: []\n"
+        );
+    }
+
+    #[test]
+    fn only_yaml_symlink_lists_are_added_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let (dotfiles_dir, mut source) = dotfiles(dir.path());
+        let toml = dotfiles_dir.join("symlinks.toml");
+        fs::write(&toml, "links = []\n").unwrap();
+        source.symlink_list_paths = vec![toml.clone()];
+        let file = dir.path().join(".zshrc");
+        fs::write(&file, "zsh").unwrap();
+
+        assert!(matches!(
+            add(&source, &file, None),
+            Err(Error::NotYaml(path)) if path == toml
+        ));
+        assert!(file.is_file());
+    }
+}
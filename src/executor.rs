@@ -0,0 +1,1103 @@
+//! Carrying out a plan, and running the build and reload commands of links and hooks.
+
+use crate::{
+    backup::backup,
+    config::SymlinkList,
+    decrypt, link_error,
+    log::{self, Status},
+    manifest::Operation,
+    planner::{
+        choose_install_action, compare_decrypted, install_operations, link_filename, link_parent,
+        missing_dirs, same_contents, same_file, ConflictPolicy, InstallAction, LinkMode, Plan,
+        PlannedAction,
+    },
+    platform::{self, Linked},
+    progress::Spinner,
+    script,
+    trash::Trash,
+    Error, LinkFailure, Result,
+};
+use std::{
+    fs::{self, read_link},
+    io::{self, ErrorKind},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use yansi::Paint;
+
+/// The user and group that created links and directories are given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Owner {
+    /// The user id, or `None` to leave the user unchanged.
+    pub uid: Option<u32>,
+    /// The group id, or `None` to leave the group unchanged.
+    pub gid: Option<u32>,
+}
+
+impl Owner {
+    /// Parse an owner written as `user`, `user:group` or `:group`, where the user and group are
+    /// either names or numeric ids.
+    ///
+    /// # Errors
+    /// + [`Error::UnknownOwner`] if there is no user or group with a given name.
+    /// + [`Error::UnsupportedPlatform`] on Windows, where files don't have a user and group id.
+    #[cfg(unix)]
+    pub fn parse(s: &str) -> Result<Self> {
+        let (user, group) = s.split_once(':').unwrap_or((s, ""));
+        let uid = match user {
+            "" => None,
+            user => Some(user.parse().or_else(|_| {
+                users::get_user_by_name(user)
+                    .map(|u| u.uid())
+                    .ok_or_else(|| Error::UnknownOwner(format!("user '{}'", user)))
+            })?),
+        };
+        let gid = match group {
+            "" => None,
+            group => Some(group.parse().or_else(|_| {
+                users::get_group_by_name(group)
+                    .map(|g| g.gid())
+                    .ok_or_else(|| Error::UnknownOwner(format!("group '{}'", group)))
+            })?),
+        };
+        Ok(Owner { uid, gid })
+    }
+
+    /// Parse an owner written as `user`, `user:group` or `:group`, where the user and group are
+    /// either names or numeric ids.
+    ///
+    /// # Errors
+    /// + [`Error::UnknownOwner`] if there is no user or group with a given name.
+    /// + [`Error::UnsupportedPlatform`] on Windows, where files don't have a user and group id.
+    #[cfg(windows)]
+    pub fn parse(_: &str) -> Result<Self> {
+        Err(Error::UnsupportedPlatform("Changing the owner of links"))
+    }
+
+    /// Give `path` this owner. If `path` is a symlink, the symlink itself is changed rather than
+    /// the file it points to.
+    fn apply(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::lchown(path, self.uid, self.gid)
+        }
+        #[cfg(windows)]
+        {
+            let _ = path;
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+}
+
+/// The outcome of applying a single [`PlannedAction`].
+#[derive(Debug)]
+pub struct ActionResult {
+    /// The link that was installed, with the [`LinkMode`] it was installed with, which is a copy
+    /// rather than a symlink where symlinks can't be created (see [`platform::symlink`]).
+    pub planned: PlannedAction,
+    pub result: Result<()>,
+    /// The changes that were made to the filesystem, including those made before a failure.
+    pub operations: Vec<Operation>,
+    /// How long it took to install the link.
+    pub elapsed: Duration,
+}
+
+/// Settings that change how [`apply`] installs links.
+#[derive(Clone, Debug, Default)]
+pub struct ApplyOptions {
+    /// Read each symlink back after creating it, and fail if it doesn't point to its origin.
+    pub verify: bool,
+    /// Only report links that are already installed at the debug level.
+    pub quiet_skip: bool,
+    /// Give created links and directories this owner. A hard link shares its owner with its
+    /// origin, so the origin's owner changes too.
+    pub owner: Option<Owner>,
+    /// Stop before installing the next link once this is set, e.g. by a Ctrl-C handler. The link
+    /// being installed when it is set is finished, so that a file is never backed up without
+    /// being replaced by its link.
+    pub interrupt: Option<&'static AtomicBool>,
+}
+
+/// Install every link in `plan`, in order.
+///
+/// The install action for each link is chosen again right before it is installed, since installing
+/// an earlier link may have changed the state of the filesystem (e.g. by creating a directory).
+/// A failure to install one link does not prevent the others from being installed.
+///
+/// If [`ApplyOptions::interrupt`] is set while installing, the links after the current one are
+/// left out, and only the results of those that were installed are returned.
+///
+/// # Params
+/// + `plan` - The links to install, as returned by [`plan`](crate::planner::plan).
+/// + `options` - Settings that change how links are installed.
+pub fn apply(plan: Plan, options: &ApplyOptions) -> Vec<ActionResult> {
+    let headings = group_headings(&plan, options.quiet_skip);
+    plan.into_iter()
+        .zip(headings)
+        .take_while(|_| !options.interrupt.is_some_and(|i| i.load(Ordering::SeqCst)))
+        .map(|(mut planned, heading)| {
+            let start = Instant::now();
+            let mut log = log::Buffer::new();
+            if let Some(heading) = heading {
+                log.info(heading);
+            }
+            let mut operations = vec![];
+            let result = if planned.cached {
+                log.debug(format!(
+                    "{} {}: {}",
+                    Paint::blue("Install action for"),
+                    planned.link.display(),
+                    Paint::blue("skip, since it is cached as installed")
+                ));
+                log_skip(&planned.origin, &planned.link, options.quiet_skip, &mut log);
+                Ok(planned.mode)
+            } else {
+                symlink(
+                    &planned.origin,
+                    &planned.link,
+                    planned.mode,
+                    &planned.policy,
+                    options.quiet_skip,
+                    &mut log,
+                    &mut operations,
+                )
+            }
+            .and_then(|installed| {
+                planned.mode = installed;
+                if options.verify && !planned.action.is_noop() {
+                    verify_link(&planned.origin, &planned.link, planned.mode)
+                } else {
+                    Ok(())
+                }
+            })
+            .and_then(|()| match options.owner {
+                Some(owner) => chown_created(&planned.origin, &planned.link, owner, &operations),
+                None => Ok(()),
+            });
+            ActionResult {
+                planned,
+                result,
+                operations,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect()
+}
+
+/// Returns the heading to print before each link in `plan`, e.g. `== nvim ==` before the first of a
+/// run of links in the `nvim` group, or `None` for the other links. Links that aren't in a group
+/// get no heading. If `quiet_skip` is set, neither does a run of links that are all already
+/// installed, since none of them is listed.
+pub fn group_headings(plan: &[PlannedAction], quiet_skip: bool) -> Vec<Option<String>> {
+    let mut headings = vec![None; plan.len()];
+    let mut start = 0;
+    while start < plan.len() {
+        let group = &plan[start].group;
+        let end = plan[start..]
+            .iter()
+            .position(|planned| planned.group != *group)
+            .map_or(plan.len(), |len| start + len);
+        let listed = !quiet_skip
+            || plan[start..end]
+                .iter()
+                .any(|planned| planned.action != InstallAction::Skip);
+        if let Some(group) = group.as_ref().filter(|_| listed) {
+            headings[start] = Some(Paint::cyan(format!("== {} ==", group)).bold().to_string());
+        }
+        start = end;
+    }
+    headings
+}
+
+/// Give every directory and link created by `operations` to `owner`.
+///
+/// # Errors
+/// + [`Error::LinkError`] if the owner of a created path could not be changed.
+fn chown_created(origin: &Path, link: &Path, owner: Owner, operations: &[Operation]) -> Result<()> {
+    for operation in operations {
+        if let Operation::CreateDir { path }
+        | Operation::Link { link: path, .. }
+        | Operation::HardLink { link: path, .. }
+        | Operation::Copy { link: path, .. }
+        | Operation::Decrypt { link: path, .. } = operation
+        {
+            owner
+                .apply(path)
+                .map_err(|e| link_error(origin, link, LinkFailure::ChownFailed(e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Report that `link` is already linked to `origin`, at the debug level if `quiet_skip` is set.
+fn log_skip(origin: &Path, link: &Path, quiet_skip: bool, log: &mut log::Buffer) {
+    let level = if quiet_skip {
+        log::Level::Debug
+    } else {
+        log::Level::Info
+    };
+    log.log(
+        level,
+        format!(
+            "{}{} '{}' {} '{}'{}",
+            log::symbol(Status::Skip),
+            Paint::green("Skipping"),
+            origin.display(),
+            Paint::green("->"),
+            link.display(),
+            Paint::green(". File already linked.")
+        ),
+    );
+}
+
+/// Create a symlink from `link` to `origin`. If `origin` already exists, back it up (rename it to
+/// `<filename>-backup-<date>`) or delete it first. If the symlink already exists, do nothing. If
+/// either `link` or `origin` are invalid paths, do nothing.
+///
+/// Returns how the link was installed, which is `mode` unless a symlink to a file had to be a copy
+/// (see [`platform::symlink`]).
+///
+/// # Params
+/// + `origin` - The canonical path that the symlink will point to.
+/// + `link` - The path where the symlink will be created.
+/// + `mode` - Whether to create a symlink, a hard link, a copy or a decrypted file.
+/// + `policy` - What to do with an existing file at `link`.
+/// + `quiet_skip` - Log that the link is already installed at the debug level, instead of info.
+/// + `operations` - Every change made to the filesystem is appended to this.
+///
+/// # Errors
+/// + [`Error::LinkError`]
+///     + If the path `link` does not exist. Either:
+///         + the parent directory does not exist, or
+///         + the path is invalid in some other way, such as not being relative to root (`/`).
+///     + If a hard link is wanted, and `link` is on a different filesystem than `origin`.
+///     + If the symlink failed for some other reason (probably a bug).
+///     + If `origin` does not exist as a path within the `dotfiles_dir` directory.
+fn symlink(
+    origin: &Path,
+    link: &Path,
+    mode: LinkMode,
+    policy: &ConflictPolicy,
+    quiet_skip: bool,
+    log: &mut log::Buffer,
+    operations: &mut Vec<Operation>,
+) -> Result<LinkMode> {
+    let link_filename = link_filename(origin, link)?;
+    let link_parent = link_parent(origin, link)?;
+
+    let action = install_action(origin, link, mode, policy)?;
+    log.debug(format!(
+        "{} {}: {}",
+        Paint::blue("Install action for"),
+        link.display(),
+        action
+    ));
+
+    match action {
+        InstallAction::CreateDirAndLink => {
+            log.info(format!(
+                "{}{} {} {}",
+                log::symbol(Status::CreateDir),
+                Paint::yellow("The directory"),
+                link_parent.display(),
+                Paint::yellow("does not exist. Creating...")
+            ));
+            let missing = missing_dirs(&link_parent);
+            fs::create_dir_all(&link_parent).map_err(|e| permission_denied_or(e, &link_parent))?;
+            operations.extend(
+                missing
+                    .into_iter()
+                    .map(|path| Operation::CreateDir { path }),
+            );
+        }
+        InstallAction::BackupAndLink => {
+            let link_parent = fs::canonicalize(&link_parent)
+                .map_err(|_| link_error(origin, link, LinkFailure::MissingParentDir))?;
+            backup(
+                origin,
+                &link_parent.join(&link_filename),
+                policy.backup_dir.as_deref(),
+                log,
+                operations,
+            )?;
+        }
+        InstallAction::OverwriteAndLink => {
+            remove(link, log)?;
+            operations.push(Operation::Remove {
+                path: link.to_owned(),
+            });
+        }
+        InstallAction::ReplaceIdenticalAndLink => {
+            let label = format!(
+                "{}{} {} {}...",
+                log::symbol(Status::Remove),
+                Paint::yellow("Replacing identical file"),
+                link.display(),
+                Paint::yellow("(no backup)")
+            );
+            run_step(log, label, || fs::remove_file(link))
+                .map_err(|e| permission_denied_or(e, &link))?;
+            operations.push(Operation::Remove {
+                path: link.to_owned(),
+            });
+        }
+        InstallAction::Skip => {
+            log_skip(origin, link, quiet_skip, log);
+            return Ok(mode);
+        }
+        InstallAction::KeepExisting => {
+            log.info(format!(
+                "{}{} {}{}",
+                log::symbol(Status::Keep),
+                Paint::yellow("Keeping existing file"),
+                link.display(),
+                Paint::yellow(". Not linking.")
+            ));
+            return Ok(mode);
+        }
+        InstallAction::Link => {}
+        InstallAction::DecryptAndCompare => unreachable!("decrypted files are compared first"),
+    }
+
+    if mode == LinkMode::Decrypt {
+        // Decrypt before the step is shown, since it may ask for a passphrase.
+        decrypt::decrypt(origin)
+            .map_err(|e| link_error(origin, link, LinkFailure::DecryptFailed(e)))?;
+    }
+    let label = format!(
+        "{}{} '{}' {} '{}'...",
+        log::symbol(Status::Link),
+        Paint::yellow(match mode {
+            LinkMode::Symlink => "Linking",
+            LinkMode::Hardlink => "Hard linking",
+            LinkMode::Copy => "Copying",
+            LinkMode::Decrypt => "Decrypting",
+        }),
+        link.display(),
+        Paint::yellow("->"),
+        origin.display()
+    );
+    let installed = run_step(log, label, || match mode {
+        LinkMode::Symlink => platform::symlink(origin, link).map(|linked| match linked {
+            Linked::Symlink | Linked::Junction => LinkMode::Symlink,
+            Linked::Copy => LinkMode::Copy,
+        }),
+        LinkMode::Hardlink => fs::hard_link(origin, link).map(|()| mode),
+        LinkMode::Copy => fs::copy(origin, link).map(|_| mode),
+        LinkMode::Decrypt => decrypt::write(origin, link).map(|()| mode),
+    })
+    .map_err(|e| {
+        if e.kind() == ErrorKind::PermissionDenied {
+            return Error::PermissionDenied(link.to_owned());
+        }
+        if e.kind() == ErrorKind::CrossesDevices {
+            return link_error(origin, link, LinkFailure::CrossDevice);
+        }
+        link_error(origin, link, LinkFailure::Io(e))
+    })?;
+    if installed != mode {
+        log.warn(format!(
+            "{} {} {}",
+            Paint::yellow("Symlinks can't be created without Developer Mode, so"),
+            link.display(),
+            Paint::yellow("is a copy of its origin.")
+        ));
+    }
+    operations.push(match installed {
+        LinkMode::Symlink => Operation::Link {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+        LinkMode::Hardlink => Operation::HardLink {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+        LinkMode::Copy => Operation::Copy {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+        LinkMode::Decrypt => Operation::Decrypt {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+    });
+    Ok(installed)
+}
+
+/// Returns the action that installs the link from `link` to `origin`, as
+/// [`choose_install_action`] chooses it, with `origin` decrypted and compared to the file at `link`
+/// if that is what it takes to choose.
+///
+/// # Errors
+/// + [`Error::LinkError`] if no action can be chosen, or `origin` can't be decrypted.
+/// + [`Error::IoError`] if `link` cannot be compared to `origin`.
+fn install_action(
+    origin: &Path,
+    link: &Path,
+    mode: LinkMode,
+    policy: &ConflictPolicy,
+) -> Result<InstallAction> {
+    match choose_install_action(origin, link, mode, policy)? {
+        InstallAction::DecryptAndCompare => compare_decrypted(origin, link, policy),
+        action => Ok(action),
+    }
+}
+
+/// Check that the symlink at `link` points to `origin`, both as written and once resolved.
+///
+/// # Errors
+/// + [`Error::LinkError`] if `link` is not a symlink to `origin`.
+fn verify_link(origin: &Path, link: &Path, mode: LinkMode) -> Result<()> {
+    if mode == LinkMode::Hardlink {
+        if same_file(origin, link)? {
+            return Ok(());
+        }
+        return Err(link_error(origin, link, LinkFailure::NotAHardLink));
+    }
+    if mode == LinkMode::Copy {
+        if same_contents(origin, link)? {
+            return Ok(());
+        }
+        return Err(link_error(origin, link, LinkFailure::NotCopied));
+    }
+    if mode == LinkMode::Decrypt {
+        let decrypted = decrypt::decrypt(origin)
+            .map_err(|e| link_error(origin, link, LinkFailure::DecryptFailed(e)))?;
+        if fs::symlink_metadata(link)?.is_file() && fs::read(link)? == decrypted {
+            return Ok(());
+        }
+        return Err(link_error(origin, link, LinkFailure::NotDecrypted));
+    }
+    let target = read_link(link).ok();
+    let resolves_to_origin = fs::canonicalize(link).ok() == fs::canonicalize(origin).ok();
+    if target.as_deref() == Some(origin) && resolves_to_origin {
+        return Ok(());
+    }
+    let cause = match target {
+        Some(target) => LinkFailure::WrongTarget(target),
+        None => LinkFailure::NotASymlink,
+    };
+    Err(link_error(origin, link, cause))
+}
+
+/// Run a filesystem operation, logging `label` followed by whether the operation succeeded. A
+/// spinner is shown after `label` if the operation takes a while.
+pub(crate) fn run_step<T, F>(log: &mut log::Buffer, label: String, operation: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T>,
+{
+    let spinner = Spinner::start(label.clone());
+    let result = operation();
+    drop(spinner);
+    match result {
+        Ok(_) => log.info(format!("{}{}", label, Paint::green("done."))),
+        Err(_) => log.info(format!("{}{}", label, Paint::red("failed."))),
+    }
+    result
+}
+
+/// Delete the file, directory or symlink at `path`. Directories are deleted recursively.
+///
+/// # Errors
+/// + [Error::PermissionDenied] if the current user may not delete `path`.
+/// + [Error::IoError] if the deletion fails for some other reason.
+fn remove(path: &Path, log: &mut log::Buffer) -> Result<()> {
+    let is_dir = fs::symlink_metadata(path)?.is_dir();
+    let label = format!(
+        "{}{} {}...",
+        log::symbol(Status::Remove),
+        Paint::yellow("Removing"),
+        path.display()
+    );
+    run_step(log, label, || {
+        if is_dir {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    })
+    .map_err(|e| permission_denied_or(e, &path))
+}
+
+/// Converts an IO error that occurred while operating on `path` into an [`Error`], keeping
+/// permission errors distinct so that they can be retried with elevated privileges.
+fn permission_denied_or<P>(e: io::Error, path: &P) -> Error
+where
+    P: AsRef<Path>,
+{
+    if e.kind() == ErrorKind::PermissionDenied {
+        Error::PermissionDenied(path.as_ref().to_owned())
+    } else {
+        Error::IoError(e)
+    }
+}
+
+/// Returns the [`Link::reload`](crate::config::Link::reload) commands of `links`, each only once,
+/// in the order they are first listed in.
+pub fn reload_commands<'a, I>(links: I) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a PlannedAction>,
+{
+    let mut commands = vec![];
+    for command in links.into_iter().filter_map(|p| p.reload.as_deref()) {
+        if !commands.contains(&command) {
+            commands.push(command);
+        }
+    }
+    commands
+}
+
+/// Returns the [`Link::build`](crate::config::Link::build) commands to run before `symlink_list` is
+/// planned: those of links whose origin doesn't exist, or of every link if `rebuild` is set. Each
+/// command is only returned once, in the order it is first listed in.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory the origins are in.
+/// + `symlink_list` - The links to build the origins of.
+/// + `rebuild` - Whether to build origins that exist already.
+pub fn build_commands<'a>(
+    dotfiles_dir: &Path,
+    symlink_list: &'a SymlinkList,
+    rebuild: bool,
+) -> Vec<&'a str> {
+    let mut commands = vec![];
+    for link in &symlink_list.links {
+        let Some(command) = link.build.as_deref() else {
+            continue;
+        };
+        let missing = fs::symlink_metadata(dotfiles_dir.join(&link.origin)).is_err();
+        if (rebuild || missing) && !commands.contains(&command) {
+            commands.push(command);
+        }
+    }
+    commands
+}
+
+/// Run a [`Link::build`](crate::config::Link::build) command with `sh`, in `dotfiles_dir`. Its
+/// output is not captured.
+///
+/// # Params
+/// + `command` - The command to run.
+/// + `dotfiles_dir` - The directory to run the command in.
+/// + `timeout` - How long to let the command run before stopping it, or `None` to wait for as long
+///   as it takes.
+///
+/// # Errors
+/// + [`Error::IoError`] if `sh` cannot be run.
+/// + [`Error::BuildFailed`] if the command exits unsuccessfully, or is still running after
+///   `timeout`.
+pub fn build(command: &str, dotfiles_dir: &Path, timeout: Option<Duration>) -> Result<()> {
+    log::info(format!(
+        "{} {}",
+        Paint::yellow("Building:"),
+        script::quote(command)
+    ));
+    let child = platform::shell(command).current_dir(dotfiles_dir).spawn()?;
+    match wait_with_timeout(child, timeout)? {
+        Some(status) if status.success() => Ok(()),
+        _ => Err(Error::BuildFailed(command.to_owned())),
+    }
+}
+
+/// Run a [`Link::reload`](crate::config::Link::reload) command with `sh`. Its output is not
+/// captured.
+///
+/// # Params
+/// + `command` - The command to run.
+/// + `timeout` - How long to let the command run before stopping it, or `None` to wait for as long
+///   as it takes.
+///
+/// # Errors
+/// + [`Error::IoError`] if `sh` cannot be run.
+/// + [`Error::ReloadFailed`] if the command exits unsuccessfully.
+/// + [`Error::ReloadTimedOut`] if the command is still running after `timeout`.
+pub fn reload(command: &str, timeout: Option<Duration>) -> Result<()> {
+    log::info(format!(
+        "{} {}",
+        Paint::yellow("Reloading:"),
+        script::quote(command)
+    ));
+    let child = platform::shell(command).spawn()?;
+    match wait_with_timeout(child, timeout)? {
+        Some(status) if status.success() => Ok(()),
+        Some(_) => Err(Error::ReloadFailed(command.to_owned())),
+        None => Err(Error::ReloadTimedOut {
+            command: command.to_owned(),
+            timeout: timeout.unwrap_or_default(),
+        }),
+    }
+}
+
+/// Run a hook with `sh`, in `dotfiles_dir`: one of the [`Hooks`](crate::config::Hooks) of a symlink
+/// list, or the [`Link::post_link`](crate::config::Link::post_link) command of a link.
+/// `$DOTCONFIG_DIR` is set to `dotfiles_dir`, and for a post-link hook, `$DOTCONFIG_LINK` and
+/// `$DOTCONFIG_ORIGIN` are set to the link's path and origin. Its output is not captured.
+///
+/// # Params
+/// + `command` - The command to run.
+/// + `dotfiles_dir` - The directory the origins are in.
+/// + `planned` - The link that was installed, for a post-link hook.
+/// + `timeout` - How long to let the command run before stopping it, or `None` to wait for as long
+///   as it takes.
+///
+/// # Errors
+/// + [`Error::IoError`] if `sh` cannot be run.
+/// + [`Error::HookFailed`] if the command exits unsuccessfully, or is still running after
+///   `timeout`.
+pub fn run_hook(
+    command: &str,
+    dotfiles_dir: &Path,
+    planned: Option<&PlannedAction>,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    log::info(format!(
+        "{} {}",
+        Paint::yellow("Running hook:"),
+        script::quote(command)
+    ));
+    let mut sh = platform::shell(command);
+    sh.current_dir(dotfiles_dir)
+        .env("DOTCONFIG_DIR", dotfiles_dir);
+    if let Some(planned) = planned {
+        sh.env("DOTCONFIG_LINK", &planned.link)
+            .env("DOTCONFIG_ORIGIN", &planned.origin);
+    }
+    match wait_with_timeout(sh.spawn()?, timeout)? {
+        Some(status) if status.success() => Ok(()),
+        _ => Err(Error::HookFailed(command.to_owned())),
+    }
+}
+
+/// Wait for `child` to exit, killing it if it is still running after `timeout`.
+///
+/// Returns the exit status of `child`, or `None` if it was killed.
+fn wait_with_timeout(
+    mut child: Child,
+    timeout: Option<Duration>,
+) -> io::Result<Option<ExitStatus>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return child.wait().map(Some),
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Install a single link by running the equivalent shell commands through `sudo`.
+///
+/// This is meant for retrying links that failed with [`Error::PermissionDenied`]. The install
+/// action is chosen again first, since the failed attempt may have been partially successful.
+///
+/// Returns the changes that were made to the filesystem.
+///
+/// # Errors
+/// + [`Error::LinkError`] if the link is invalid or the commands fail.
+/// + [`Error::IoError`] if `sudo` cannot be run.
+pub fn sudo_symlink(planned: &PlannedAction) -> Result<Vec<Operation>> {
+    sudo_symlinks(&[planned]).remove(0)
+}
+
+/// Install several links by running the equivalent shell commands through a single `sudo`, so
+/// that e.g. the password is only asked for once. The commands of each link stop at the first one
+/// that fails, and the other links are installed all the same.
+///
+/// See [`sudo_symlink`]. Returns the result of each link, in order.
+pub fn sudo_symlinks(planned: &[&PlannedAction]) -> Vec<Result<Vec<Operation>>> {
+    let mut results = sudo_operations(planned);
+    let Some(script) = sudo_script(&results) else {
+        return results;
+    };
+    log::info(format!(
+        "{} {}",
+        Paint::yellow("Running: sudo sh -c"),
+        script::quote(&script)
+    ));
+    let output = Command::new("sudo")
+        .args(["sh", "-c", &script])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output();
+    let failed: Vec<usize> = match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .filter_map(|i| i.parse().ok())
+            .collect(),
+        // sudo itself failed, so nothing was installed
+        Ok(_) => (0..planned.len()).collect(),
+        Err(e) => {
+            return results
+                .into_iter()
+                .map(|result| match result {
+                    Ok(operations) if !operations.is_empty() => {
+                        Err(io::Error::new(e.kind(), e.to_string()).into())
+                    }
+                    result => result,
+                })
+                .collect()
+        }
+    };
+
+    for (i, result) in results.iter_mut().enumerate() {
+        let PlannedAction {
+            origin,
+            link,
+            policy,
+            ..
+        } = planned[i];
+        let Ok(operations) = result else {
+            continue;
+        };
+        if operations.is_empty() {
+            continue;
+        }
+        if failed.contains(&i) {
+            *result = Err(link_error(origin, link, LinkFailure::SudoFailed));
+            continue;
+        }
+        if let Some(backup_dir) = &policy.backup_dir {
+            let trash = Trash::at(backup_dir.clone());
+            for operation in operations.iter() {
+                match operation {
+                    Operation::Backup { from, to } if to.starts_with(backup_dir) => {
+                        if let Err(e) = trash.record(from, to) {
+                            *result = Err(e);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    results
+}
+
+/// Returns the operations that install each link, with the install action chosen again. A
+/// directory that an earlier link creates is only created by that link.
+fn sudo_operations(planned: &[&PlannedAction]) -> Vec<Result<Vec<Operation>>> {
+    let mut results: Vec<Result<Vec<Operation>>> = vec![];
+    let mut dirs = vec![];
+    for PlannedAction {
+        origin,
+        link,
+        mode,
+        policy,
+        ..
+    } in planned.iter().copied()
+    {
+        let operations = install_action(origin, link, *mode, policy)
+            .and_then(|action| install_operations(origin, link, action, *mode, policy));
+        results.push(operations.map(|operations| {
+            operations
+                .into_iter()
+                .filter(|operation| match operation {
+                    Operation::CreateDir { path } if dirs.contains(path) => false,
+                    Operation::CreateDir { path } => {
+                        dirs.push(path.clone());
+                        true
+                    }
+                    _ => true,
+                })
+                .collect()
+        }));
+    }
+    results
+}
+
+/// Returns a shell script that runs the operations of each link in `results` that has any, in
+/// order, and prints the index of each link whose operations fail. The operations of a link stop
+/// at the first one that fails, and the other links are installed all the same.
+fn sudo_script(results: &[Result<Vec<Operation>>]) -> Option<String> {
+    let groups: Vec<_> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, result)| match result {
+            Ok(operations) if !operations.is_empty() => {
+                let commands: Vec<_> = operations.iter().map(script::command).collect();
+                Some(format!("{{ {}; }} || echo {}", commands.join(" && "), i))
+            }
+            _ => None,
+        })
+        .collect();
+    (!groups.is_empty()).then(|| groups.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planner::{plan, PlanOptions};
+    use std::path::PathBuf;
+
+    #[test]
+    fn links_retried_with_sudo_run_in_one_script_that_reports_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        for origin in ["zshrc", "vimrc", "inputrc"] {
+            fs::write(dotfiles_dir.join(origin), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("other")).unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  \
+             - {{path: {0}/new/.zshrc, origin: zshrc}}\n  \
+             - {{path: {0}/new/.vimrc, origin: vimrc}}\n  \
+             - {{path: {0}/other/.inputrc, origin: inputrc}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let plan = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+
+        let planned: Vec<_> = plan.iter().collect();
+        let results = sudo_operations(&planned);
+        let dirs: Vec<_> = results
+            .iter()
+            .map(|result| {
+                let operations = result.as_ref().unwrap();
+                operations
+                    .iter()
+                    .filter(|operation| matches!(operation, Operation::CreateDir { .. }))
+                    .count()
+            })
+            .collect();
+        assert_eq!(dirs, vec![1, 0, 0]);
+
+        // The last link fails, since its directory is replaced after its operations were chosen
+        fs::remove_dir(dir.path().join("other")).unwrap();
+        fs::write(dir.path().join("other"), "").unwrap();
+        let script = sudo_script(&results).unwrap();
+        let output = Command::new("sh")
+            .args(["-c", &script])
+            .stderr(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "2");
+        assert!(dir.path().join("new/.zshrc").is_symlink());
+        assert!(dir.path().join("new/.vimrc").is_symlink());
+    }
+
+    #[test]
+    fn reload_commands_are_run_once_for_changed_links() {
+        let planned = |action, reload: Option<&str>| PlannedAction {
+            origin: PathBuf::from("/cfg/tmux.conf"),
+            link: PathBuf::from("/home/me/.tmux.conf"),
+            action,
+            policy: ConflictPolicy::default(),
+            mode: LinkMode::Symlink,
+            description: None,
+            reload: reload.map(str::to_owned),
+            post_link: None,
+            group: None,
+            writable: true,
+            planning_time: Duration::ZERO,
+            cached: false,
+        };
+        let plan = [
+            planned(InstallAction::Link, Some("tmux source-file ~/.tmux.conf")),
+            planned(InstallAction::Link, None),
+            planned(InstallAction::BackupAndLink, Some("exec zsh")),
+            planned(InstallAction::Link, Some("tmux source-file ~/.tmux.conf")),
+        ];
+        assert_eq!(
+            reload_commands(&plan),
+            ["tmux source-file ~/.tmux.conf", "exec zsh"]
+        );
+    }
+
+    #[test]
+    fn hooks_see_the_link_and_failures_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "hooks: {post_install: fc-cache}\nlinks:\n  - {path: ~/.tmux.conf, origin: tmux.conf, \
+             post_link: 'tmux source-file \"$DOTCONFIG_LINK\"'}\n",
+        )
+        .unwrap();
+        assert_eq!(symlink_list.hooks.post_install.as_deref(), Some("fc-cache"));
+        assert_eq!(
+            symlink_list.links[0].post_link.as_deref(),
+            Some("tmux source-file \"$DOTCONFIG_LINK\"")
+        );
+
+        let planned = PlannedAction {
+            origin: dir.path().join("tmux.conf"),
+            link: PathBuf::from("/home/me/.tmux.conf"),
+            action: InstallAction::Link,
+            policy: ConflictPolicy::default(),
+            mode: LinkMode::Symlink,
+            description: None,
+            reload: None,
+            post_link: None,
+            group: None,
+            writable: true,
+            planning_time: Duration::ZERO,
+            cached: false,
+        };
+        // The hook runs in the dotfiles directory, where tmux.conf is
+        fs::write(dir.path().join("tmux.conf"), "").unwrap();
+        let command = format!(
+            "test \"$DOTCONFIG_LINK\" = /home/me/.tmux.conf && test \"$DOTCONFIG_ORIGIN\" = {0}/tmux.conf \
+             && test \"$DOTCONFIG_DIR\" = {0} && test -e tmux.conf",
+            dir.path().display()
+        );
+        run_hook(&command, dir.path(), Some(&planned), None).unwrap();
+        assert!(matches!(
+            run_hook("exit 1", dir.path(), None, None),
+            Err(Error::HookFailed(command)) if command == "exit 1"
+        ));
+    }
+
+    #[test]
+    fn reload_commands_are_stopped_after_the_timeout() {
+        assert!(reload("true", Some(Duration::from_secs(10))).is_ok());
+        let start = Instant::now();
+        assert!(matches!(
+            reload("sleep 10", Some(Duration::from_millis(100))),
+            Err(Error::ReloadTimedOut { .. })
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn owners_are_parsed_from_names_or_ids() {
+        let owner = |uid, gid| Owner { uid, gid };
+        assert_eq!(Owner::parse("root").unwrap(), owner(Some(0), None));
+        assert_eq!(
+            Owner::parse("1000:100").unwrap(),
+            owner(Some(1000), Some(100))
+        );
+        assert_eq!(Owner::parse(":root").unwrap(), owner(None, Some(0)));
+        assert!(matches!(
+            Owner::parse("root:no-such-group"),
+            Err(Error::UnknownOwner(_))
+        ));
+    }
+
+    #[test]
+    fn copies_are_installed_while_their_contents_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("settings.json"), "{}").unwrap();
+        let link = dir.path().join("settings.json");
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}, origin: settings.json, strategy: copy}}\n",
+            link.display()
+        ))
+        .unwrap();
+        let options = PlanOptions::default();
+        let install = || {
+            let planned = plan(&dotfiles_dir, &symlink_list, &options).unwrap();
+            let action = planned[0].action;
+            let results = apply(planned, &ApplyOptions::default());
+            assert!(results[0].result.is_ok());
+            action
+        };
+
+        assert_eq!(install(), InstallAction::Link);
+        assert!(!fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(install(), InstallAction::Skip);
+        fs::write(dotfiles_dir.join("settings.json"), "{\"theme\": \"dark\"}").unwrap();
+        assert_eq!(install(), InstallAction::BackupAndLink);
+        assert_eq!(fs::read_to_string(&link).unwrap(), "{\"theme\": \"dark\"}");
+    }
+
+    #[test]
+    fn runs_of_links_in_a_group_get_a_heading() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        platform::symlink(dotfiles_dir.join("zshrc"), dir.path().join("installed")).unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}/a, origin: zshrc}}\ngroups:\n  zsh:\n    - {{path: {0}/b, \
+             origin: zshrc}}\n    - {{path: {0}/c, origin: zshrc}}\n  done:\n    - {{path: \
+             {0}/installed, origin: zshrc}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let plan = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+
+        Paint::disable();
+        let heading = |group: &str| Some(format!("== {} ==", group));
+        assert_eq!(
+            group_headings(&plan, false),
+            [None, heading("zsh"), None, heading("done")]
+        );
+        assert_eq!(
+            group_headings(&plan, true),
+            [None, heading("zsh"), None, None]
+        );
+    }
+
+    #[test]
+    fn missing_origins_are_built() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("gitconfig"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}/.gitconfig, origin: gitconfig, build: 'echo > gitconfig'}}\n  \
+             - {{path: {0}/.zshrc, origin: zshrc, build: 'echo theme > zshrc'}}\n  - {{path: \
+             {0}/.zshenv, origin: zshrc, build: 'echo theme > zshrc'}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+
+        let commands = build_commands(&dotfiles_dir, &symlink_list, false);
+        assert_eq!(commands, ["echo theme > zshrc"]);
+        assert_eq!(
+            build_commands(&dotfiles_dir, &symlink_list, true),
+            ["echo > gitconfig", "echo theme > zshrc"]
+        );
+        for command in commands {
+            build(command, &dotfiles_dir, None).unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(dotfiles_dir.join("zshrc")).unwrap(),
+            "theme\n"
+        );
+        assert!(build_commands(&dotfiles_dir, &symlink_list, false).is_empty());
+        assert!(matches!(
+            build("exit 1", &dotfiles_dir, None),
+            Err(Error::BuildFailed(_))
+        ));
+    }
+
+    #[test]
+    fn no_links_are_installed_after_an_interrupt() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(true);
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}/.zshrc, origin: zshrc}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        let options = ApplyOptions {
+            interrupt: Some(&INTERRUPTED),
+            ..ApplyOptions::default()
+        };
+
+        assert!(apply(planned, &options).is_empty());
+        assert!(fs::symlink_metadata(dir.path().join(".zshrc")).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! A step-by-step account of how a single link is resolved, and why its install action was chosen.
+
+use crate::{
+    config::{Hooks, Link, SymlinkList},
+    expand_path,
+    planner::{
+        origin_case_on_disk, plan, resolve_link, unmet_condition, DirListings, InstallAction,
+        LinkMode, OnConflict, PlanOptions,
+    },
+    platform, Result,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Returns an account of every link in `symlink_list` that has `query` as its origin or one of its
+/// paths, as written or once expanded. Each one lists the link's fields, what its path and origin
+/// resolve to, what is at its path now, and the install action that [`plan`] chooses, with why.
+/// Returns an empty string if no link matches.
+///
+/// Each path of a link is planned on its own, so a link inside of another link's path is planned
+/// as if that link weren't there.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory the origins are in.
+/// + `symlink_list` - The links to look for `query` in.
+/// + `options` - The settings the links are planned with.
+/// + `query` - A path or origin, as written in the symlink list or expanded.
+///
+/// # Errors
+/// + [`Error::ShellexpandLookupError`](crate::Error::ShellexpandLookupError) if `query` references
+///   an unset shell variable.
+pub fn explain(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    options: &PlanOptions,
+    query: &str,
+) -> Result<String> {
+    let expand = |path: &str| -> Option<PathBuf> {
+        match &options.root {
+            Some(root) => root.expand(&path).ok(),
+            None => expand_path(&path).ok(),
+        }
+    };
+    let expanded_query = expand(query);
+    let matches = |entry: &Link, path: &String| {
+        entry.origin == query
+            || path == query
+            || (expanded_query.is_some() && expand(path) == expanded_query)
+    };
+    let mut out = String::new();
+    for entry in &symlink_list.links {
+        // A glob or recursive link is explained for each file, if the query is its origin as
+        // written or matches that file
+        let expanded = SymlinkList {
+            links: vec![entry.clone()],
+            origin_base: None,
+            vars: BTreeMap::new(),
+            hooks: Hooks::default(),
+        }
+        .expand_origins(&dotfiles_dir)
+        .map(|list| list.links)
+        .unwrap_or_else(|_| vec![entry.clone()]);
+        for file in &expanded {
+            for path in file
+                .path
+                .iter()
+                .filter(|path| entry.origin == query || matches(file, path))
+            {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                explain_path(dotfiles_dir, file, path, options, &mut out);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Append the account of `path`, one of the paths of `entry`, to `out`.
+fn explain_path(
+    dotfiles_dir: &Path,
+    entry: &Link,
+    path: &str,
+    options: &PlanOptions,
+    out: &mut String,
+) {
+    let mut line = |label: &str, value: &dyn std::fmt::Display| {
+        writeln!(out, "  {:<20}{}", format!("{}:", label), value).unwrap();
+    };
+    let unset = "(unset)".to_owned();
+    line("path", &path);
+    line("origin", &entry.origin);
+    line("rename", entry.rename.as_ref().unwrap_or(&unset));
+    line("group", entry.group.as_ref().unwrap_or(&unset));
+    line(
+        "backup",
+        &match entry.backup {
+            Some(backup) => backup.to_string(),
+            None => format!("(unset, so --on-conflict {})", on_conflict(options)),
+        },
+    );
+    line("optional", &entry.optional());
+    line("encrypted", &entry.encrypted);
+    line("template", &entry.template);
+    if let Some(strategy) = entry.strategy {
+        line("strategy", &format!("{:?}", strategy).to_lowercase());
+    }
+    if let Some(os) = &entry.os {
+        line("os", &os.join(", "));
+    }
+    if let Some(arch) = &entry.arch {
+        line("arch", &arch.join(", "));
+    }
+    if let Some(if_exists) = &entry.if_exists {
+        line("if_exists", if_exists);
+    }
+    if let Some(unless_exists) = &entry.unless_exists {
+        line("unless_exists", unless_exists);
+    }
+
+    let expanded = match &options.root {
+        Some(root) => root.expand(&path),
+        None => expand_path(&path),
+    };
+    match &expanded {
+        Ok(expanded) => line("expanded path", &expanded.display()),
+        Err(e) => line("expanded path", &format!("error: {}", e)),
+    }
+    let unresolved_origin = dotfiles_dir.join(&entry.origin);
+    let origin_path =
+        match origin_case_on_disk(dotfiles_dir, &entry.origin, &mut DirListings::new()) {
+            Some(on_disk) => {
+                line(
+                    "origin path",
+                    &format!(
+                        "{} (named {} on disk)",
+                        unresolved_origin.display(),
+                        on_disk.display()
+                    ),
+                );
+                dotfiles_dir.join(on_disk)
+            }
+            None => {
+                line("origin path", &unresolved_origin.display());
+                unresolved_origin
+            }
+        };
+    match fs::canonicalize(&origin_path) {
+        Ok(canonical) => line("canonical origin", &canonical.display()),
+        Err(e) => line("canonical origin", &format!("error: {}", e)),
+    }
+    let link = resolve_link(
+        path,
+        entry.rename.as_deref(),
+        &origin_path,
+        options.root.as_ref(),
+    );
+    match &link {
+        Ok(link) => {
+            line("link", &link.display());
+            line("at the link", &describe(link));
+            let parent = link.parent().unwrap_or(Path::new("/"));
+            line("in directory", &describe(parent));
+        }
+        Err(e) => line("link", &format!("error: {}", e)),
+    }
+
+    match unmet_condition(entry, options.root.as_ref()) {
+        Ok(Some(reason)) => {
+            line("action", &"none");
+            line(
+                "because",
+                &format!("The link is left out, since {}.", reason),
+            );
+            return;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            line("action", &"none");
+            line("because", &e);
+            return;
+        }
+    }
+    // Plan only this path of the link, so that its action is chosen exactly as when installing.
+    let single = SymlinkList {
+        links: vec![Link {
+            path: vec![path.to_owned()],
+            ..entry.clone()
+        }],
+        origin_base: None,
+        vars: BTreeMap::new(),
+        hooks: Hooks::default(),
+    };
+    match plan(&dotfiles_dir, &single, options) {
+        Ok(planned) => match planned.first() {
+            Some(planned) => {
+                line("action", &planned.action);
+                line(
+                    "because",
+                    &reason(planned.action, planned.mode, planned.cached),
+                );
+            }
+            None => {
+                line("action", &"none");
+                line(
+                    "because",
+                    &"The link is left out, since it is optional and its origin doesn't exist.",
+                );
+            }
+        },
+        Err(e) => {
+            line("action", &"none");
+            line("because", &e);
+        }
+    }
+}
+
+/// Returns what is at `path`, without following a symlink there, e.g. "a symlink to
+/// /home/me/.cfg/zshrc".
+fn describe(path: &Path) -> String {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return "nothing".to_owned(),
+    };
+    let kind = if metadata.file_type().is_symlink() {
+        match fs::read_link(path) {
+            Ok(target) => format!("a symlink to {}", target.display()),
+            Err(_) => "a symlink".to_owned(),
+        }
+    } else if metadata.is_dir() {
+        "a directory".to_owned()
+    } else {
+        format!("a file of {} bytes", metadata.len())
+    };
+    format!("{} ({})", kind, platform::describe_permissions(&metadata))
+}
+
+/// Returns the `--on-conflict` policy that `options` plan with.
+fn on_conflict(options: &PlanOptions) -> &'static str {
+    match options.on_conflict {
+        OnConflict::Backup => "backup",
+        OnConflict::Overwrite => "overwrite",
+        OnConflict::Skip => "skip",
+        OnConflict::Fail => "fail",
+    }
+}
+
+/// Returns why [`choose_install_action`](crate::choose_install_action) chose `action`.
+fn reason(action: InstallAction, mode: LinkMode, cached: bool) -> &'static str {
+    match action {
+        InstallAction::Skip if cached => {
+            "The link is recorded as installed in the cache, and neither it nor its origin has \
+             changed since."
+        }
+        InstallAction::Skip => match mode {
+            LinkMode::Symlink => "A symlink to the origin is already at the path.",
+            LinkMode::Hardlink => "The file at the path is already a hard link to the origin.",
+            LinkMode::Copy => "The file at the path already has the contents of the origin.",
+            LinkMode::Decrypt => "The file at the path already has the decrypted contents.",
+        },
+        InstallAction::Link => "Nothing is at the path, and the directory it goes in exists.",
+        InstallAction::CreateDirAndLink => {
+            "The directory the link goes in doesn't exist, so it is created first."
+        }
+        InstallAction::BackupAndLink => {
+            "Something other than the link is at the path, and is backed up first."
+        }
+        InstallAction::OverwriteAndLink => {
+            "Something other than the link is at the path, and is deleted first, since the link \
+             sets `backup: false` or --on-conflict is overwrite."
+        }
+        InstallAction::ReplaceIdenticalAndLink => {
+            "A file identical to the origin is at the path, and is replaced without a backup, since \
+             --skip-identical is set."
+        }
+        InstallAction::KeepExisting => {
+            "Something other than the link is at the path, and is kept, since --on-conflict is \
+             skip."
+        }
+        InstallAction::DecryptAndCompare => {
+            "A file is at the path, and whether it already has the decrypted contents is only known \
+             once the origin is decrypted, when installing. If it doesn't, it is in the way."
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_explained_by_path_or_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        fs::write(dir.path().join(".zshrc"), "export EDITOR=vim\n").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: [{0}/.zshrc, {0}/.zshenv], origin: zshrc}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let options = PlanOptions::default();
+
+        let by_path = explain(
+            &dotfiles_dir,
+            &symlink_list,
+            &options,
+            &format!("{}/.zshrc", dir.path().display()),
+        )
+        .unwrap();
+        assert!(by_path.contains("a file of 18 bytes"));
+        assert!(by_path.contains("action:             backup-and-link"));
+        assert!(!by_path.contains(".zshenv"));
+        let by_origin = explain(&dotfiles_dir, &symlink_list, &options, "zshrc").unwrap();
+        assert!(by_origin.contains("action:             link\n"));
+        assert!(explain(&dotfiles_dir, &symlink_list, &options, "vimrc")
+            .unwrap()
+            .is_empty());
+    }
+}
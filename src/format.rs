@@ -0,0 +1,572 @@
+//! Rewriting a YAML symlink list in a consistent layout, with its links sorted.
+
+use crate::{
+    config::{ConfigFormat, Source, SymlinkList},
+    log, Result,
+};
+use serde_yaml::{Mapping, Value};
+use std::{fs, path::PathBuf};
+use yansi::Paint;
+
+/// The order of the top-level fields of a symlink list.
+const LIST_FIELDS: [&str; 9] = [
+    "version",
+    "origin_base",
+    "vars",
+    "hooks",
+    "defaults",
+    "links",
+    "common",
+    "groups",
+    "hosts",
+];
+/// The order of the fields under `defaults:`.
+const DEFAULTS_FIELDS: [&str; 8] = [
+    "group", "mode", "strategy", "backup", "optional", "tags", "os", "arch",
+];
+/// The order of the fields of a link.
+const LINK_FIELDS: [&str; 18] = [
+    "path",
+    "origin",
+    "rename",
+    "mode",
+    "strategy",
+    "encrypted",
+    "template",
+    "backup",
+    "optional",
+    "tags",
+    "os",
+    "arch",
+    "if_exists",
+    "unless_exists",
+    "description",
+    "build",
+    "reload",
+    "post_link",
+];
+
+/// What to sort the links of a symlink list by.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// The link's first path
+    #[default]
+    Path,
+    /// The link's origin
+    Origin,
+}
+
+/// Returns `contents`, a YAML symlink list, with its fields in a consistent order and the links
+/// under `links:` and `common:`, in each group and for each host sorted by `sort_by`. Links with
+/// the same key keep their order, and groups and hosts keep theirs.
+///
+/// Every field is kept, except the empty `link:` key that may start a link, which has no effect. A
+/// `path` with a single path is written as a string rather than a list. Comments are lost, and
+/// YAML aliases are replaced with the values they refer to.
+///
+/// # Errors
+/// + [`Error::YamlError`](crate::Error::YamlError) if `contents` is not valid YAML.
+pub fn format(contents: &str, sort_by: SortKey) -> Result<String> {
+    let mut list: Value = serde_yaml::from_str(contents)?;
+    if let Value::Mapping(mapping) = &mut list {
+        if let Some(Value::Mapping(defaults)) = mapping.get_mut("defaults") {
+            *defaults = reorder(defaults, &DEFAULTS_FIELDS);
+        }
+        for links in link_lists(mapping) {
+            sort_links(links, sort_by);
+        }
+        *mapping = reorder(mapping, &LIST_FIELDS);
+    }
+    Ok(serde_yaml::to_string(&list)?)
+}
+
+/// The symlink lists that [`format_files`] didn't format.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Unformatted {
+    /// The symlink lists that aren't YAML, and so can't be formatted.
+    pub not_yaml: Vec<PathBuf>,
+    /// The YAML symlink lists that aren't formatted, if they were only checked.
+    pub unformatted: Vec<PathBuf>,
+}
+
+/// Format each symlink list from `source`, as by [`format`], or with `check`, only report those
+/// that aren't formatted. Symlink lists that aren't YAML are reported and left alone.
+///
+/// # Errors
+/// + Any error from [`SymlinkList::from_file_as`], since a symlink list must be valid to be
+///   formatted.
+/// + [`Error::IoError`](crate::Error::IoError) if a symlink list cannot be read or written.
+pub fn format_files(source: &Source, sort_by: SortKey, check: bool) -> Result<Unformatted> {
+    let mut unformatted = Unformatted::default();
+    for path in &source.symlink_list_paths {
+        let format = source.format_of(path);
+        if format != ConfigFormat::Yaml {
+            log::error(format!(
+                "{} {} {}",
+                Paint::red("Can't format"),
+                path.display(),
+                Paint::red(format!(
+                    "because only YAML can be formatted, not {}.",
+                    format
+                ))
+            ));
+            unformatted.not_yaml.push(path.clone());
+            continue;
+        }
+        SymlinkList::from_file_as(path, Some(format))?;
+        let contents = fs::read_to_string(path)?;
+        let formatted = self::format(&contents, sort_by)?;
+        if formatted == contents {
+            log::debug(format!(
+                "{} {}",
+                path.display(),
+                Paint::blue("is already formatted.")
+            ));
+        } else if check {
+            log::warn(format!(
+                "{} {}",
+                path.display(),
+                Paint::yellow("is not formatted.")
+            ));
+            unformatted.unformatted.push(path.clone());
+        } else {
+            fs::write(path, formatted)?;
+            log::info(format!("{} {}", Paint::green("Formatted"), path.display()));
+        }
+    }
+    Ok(unformatted)
+}
+
+/// Returns `contents`, a YAML symlink list, with a link from `path` to `origin` added at the end
+/// of `links:`. The rest of the text, comments included, is kept as it is, and the link is
+/// indented like the links before it. If `links:` is written in a way that the link can't be
+/// added to as text, e.g. as a flow sequence, the list is rewritten as [`format`] would, without
+/// sorting it.
+///
+/// # Errors
+/// + [`Error::YamlError`](crate::Error::YamlError) if `contents` is not valid YAML.
+pub fn append_link(contents: &str, path: &str, origin: &str) -> Result<String> {
+    let mut expected: Value = serde_yaml::from_str(contents)?;
+    let mut link = Mapping::new();
+    link.insert("path".into(), path.into());
+    link.insert("origin".into(), origin.into());
+    match &mut expected {
+        Value::Mapping(mapping) => match mapping.get_mut("links") {
+            Some(Value::Sequence(links)) => links.push(Value::Mapping(link.clone())),
+            _ => {
+                mapping.insert("links".into(), Value::Sequence(vec![link.clone().into()]));
+            }
+        },
+        _ => {
+            let mut mapping = Mapping::new();
+            mapping.insert("links".into(), Value::Sequence(vec![link.clone().into()]));
+            expected = Value::Mapping(mapping);
+        }
+    }
+
+    let appended = append_link_text(contents, &link)?;
+    if serde_yaml::from_str::<Value>(&appended).ok().as_ref() == Some(&expected) {
+        Ok(appended)
+    } else {
+        Ok(serde_yaml::to_string(&expected)?)
+    }
+}
+
+/// Returns `contents`, a YAML symlink list, without `path` in the link from `path` to `origin`,
+/// under `links:` or in a group. A link with no other paths is removed entirely, along with the
+/// comments directly above it, and the rest of the text is kept as it is. A link with other paths
+/// keeps them, and the list is rewritten as [`format`] would, without sorting it.
+///
+/// Returns `None` if no link in `contents` is from `path` to `origin`.
+///
+/// # Errors
+/// + [`Error::YamlError`](crate::Error::YamlError) if `contents` is not valid YAML.
+pub fn remove_link(contents: &str, path: &str, origin: &str) -> Result<Option<String>> {
+    let mut expected: Value = serde_yaml::from_str(contents)?;
+    let mut removed = false;
+    let mut remove_from = |links: &mut Vec<Value>| {
+        links.retain_mut(|link| {
+            if !is_link(link, path, origin) {
+                return true;
+            }
+            removed = true;
+            match link.get_mut("path") {
+                Some(Value::Sequence(paths)) if paths.len() > 1 => {
+                    paths.retain(|p| p.as_str() != Some(path));
+                    true
+                }
+                _ => false,
+            }
+        })
+    };
+    if let Value::Mapping(mapping) = &mut expected {
+        for links in link_lists(mapping) {
+            remove_from(links);
+        }
+    }
+    if !removed {
+        return Ok(None);
+    }
+
+    let lines: Vec<&str> = contents.lines().collect();
+    for (start, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with("- ") && trimmed != "-" {
+            continue;
+        }
+        // The item ends at the next line, other than a blank line or comment, that is indented no
+        // further than its `-`
+        let indent = line.len() - trimmed.len();
+        let end = lines[start + 1..]
+            .iter()
+            .position(|line| {
+                let trimmed = line.trim_start();
+                !trimmed.is_empty()
+                    && !trimmed.starts_with('#')
+                    && line.len() - trimmed.len() <= indent
+            })
+            .map_or(lines.len(), |i| start + 1 + i);
+        let item: String = lines[start..end]
+            .iter()
+            .map(|line| format!("{}\n", line.get(indent..).unwrap_or_default()))
+            .collect();
+        let is_match = serde_yaml::from_str::<Vec<Value>>(&item)
+            .is_ok_and(|items| matches!(items.as_slice(), [link] if is_link(link, path, origin)));
+        if !is_match {
+            continue;
+        }
+        let mut first = start;
+        while first > 0 && lines[first - 1].trim_start().starts_with('#') {
+            first -= 1;
+        }
+        let mut last = end;
+        while last > start + 1 && lines[last - 1].trim().is_empty() {
+            last -= 1;
+        }
+        let kept: String = lines[..first]
+            .iter()
+            .chain(&lines[last..])
+            .map(|line| format!("{}\n", line))
+            .collect();
+        if serde_yaml::from_str::<Value>(&kept).ok().as_ref() == Some(&expected) {
+            return Ok(Some(kept));
+        }
+    }
+    Ok(Some(serde_yaml::to_string(&expected)?))
+}
+
+/// Returns every list of links in `list`: those under `links:` and `common:`, and those in each
+/// group and for each host.
+fn link_lists(list: &mut Mapping) -> Vec<&mut Vec<Value>> {
+    let mut lists = vec![];
+    for (field, value) in list.iter_mut() {
+        match (field.as_str(), value) {
+            (Some("links" | "common"), Value::Sequence(links)) => lists.push(links),
+            (Some("groups" | "hosts"), Value::Mapping(map)) => {
+                for links in map.values_mut() {
+                    if let Value::Sequence(links) = links {
+                        lists.push(links);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    lists
+}
+
+/// Returns whether `link` is a link to `origin` with `path` as one of its paths.
+fn is_link(link: &Value, path: &str, origin: &str) -> bool {
+    let has_path = match link.get("path") {
+        Some(Value::Sequence(paths)) => paths.iter().any(|p| p.as_str() == Some(path)),
+        Some(p) => p.as_str() == Some(path),
+        None => false,
+    };
+    has_path && link.get("origin").and_then(Value::as_str) == Some(origin)
+}
+
+/// Returns `contents` with `link` written as a block sequence item after the last line of the
+/// top-level `links:`, or with `links:` added at the end if there is none.
+fn append_link_text(contents: &str, link: &Mapping) -> Result<String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    // The lines before the link, the lines after it, and how far the link is indented
+    let (before, after, indent) = match lines.iter().position(|line| links_key(line).is_some()) {
+        None => (lines.len(), lines.len(), None),
+        Some(start) if links_key(lines[start]) == Some("[]") => (start, start + 1, None),
+        Some(start) => {
+            // The links end at the first line after them that starts a top-level field. Comments
+            // and blank lines before that field belong to it.
+            let mut end = start + 1;
+            let mut indent = None;
+            for (i, line) in lines.iter().enumerate().skip(start + 1) {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                if !line.starts_with([' ', '-']) {
+                    break;
+                }
+                if indent.is_none() && trimmed.starts_with('-') {
+                    indent = Some(&line[..line.len() - trimmed.len()]);
+                }
+                end = i + 1;
+            }
+            (end, end, Some(indent.unwrap_or("  ")))
+        }
+    };
+
+    let mut appended = String::new();
+    for line in &lines[..before] {
+        appended.push_str(line);
+        appended.push('\n');
+    }
+    let indent = indent.unwrap_or_else(|| {
+        appended.push_str("links:\n");
+        "  "
+    });
+    for (i, field) in serde_yaml::to_string(link)?.lines().enumerate() {
+        appended.push_str(indent);
+        appended.push_str(if i == 0 { "- " } else { "  " });
+        appended.push_str(field);
+        appended.push('\n');
+    }
+    for line in &lines[after..] {
+        appended.push_str(line);
+        appended.push('\n');
+    }
+    Ok(appended)
+}
+
+/// Returns what follows `links:` on `line`, without a comment, if `line` starts the top-level
+/// `links:` field.
+fn links_key(line: &str) -> Option<&str> {
+    line.strip_prefix("links:")
+        .map(|rest| rest.split('#').next().unwrap_or_default().trim())
+}
+
+/// Put the fields of every link in `links` in order, and sort the links by `sort_by`.
+fn sort_links(links: &mut [Value], sort_by: SortKey) {
+    for link in links.iter_mut() {
+        if let Value::Mapping(fields) = link {
+            fields.remove("link");
+            if let Some(Value::Sequence(paths)) = fields.get("path") {
+                if let [path] = paths.as_slice() {
+                    let path = path.clone();
+                    fields.insert("path".into(), path);
+                }
+            }
+            *fields = reorder(fields, &LINK_FIELDS);
+        }
+    }
+    let key = |link: &Value| -> String {
+        let value = match sort_by {
+            SortKey::Path => link.get("path").map(|path| path.get(0).unwrap_or(path)),
+            SortKey::Origin => link.get("origin"),
+        };
+        value.and_then(Value::as_str).unwrap_or_default().to_owned()
+    };
+    links.sort_by_cached_key(key);
+}
+
+/// Returns the fields of `mapping`, with those named in `order` first, in that order, followed by
+/// any others in the order they were in.
+fn reorder(mapping: &Mapping, order: &[&str]) -> Mapping {
+    let mut reordered = Mapping::new();
+    for field in order {
+        if let Some(value) = mapping.get(field) {
+            reordered.insert((*field).into(), value.clone());
+        }
+    }
+    for (field, value) in mapping {
+        if !reordered.contains_key(field) {
+            reordered.insert(field.clone(), value.clone());
+        }
+    }
+    reordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_yaml_symlink_lists_are_formatted() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("symlinks.yml");
+        let toml = dir.path().join("symlinks.toml");
+        let unsorted =
+            "links:\n- path: ~/.zshrc\n  origin: zshrc\n- path: ~/.bashrc\n  origin: bashrc\n";
+        fs::write(&yaml, unsorted).unwrap();
+        fs::write(&toml, "links = []\n").unwrap();
+        let source = Source {
+            symlink_list_paths: vec![yaml.clone(), toml.clone()],
+            ..Source::default()
+        };
+
+        let checked = format_files(&source, SortKey::Path, true).unwrap();
+        assert_eq!(checked.not_yaml, vec![toml.clone()]);
+        assert_eq!(checked.unformatted, vec![yaml.clone()]);
+        assert_eq!(fs::read_to_string(&yaml).unwrap(), unsorted);
+
+        let formatted = format_files(&source, SortKey::Path, false).unwrap();
+        assert_eq!(formatted.not_yaml, [toml]);
+        assert!(formatted.unformatted.is_empty());
+        assert!(format_files(&source, SortKey::Path, true)
+            .unwrap()
+            .unformatted
+            .is_empty());
+    }
+
+    #[test]
+    fn links_are_sorted_and_their_fields_ordered() {
+        let contents = "\
+groups:
+  zsh:
+    - origin: zshrc
+      path: ~/.zshrc
+    - path: [~/.zprofile]
+      origin: zprofile
+links:
+  - link:
+    origin: vimrc
+    path: ~/.vimrc
+    backup: false
+  - description: Editor
+    path: [~/.config/nvim, ~/.nvim]
+    origin: nvim
+defaults:
+  optional: true
+";
+        assert_eq!(
+            format(contents, SortKey::Path).unwrap(),
+            "\
+defaults:
+  optional: true
+links:
+- path:
+  - ~/.config/nvim
+  - ~/.nvim
+  origin: nvim
+  description: Editor
+- path: ~/.vimrc
+  origin: vimrc
+  backup: false
+groups:
+  zsh:
+  - path: ~/.zprofile
+    origin: zprofile
+  - path: ~/.zshrc
+    origin: zshrc
+"
+        );
+        let formatted = format(contents, SortKey::Origin).unwrap();
+        assert!(formatted.find("origin: nvim") < formatted.find("origin: vimrc"));
+        assert!(formatted.find("origin: zprofile") < formatted.find("origin: zshrc"));
+        // Formatting is idempotent.
+        assert_eq!(format(&formatted, SortKey::Origin).unwrap(), formatted);
+    }
+
+    #[test]
+    fn links_are_removed_without_changing_the_rest_of_the_list() {
+        let contents = "\
+links:
+  # The shell
+  - path: ~/.zshrc
+    origin: zshrc
+
+  - path: [~/.vimrc, ~/.config/nvim/init.vim]
+    origin: vimrc
+groups:
+  git:
+    - path: ~/.gitconfig  # global
+      origin: gitconfig
+    - path: ~/.gitignore
+      origin: gitignore
+";
+        assert_eq!(
+            remove_link(contents, "~/.zshrc", "zshrc").unwrap().unwrap(),
+            "\
+links:
+
+  - path: [~/.vimrc, ~/.config/nvim/init.vim]
+    origin: vimrc
+groups:
+  git:
+    - path: ~/.gitconfig  # global
+      origin: gitconfig
+    - path: ~/.gitignore
+      origin: gitignore
+"
+        );
+        assert_eq!(
+            remove_link(contents, "~/.gitconfig", "gitconfig")
+                .unwrap()
+                .unwrap(),
+            "\
+links:
+  # The shell
+  - path: ~/.zshrc
+    origin: zshrc
+
+  - path: [~/.vimrc, ~/.config/nvim/init.vim]
+    origin: vimrc
+groups:
+  git:
+    - path: ~/.gitignore
+      origin: gitignore
+"
+        );
+        let removed = remove_link(contents, "~/.vimrc", "vimrc").unwrap().unwrap();
+        assert!(!removed.contains("~/.vimrc"));
+        assert!(removed.contains("~/.config/nvim/init.vim"));
+        assert_eq!(remove_link(contents, "~/.vimrc", "zshrc").unwrap(), None);
+    }
+
+    #[test]
+    fn links_are_appended_without_changing_the_rest_of_the_list() {
+        let contents = "\
+# My links
+links:
+- path: ~/.zshrc  # the shell
+  origin: zshrc
+
+  # Editor
+- path: ~/.vimrc
+  origin: vimrc
+
+# Grouped links
+groups: {}
+";
+        assert_eq!(
+            append_link(contents, "~/.config/nvim/init.lua", "nvim/init.lua").unwrap(),
+            "\
+# My links
+links:
+- path: ~/.zshrc  # the shell
+  origin: zshrc
+
+  # Editor
+- path: ~/.vimrc
+  origin: vimrc
+- path: ~/.config/nvim/init.lua
+  origin: nvim/init.lua
+
+# Grouped links
+groups: {}
+"
+        );
+        assert_eq!(
+            append_link("# Empty\nlinks: []\n", "~/.zshrc", "zshrc").unwrap(),
+            "# Empty\nlinks:\n  - path: ~/.zshrc\n    origin: zshrc\n"
+        );
+        assert_eq!(
+            append_link(
+                "links: [{path: ~/.vimrc, origin: vimrc}]",
+                "~/.zshrc",
+                "zshrc"
+            )
+            .unwrap(),
+            "links:\n- path: ~/.vimrc\n  origin: vimrc\n- path: ~/.zshrc\n  origin: zshrc\n"
+        );
+    }
+}
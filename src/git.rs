@@ -0,0 +1,203 @@
+//! Optional checks that the dotfiles directory is under version control, and cloning dotfiles
+//! directories from remote repositories.
+
+use crate::{log, Error, Result};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use yansi::Paint;
+
+/// Returns whether `dir` is inside a git working tree, i.e. whether it or one of its ancestors
+/// contains a `.git` directory (or a `.git` file, for worktrees and submodules).
+pub fn is_work_tree(dir: &Path) -> bool {
+    dir.ancestors().any(|dir| dir.join(".git").exists())
+}
+
+/// Create an empty git repository in `dir`.
+///
+/// # Errors
+/// + [`Error::GitError`] if `git` cannot be run or exits unsuccessfully.
+pub fn init(dir: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["init", "--quiet"])
+        .output()
+        .map_err(|e| Error::GitError(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the paths in the working tree containing `dir` that have uncommitted changes, as
+/// reported by `git status --porcelain`.
+///
+/// # Errors
+/// + [`Error::GitError`] if `git` cannot be run or exits unsuccessfully.
+pub fn uncommitted_changes(dir: &Path) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["status", "--porcelain"])
+        .output()
+        .map_err(|e| Error::GitError(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.get(3..).unwrap_or(line).to_owned())
+        .collect())
+}
+
+/// Returns those of `paths` that git ignores in the working tree containing `dir`, each with the
+/// rule that ignores it, written as `<file>:<line>:<pattern>`, e.g. `.gitignore:3:*.local`. Files
+/// that are tracked are never ignored, even if a rule matches them.
+///
+/// # Errors
+/// + [`Error::GitError`] if `git` cannot be run or fails, e.g. because a path is outside of the
+///   working tree.
+pub fn ignored(dir: &Path, paths: &[PathBuf]) -> Result<Vec<(PathBuf, String)>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["check-ignore", "--verbose", "--stdin", "-z"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::GitError(format!("Failed to run git: {}", e)))?;
+    let mut input = Vec::new();
+    for path in paths {
+        input.extend(path.as_os_str().as_encoded_bytes());
+        input.push(0);
+    }
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&input)
+        .map_err(|e| Error::GitError(format!("Failed to run git: {}", e)))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::GitError(format!("Failed to run git: {}", e)))?;
+    // git check-ignore exits with 1 if no path is ignored.
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        return Err(Error::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    // Each ignored path is reported as its source, line number, pattern and path, each followed by
+    // a NUL.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<_> = stdout.split_terminator('\0').collect();
+    Ok(fields
+        .chunks_exact(4)
+        .map(|fields| {
+            let [source, line, pattern, path] = [fields[0], fields[1], fields[2], fields[3]];
+            (
+                PathBuf::from(path),
+                format!("{}:{}:{}", source, line, pattern),
+            )
+        })
+        .collect())
+}
+
+/// Returns whether `dir` looks like the URL of a remote git repository rather than a local path,
+/// e.g. `https://github.com/me/dotfiles` or `git@github.com:me/dotfiles.git`.
+pub fn is_url(dir: &str) -> bool {
+    ["https://", "http://", "ssh://", "git://", "file://", "git@"]
+        .iter()
+        .any(|prefix| dir.starts_with(prefix))
+}
+
+/// Clone the repository at `url` into the cache directory, or pull the latest changes if it has
+/// been cloned before. Returns the path of the clone.
+///
+/// Clones are kept in `~/.cache/dotconfig/<owner>-<repository>`. git's output, including any
+/// prompts for credentials, goes straight to the terminal.
+///
+/// # Errors
+/// + [`Error::GitError`] if the cache directory cannot be determined, or if `git` cannot be run or
+///   exits unsuccessfully.
+pub fn clone_or_pull(url: &str) -> Result<PathBuf> {
+    let cache_dir = dirs_next::cache_dir()
+        .ok_or_else(|| Error::GitError("Could not determine the cache directory.".to_owned()))?;
+    let dir = cache_dir.join("dotconfig").join(clone_name(url));
+    let mut git = Command::new("git");
+    if dir.join(".git").exists() {
+        log::info(format!(
+            "{} {}...",
+            Paint::yellow("Pulling the latest changes into"),
+            dir.display()
+        ));
+        git.arg("-C")
+            .arg(&dir)
+            .args(["pull", "--ff-only", "--quiet"]);
+    } else {
+        log::info(format!(
+            "{} {} {} {}...",
+            Paint::yellow("Cloning"),
+            url,
+            Paint::yellow("into"),
+            dir.display()
+        ));
+        git.args(["clone", "--quiet", url]).arg(&dir);
+    }
+    let status = git
+        .status()
+        .map_err(|e| Error::GitError(format!("Failed to run git: {}", e)))?;
+    if !status.success() {
+        return Err(Error::GitError(format!(
+            "Failed to clone or update {} in {}.",
+            url,
+            dir.display()
+        )));
+    }
+    Ok(dir)
+}
+
+/// Returns the name of the directory `url` is cloned into: its last two path components joined
+/// with a `-`, without a `.git` extension, e.g. `me-dotfiles` for `git@github.com:me/dotfiles.git`.
+fn clone_name(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    let mut components: Vec<_> = url.rsplit(['/', ':']).take(2).collect();
+    components.reverse();
+    components.join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn ignored_paths_are_reported_with_their_rule() {
+        let dir = tempfile::tempdir().unwrap();
+        init(dir.path()).unwrap();
+        fs::write(dir.path().join(".gitignore"), "# Local files\n*.local\n").unwrap();
+        let paths = [PathBuf::from("zshrc"), PathBuf::from("zshrc.local")];
+        assert_eq!(
+            ignored(dir.path(), &paths).unwrap(),
+            [(
+                PathBuf::from("zshrc.local"),
+                ".gitignore:2:*.local".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn clones_are_named_after_the_owner_and_repository() {
+        assert_eq!(clone_name("git@github.com:me/dotfiles.git"), "me-dotfiles");
+        assert_eq!(clone_name("https://github.com/me/dotfiles"), "me-dotfiles");
+        assert_eq!(clone_name("https://github.com/me/dotfiles/"), "me-dotfiles");
+    }
+}
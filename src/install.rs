@@ -0,0 +1,1224 @@
+//! Installing a symlink list from start to finish: reading it, planning, showing the plan, asking
+//! for confirmation, applying it, retrying with sudo, and running reload commands and hooks.
+
+use crate::{
+    archive,
+    cache::{self, Cache},
+    config::{ConfigFormat, Hooks, OriginBase, SymlinkList},
+    executor::{self, ActionResult, ApplyOptions, Owner},
+    explain, git,
+    log::{self, count, Level, Status},
+    manifest::{Manifest, Operation},
+    planner::{self, InstallAction, LinkMode, PlanOptions, PlannedAction},
+    platform,
+    prompt::confirm,
+    script,
+    state::{self, Installed},
+    template, tree, Error, Result,
+};
+use notify::{RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, stdin, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+use yansi::Paint;
+
+/// What [`install`] does with the plan, if it doesn't install it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    /// Show the plan, ask for confirmation and install it.
+    #[default]
+    Install,
+    /// Show the plan without changing anything or asking for confirmation.
+    DryRun,
+    /// Print the plan as a JSON array.
+    DryRunJson,
+    /// Print the resolved origin, link and install action of every link.
+    PrintConfig,
+    /// Print the order links are installed in, and the links each one must be installed after.
+    PrintPlanGraph,
+    /// Print the links as a single symlink list, after combining the symlink lists and applying
+    /// defaults.
+    PrintEffectiveConfig,
+    /// Print the directory the origins are in as a tree.
+    PrintOriginsTree,
+    /// Print the files in the directory the origins are in that no link refers to.
+    FindOrphans,
+    /// Print how the link with this path or origin is resolved.
+    Explain(String),
+    /// Write a shell script that performs the installation to this file.
+    EmitScript(PathBuf),
+}
+
+impl Output {
+    /// Returns whether nothing is changed, so that no lock needs to be held.
+    pub fn is_read_only(&self) -> bool {
+        *self != Output::Install
+    }
+}
+
+/// Settings for [`install`] and [`watch`].
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// The directory that holds the config files.
+    pub dotfiles_dir: PathBuf,
+    /// The full paths of the symlink lists. Links in later lists override links in earlier lists.
+    pub symlink_list_paths: Vec<PathBuf>,
+    /// Read the symlink lists in this format, whatever their extension.
+    pub config_format: Option<ConfigFormat>,
+    /// What the origins in each symlink list are relative to.
+    pub origins_relative_to: OriginBase,
+    /// Look for origins in this directory, whatever the symlink lists set.
+    pub origin_base: Option<PathBuf>,
+    /// Install the links for this host.
+    pub host: String,
+    /// Leave out the links in these groups.
+    pub exclude_groups: Vec<String>,
+    /// Only install the links with one of these tags, and those without tags.
+    pub only: Vec<String>,
+    /// Leave out the links with any of these tags.
+    pub exclude: Vec<String>,
+    /// What to do with the plan.
+    pub output: Output,
+    /// Files and directories, relative to the directory the origins are in, that are never
+    /// orphans.
+    pub ignore_orphans: Vec<PathBuf>,
+    /// How many directories deep to look for orphans and print the origins tree.
+    pub max_depth: Option<usize>,
+    /// How links are resolved. Its `cache` is read from the directory the origins are in with
+    /// `only_changed`.
+    pub plan: PlanOptions,
+    /// Skip links that were installed by an earlier run without checking them, if nothing has
+    /// changed since.
+    pub only_changed: bool,
+    /// With `only_changed`, check every link anyway, and record them in a new cache.
+    pub no_cache: bool,
+    /// Install without asking for confirmation.
+    pub yes: bool,
+    /// Cancel the installation if the confirmation prompt is answered with an empty line.
+    pub default_no: bool,
+    /// Offer to retry links that fail due to missing permissions with sudo.
+    pub allow_sudo: bool,
+    /// Warn about origins that git ignores.
+    pub check_git_ignored: bool,
+    /// Run the build command of every link, not only of those whose origin doesn't exist.
+    pub rebuild: bool,
+    /// Stop build and reload commands and hooks that are still running after this long.
+    pub command_timeout: Option<Duration>,
+    /// Give created links and directories this owner.
+    pub owner: Option<Owner>,
+    /// Read each symlink back after creating it.
+    pub verify: bool,
+    /// Only list links that are already installed at the debug level.
+    pub quiet_skip: bool,
+    /// The state file to record installed symlinks in, rather than the default one.
+    pub state: Option<PathBuf>,
+    /// Remove symlinks whose links have been removed from the symlink list without asking.
+    pub prune: bool,
+    /// Write a record of every change made to the filesystem to this file.
+    pub manifest: Option<PathBuf>,
+    /// Write every file that links will replace to this archive before installing.
+    pub archive: Option<PathBuf>,
+    /// Plan again after installing, and fail if anything is left to do.
+    pub assert_idempotent: bool,
+    /// Print how long the slowest links took to plan and install.
+    pub timings: bool,
+    /// Links that took longer than this are marked as slow with `timings`.
+    pub slow_threshold: Duration,
+    /// Set while links are being installed, e.g. so that a Ctrl-C handler can wait for the link
+    /// being installed.
+    pub installing: Option<&'static AtomicBool>,
+    /// Stop before installing the next link once this is set (see [`ApplyOptions::interrupt`]).
+    pub interrupt: Option<&'static AtomicBool>,
+}
+
+/// How an installation ended, if it didn't fail with an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Everything was installed, or printed.
+    Done,
+    /// No link has the path or origin to explain.
+    NotFound,
+    /// One or more links could not be installed or removed, or a reload command or hook failed.
+    Failed,
+    /// The installation was cancelled at the confirmation prompt.
+    Cancelled,
+    /// The installation was interrupted by [`Options::interrupt`].
+    Interrupted,
+    /// Some links would still be changed by another installation.
+    NotIdempotent,
+}
+
+/// Returns the directory the origins are in, which is only the dotfiles directory if neither
+/// `origin_base` nor the symlink list sets another.
+pub fn origin_dir(
+    dotfiles_dir: &Path,
+    origin_base: Option<&Path>,
+    symlink_list: &SymlinkList,
+) -> PathBuf {
+    match (origin_base, &symlink_list.origin_base) {
+        (Some(origin_base), _) => origin_base.to_owned(),
+        (None, Some(origin_base)) => PathBuf::from(origin_base),
+        (None, None) => dotfiles_dir.to_owned(),
+    }
+}
+
+/// Plan the installation of every link in the symlink lists, show the plan, and install it once
+/// the user confirms.
+///
+/// # Errors
+/// + Any error from reading the symlink lists, rendering templates, running build commands or
+///   planning, since nothing can be installed without a plan.
+/// + [`Error::NotATerminal`] if confirmation is needed and stdin is not a terminal.
+/// + [`Error::IoError`] if the script, archive or manifest cannot be written.
+pub fn install(options: &Options) -> Result<Outcome> {
+    run(options, false)
+}
+
+/// Plan the installation, show the plan, and install it.
+///
+/// # Params
+/// + `unattended` - Install without asking for confirmation, and replace identical files without
+///   backing them up, as if `skip_identical` were set. Links that fail due to missing permissions
+///   are not retried with sudo.
+fn run(options: &Options, unattended: bool) -> Result<Outcome> {
+    let symlink_list_paths = &options.symlink_list_paths;
+    if options.origins_relative_to == OriginBase::DotfilesDir {
+        for path in symlink_list_paths {
+            if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+                log::debug(format!(
+                    "{} {} {}",
+                    Paint::blue("The symlink list"),
+                    path.display(),
+                    Paint::blue(
+                        "is a symlink, but its origins are relative to the dotfiles directory. \
+                         Pass --origins-relative-to symlink-list to change this."
+                    )
+                ));
+            }
+        }
+    }
+    let mut symlink_list = SymlinkList::from_files(
+        symlink_list_paths,
+        options.config_format,
+        options.origins_relative_to,
+        Some(&options.host),
+    )?;
+    let origin_dir = origin_dir(
+        &options.dotfiles_dir,
+        options.origin_base.as_deref(),
+        &symlink_list,
+    );
+    for group in symlink_list.exclude_groups(&options.exclude_groups) {
+        log::warn(format!(
+            "{} {}",
+            Paint::yellow("No links are in the excluded group"),
+            group
+        ));
+    }
+    for tag in symlink_list.select_tags(&options.only, &options.exclude) {
+        log::warn(format!(
+            "{} {}",
+            Paint::yellow("No links have the tag"),
+            tag
+        ));
+    }
+    let plan_options = PlanOptions {
+        skip_identical: options.plan.skip_identical || unattended,
+        cache: (options.only_changed && !options.no_cache)
+            .then(|| Cache::from_file(&origin_dir.join(cache::FILE_NAME))),
+        ..options.plan.clone()
+    };
+    match &options.output {
+        Output::PrintEffectiveConfig => {
+            let format = options
+                .config_format
+                .or_else(|| ConfigFormat::from_extension(&symlink_list_paths[0]))
+                .unwrap_or(ConfigFormat::Yaml);
+            print!("{}", symlink_list.to_string_as(format)?);
+            return Ok(Outcome::Done);
+        }
+        Output::PrintOriginsTree => {
+            // Printed before planning, since planning fails if an origin is missing
+            print!(
+                "{}",
+                tree::render(
+                    &origin_dir,
+                    &symlink_list,
+                    symlink_list_paths,
+                    options.max_depth
+                )?
+            );
+            return Ok(Outcome::Done);
+        }
+        Output::FindOrphans => {
+            // Files linked only on other hosts aren't orphans
+            let symlink_list = SymlinkList::from_files(
+                symlink_list_paths,
+                options.config_format,
+                options.origins_relative_to,
+                None,
+            )?;
+            let mut ignore = symlink_list_paths.to_vec();
+            ignore.extend(
+                options
+                    .ignore_orphans
+                    .iter()
+                    .map(|path| origin_dir.join(path)),
+            );
+            ignore.push(origin_dir.join(cache::FILE_NAME));
+            ignore.push(origin_dir.join(template::DIR_NAME));
+            let orphans = tree::orphans(&origin_dir, &symlink_list, &ignore, options.max_depth)?;
+            for orphan in &orphans {
+                println!("{}", orphan.display());
+            }
+            log::info(format!("{} not linked.", count(orphans.len(), "file")));
+            return Ok(Outcome::Done);
+        }
+        _ => {}
+    }
+
+    // Rendered in memory even when only planning, since the links of templates point to the
+    // rendered files, which are only written when installing
+    let variables = template::variables(&symlink_list.vars, &options.host);
+    let rendered = template::render_all(&origin_dir, &mut symlink_list, &variables)?;
+    if matches!(options.output, Output::Install | Output::EmitScript(_)) {
+        for rendered in &rendered {
+            rendered.write()?;
+        }
+    } else {
+        template::unwritten_to_templates(&origin_dir, &mut symlink_list, &rendered);
+    }
+
+    if let Output::Explain(query) = &options.output {
+        // Explained before building, so that a missing origin is shown as it is
+        let explanation = explain::explain(&origin_dir, &symlink_list, &plan_options, query)?;
+        if explanation.is_empty() {
+            log::error(format!(
+                "{} {}",
+                Paint::red("No link has the path or origin"),
+                query
+            ));
+            return Ok(Outcome::NotFound);
+        }
+        print!("{}", explanation);
+        return Ok(Outcome::Done);
+    }
+
+    // Generated origins must exist before they can be planned, but printing the plan shouldn't
+    // change anything
+    let timeout = options.command_timeout;
+    if matches!(options.output, Output::Install | Output::EmitScript(_)) {
+        for command in executor::build_commands(&origin_dir, &symlink_list, options.rebuild) {
+            executor::build(command, &origin_dir, timeout)?;
+        }
+    }
+
+    let planning_start = Instant::now();
+    let plan = planner::plan(&origin_dir, &symlink_list, &plan_options)?;
+    let planning_time = planning_start.elapsed();
+    // The link, and the time it took to plan and to install it
+    let mut timings: Vec<_> = plan
+        .iter()
+        .map(|p| (p.link.clone(), p.planning_time, Duration::ZERO))
+        .collect();
+
+    match &options.output {
+        Output::PrintConfig => {
+            for PlannedAction {
+                origin,
+                link,
+                action,
+                ..
+            } in &plan
+            {
+                println!("{}\t{}\t{}", origin.display(), link.display(), action);
+            }
+            return Ok(Outcome::Done);
+        }
+        Output::PrintPlanGraph => {
+            for (i, (planned, dependencies)) in
+                plan.iter().zip(planner::dependencies(&plan)).enumerate()
+            {
+                println!("{}. {}", i + 1, planned.link.display());
+                for j in dependencies {
+                    println!("    after {}. {}", j + 1, plan[j].link.display());
+                }
+            }
+            return Ok(Outcome::Done);
+        }
+        _ => {}
+    }
+
+    if options.check_git_ignored {
+        check_git_ignored(&origin_dir, &plan)?;
+    }
+
+    match &options.output {
+        Output::DryRunJson => {
+            println!("{}", plan_json(&plan)?);
+            return Ok(Outcome::Done);
+        }
+        Output::EmitScript(script_path) => {
+            fs::write(script_path, script::render(&plan)?)?;
+            platform::make_executable(script_path)?;
+            log::info(format!(
+                "Wrote installation script to {}",
+                script_path.display()
+            ));
+            return Ok(Outcome::Done);
+        }
+        _ => {}
+    }
+    let dry_run = options.output == Output::DryRun;
+
+    // Links left out of this run would look like they were removed from the symlink list
+    let orphans: Vec<Installed> = if options.only.is_empty()
+        && options.exclude.is_empty()
+        && options.exclude_groups.is_empty()
+    {
+        let links: Vec<_> = plan.iter().map(|p| p.link.clone()).collect();
+        state::read(options.state.as_deref()).map_or(vec![], |(_, state)| {
+            state
+                .orphans(&origin_dir, &links)
+                .into_iter()
+                .cloned()
+                .collect()
+        })
+    } else {
+        vec![]
+    };
+
+    preview(options, &plan, &orphans, &symlink_list.hooks);
+
+    let not_writable = plan.iter().filter(|p| !p.writable).count();
+    if not_writable > 0 {
+        log::warn(format!(
+            "{} {}",
+            Paint::red(format!(
+                "{} can't be written to, so linking there will fail.",
+                count(not_writable, "destination")
+            )),
+            if options.allow_sudo {
+                "You will be offered to retry with sudo."
+            } else {
+                "Pass --allow-sudo to be offered to retry with sudo."
+            }
+        ));
+    }
+
+    if plan.iter().all(|p| p.action.is_noop()) {
+        // All actions are `Skip` or `KeepExisting`.
+        log::info(Paint::green("Everything is already up to date."));
+        if options.only_changed && !dry_run {
+            let mut cache = Cache::default();
+            for planned in plan.iter().filter(|p| p.action == InstallAction::Skip) {
+                cache.record(&planned.origin, &planned.link);
+            }
+            write_cache(&cache, &origin_dir);
+        }
+        if !dry_run {
+            if !prune_orphans(options, &orphans, unattended)? {
+                return Ok(Outcome::Failed);
+            }
+            update_state(
+                options,
+                plan.iter()
+                    .filter(|p| p.action == InstallAction::Skip && p.mode == LinkMode::Symlink)
+                    .map(|p| (p.origin.clone(), p.link.clone()))
+                    .collect(),
+            );
+        }
+        if options.timings {
+            print_timings(options, planning_time, &timings);
+        }
+        return Ok(Outcome::Done);
+    }
+
+    log::info(summary(&plan)?);
+    if dry_run {
+        return Ok(Outcome::Done);
+    }
+
+    // Ask for permission to proceed
+    if !unattended && !options.yes && !confirm(&question(&plan), !options.default_no)? {
+        log::info("Installation cancelled.");
+        return Ok(Outcome::Cancelled);
+    }
+
+    if let Some(archive) = &options.archive {
+        let archived = archive::write(archive, &plan)?;
+        log::info(format!(
+            "Archived {} to {}",
+            count(archived.len(), "file"),
+            archive.display()
+        ));
+    }
+
+    let mut failed = false;
+    if let Some(command) = &symlink_list.hooks.pre_install {
+        if let Err(e) = executor::run_hook(command, &origin_dir, None, timeout) {
+            log::error(e.painted());
+            failed = true;
+        }
+    }
+
+    // Symlink each file listed in config.links
+    let mut denied = vec![];
+    let mut operations = vec![];
+    // Links that were created or changed, whose reload commands should run
+    let mut changed = vec![];
+    let apply_options = ApplyOptions {
+        verify: options.verify,
+        quiet_skip: options.quiet_skip,
+        owner: options.owner,
+        interrupt: options.interrupt,
+    };
+    let total = plan.len();
+    if let Some(installing) = options.installing {
+        installing.store(true, Ordering::SeqCst);
+    }
+    let results = executor::apply(plan, &apply_options);
+    if let Some(installing) = options.installing {
+        installing.store(false, Ordering::SeqCst);
+    }
+    let interrupted = options
+        .interrupt
+        .is_some_and(|interrupt| interrupt.swap(false, Ordering::SeqCst));
+    if interrupted {
+        log::warn(format!(
+            "{} {} {}",
+            Paint::yellow("Interrupted after"),
+            count(results.len(), "link"),
+            Paint::yellow(format!(
+                "of {}. The others were left as they were, and no reload commands or hooks will run.",
+                total
+            ))
+        ));
+    }
+    let mut cache = Cache::default();
+    // The origin and path of each symlink that is installed, to record in the state
+    let mut installed = vec![];
+    for (i, mut result) in results.into_iter().enumerate() {
+        timings[i].2 = result.elapsed;
+        let changed_anything = !result.operations.is_empty();
+        if result.result.is_ok()
+            && (changed_anything || result.planned.action == InstallAction::Skip)
+        {
+            cache.record(&result.planned.origin, &result.planned.link);
+            if result.planned.mode == LinkMode::Symlink {
+                installed.push((result.planned.origin.clone(), result.planned.link.clone()));
+            }
+        }
+        operations.append(&mut result.operations);
+        match result.result {
+            Err(Error::PermissionDenied(_)) if options.allow_sudo && !unattended => {
+                denied.push(result)
+            }
+            Err(e) => {
+                log::error(e.painted());
+                failed = true;
+            }
+            Ok(()) if changed_anything => changed.push(result.planned),
+            Ok(()) => {}
+        }
+    }
+
+    let retried = retry_with_sudo(options, denied, interrupted)?;
+    failed |= retried.failed;
+    installed.extend(retried.installed);
+    changed.extend(retried.changed);
+    operations.extend(retried.operations);
+
+    if options.only_changed {
+        write_cache(&cache, &origin_dir);
+    }
+    if !interrupted && !prune_orphans(options, &orphans, unattended)? {
+        failed = true;
+    }
+    update_state(options, installed);
+    if let Some(manifest_path) = &options.manifest {
+        let dotfiles_dir = fs::canonicalize(&options.dotfiles_dir)
+            .unwrap_or_else(|_| options.dotfiles_dir.clone());
+        Manifest::new(dotfiles_dir, operations).write(manifest_path)?;
+        log::info(format!("Wrote manifest to {}", manifest_path.display()));
+    }
+
+    if interrupted {
+        return Ok(Outcome::Interrupted);
+    }
+    for planned in &changed {
+        if let Some(command) = &planned.post_link {
+            if let Err(e) = executor::run_hook(command, &origin_dir, Some(planned), timeout) {
+                log::error(e.painted());
+                failed = true;
+            }
+        }
+    }
+    for command in executor::reload_commands(&changed) {
+        if let Err(e) = executor::reload(command, timeout) {
+            log::error(e.painted());
+            failed = true;
+        }
+    }
+    if let Some(command) = &symlink_list.hooks.post_install {
+        if let Err(e) = executor::run_hook(command, &origin_dir, None, timeout) {
+            log::error(e.painted());
+            failed = true;
+        }
+    }
+    if options.timings {
+        print_timings(options, planning_time, &timings);
+    }
+    if failed {
+        return Ok(Outcome::Failed);
+    }
+
+    if options.assert_idempotent {
+        let replan = planner::plan(&origin_dir, &symlink_list, &plan_options)?;
+        let changed: Vec<_> = replan.iter().filter(|p| !p.action.is_noop()).collect();
+        for PlannedAction { link, action, .. } in &changed {
+            log::error(format!(
+                "{} {} {} {}",
+                Paint::red("Not idempotent:"),
+                link.display(),
+                Paint::red("would be installed again with action"),
+                action
+            ));
+        }
+        if !changed.is_empty() {
+            return Ok(Outcome::NotIdempotent);
+        }
+        log::info(Paint::green(
+            "Installing again would change nothing. The installation is idempotent.",
+        ));
+    }
+    Ok(Outcome::Done)
+}
+
+/// Show every link in `plan` with what will be done to it, followed by the symlinks in `orphans`
+/// and the reload commands and hooks that will run.
+fn preview(options: &Options, plan: &[PlannedAction], orphans: &[Installed], hooks: &Hooks) {
+    let mut preview = log::Buffer::new();
+    let headings = executor::group_headings(plan, options.quiet_skip);
+    for (
+        PlannedAction {
+            origin,
+            link,
+            action,
+            description,
+            writable,
+            ..
+        },
+        heading,
+    ) in plan.iter().zip(headings)
+    {
+        if let Some(heading) = heading {
+            preview.info(heading);
+        }
+        let mut description = match description {
+            Some(description) => format!("  {}", Paint::new(description).dimmed()),
+            None => String::new(),
+        };
+        if !writable {
+            description.push_str(&format!("  {}", Paint::red("(destination not writable)")));
+        }
+        match action {
+            InstallAction::Link | InstallAction::CreateDirAndLink => preview.info(format!(
+                "{}{} {} {} {}{}",
+                log::symbol(Status::Link),
+                Paint::yellow("Will link:           "),
+                link.display(),
+                Paint::yellow("->"),
+                origin.display(),
+                description
+            )),
+            InstallAction::BackupAndLink => preview.info(format!(
+                "{}{} {} {} {}{}",
+                log::symbol(Status::Backup),
+                Paint::yellow("Will backup and link:"),
+                link.display(),
+                Paint::yellow("->"),
+                origin.display(),
+                description
+            )),
+            InstallAction::OverwriteAndLink => preview.info(format!(
+                "{}{} {} {} {}{}",
+                log::symbol(Status::Remove),
+                Paint::yellow("Will overwrite:      "),
+                link.display(),
+                Paint::yellow("->"),
+                origin.display(),
+                description
+            )),
+            InstallAction::ReplaceIdenticalAndLink => preview.info(format!(
+                "{}{} {} {} {}{}",
+                log::symbol(Status::Link),
+                Paint::yellow("Identical, will link:"),
+                link.display(),
+                Paint::yellow("->"),
+                origin.display(),
+                description
+            )),
+            InstallAction::Skip => preview.log(
+                if options.quiet_skip {
+                    Level::Debug
+                } else {
+                    Level::Info
+                },
+                format!(
+                    "{}{} {} {} {}{}",
+                    log::symbol(Status::Skip),
+                    Paint::green("Already linked:      "),
+                    link.display(),
+                    Paint::green("->"),
+                    origin.display(),
+                    description
+                ),
+            ),
+            InstallAction::KeepExisting => preview.info(format!(
+                "{}{} {}{}",
+                log::symbol(Status::Keep),
+                Paint::yellow("Will keep existing:  "),
+                link.display(),
+                description
+            )),
+            InstallAction::DecryptAndCompare => preview.info(format!(
+                "{}{} {} {} {}{}",
+                log::symbol(Status::Link),
+                Paint::yellow("Will decrypt:        "),
+                link.display(),
+                Paint::yellow("->"),
+                origin.display(),
+                description
+            )),
+        }
+    }
+    for Installed { link, origin, .. } in orphans {
+        preview.info(format!(
+            "{}{} {} {} {}",
+            log::symbol(Status::Remove),
+            Paint::yellow(if options.prune {
+                "Will remove:         "
+            } else {
+                "No longer listed:    "
+            }),
+            link.display(),
+            Paint::yellow("->"),
+            origin.display()
+        ));
+    }
+    for command in executor::reload_commands(plan.iter().filter(|p| !p.action.is_noop())) {
+        preview.info(format!(
+            "{} {}",
+            Paint::yellow("Will reload:         "),
+            command
+        ));
+    }
+    // Hooks only run if something is installed
+    let global_hooks = if plan.iter().all(|p| p.action.is_noop()) {
+        Hooks::default()
+    } else {
+        hooks.clone()
+    };
+    let hooks = global_hooks
+        .pre_install
+        .iter()
+        .chain(
+            plan.iter()
+                .filter(|p| !p.action.is_noop())
+                .filter_map(|p| p.post_link.as_ref()),
+        )
+        .chain(&global_hooks.post_install);
+    for command in hooks {
+        preview.info(format!(
+            "{} {}",
+            Paint::yellow("Will run hook:       "),
+            command
+        ));
+    }
+    preview.flush();
+}
+
+/// The links that [`retry_with_sudo`] installed.
+#[derive(Default)]
+struct Retried {
+    /// The origin and path of each symlink that was installed.
+    installed: Vec<(PathBuf, PathBuf)>,
+    /// The links that were created or changed.
+    changed: Vec<PlannedAction>,
+    operations: Vec<Operation>,
+    /// Whether a link was not retried, or failed again.
+    failed: bool,
+}
+
+/// Offer to retry the links in `denied`, which could not be installed due to missing permissions.
+/// Those that are retried are installed with a single sudo, so that the password is only asked
+/// for once. With `yes`, they are retried without asking.
+///
+/// # Errors
+/// + [`Error::IoError`] if the answer cannot be read.
+fn retry_with_sudo(
+    options: &Options,
+    denied: Vec<ActionResult>,
+    interrupted: bool,
+) -> Result<Retried> {
+    let mut retried = Retried::default();
+    let can_ask = stdin().is_terminal();
+    if !denied.is_empty() && !interrupted && !options.yes && !can_ask {
+        log::warn(Paint::yellow(
+            "Not retrying with sudo, since stdin is not a terminal. Pass --yes to retry without \
+             asking.",
+        ));
+    }
+    let mut retry = vec![];
+    for ActionResult {
+        planned, result, ..
+    } in denied
+    {
+        if let Err(e) = result {
+            log::error(e.painted());
+        }
+        if interrupted || !(options.yes || (can_ask && confirm("Retry with sudo?", false)?)) {
+            retried.failed = true;
+            continue;
+        }
+        retry.push(planned);
+    }
+    let results = executor::sudo_symlinks(&retry.iter().collect::<Vec<_>>());
+    for (planned, result) in retry.into_iter().zip(results) {
+        match result {
+            Ok(mut operations) => {
+                if planned.mode == LinkMode::Symlink {
+                    retried
+                        .installed
+                        .push((planned.origin.clone(), planned.link.clone()));
+                }
+                if !operations.is_empty() {
+                    retried.changed.push(planned);
+                }
+                retried.operations.append(&mut operations);
+            }
+            Err(e) => {
+                log::error(e.painted());
+                retried.failed = true;
+            }
+        }
+    }
+    Ok(retried)
+}
+
+/// Write `cache` to the cache file in `origin_dir`. A cache that can't be written is only warned
+/// about, since the links are installed all the same.
+fn write_cache(cache: &Cache, origin_dir: &Path) {
+    let path = origin_dir.join(cache::FILE_NAME);
+    if let Err(e) = cache.write(&path) {
+        log::warn(format!(
+            "{} {}: {}",
+            Paint::yellow("Failed to write the cache"),
+            path.display(),
+            e
+        ));
+    }
+}
+
+/// Record that a symlink to each origin is installed at each path in `installed` in the state
+/// file, and forget the symlinks that have since been removed or replaced.
+fn update_state(options: &Options, installed: Vec<(PathBuf, PathBuf)>) {
+    let Some((path, mut state)) = state::read(options.state.as_deref()) else {
+        return;
+    };
+    for (origin, link) in installed {
+        state.record(&origin, &link);
+    }
+    state.retain_installed();
+    state::write(&state, &path);
+}
+
+/// Remove the symlinks in `orphans`, which were installed by an earlier run but whose links are no
+/// longer in the symlink list: right away with `prune`, or once the user confirms if stdin is a
+/// terminal and the installation isn't unattended. Otherwise they are left in place.
+///
+/// Returns whether every symlink that was to be removed was removed.
+fn prune_orphans(options: &Options, orphans: &[Installed], unattended: bool) -> Result<bool> {
+    if orphans.is_empty() {
+        return Ok(true);
+    }
+    let prune = options.prune
+        || (!unattended
+            && !options.yes
+            && stdin().is_terminal()
+            && confirm(
+                &format!(
+                    "Remove {} no longer in the symlink list?",
+                    count(orphans.len(), "link")
+                ),
+                false,
+            )?);
+    if !prune {
+        log::info(
+            "Links no longer in the symlink list were left in place. Pass --prune to remove them.",
+        );
+        return Ok(true);
+    }
+    let mut pruned = true;
+    // Each symlink is checked again, in case it was replaced since the plan was made
+    for installed in orphans.iter().filter(|installed| installed.is_installed()) {
+        match fs::remove_file(&installed.link) {
+            Ok(()) => log::info(format!(
+                "{}{} {}",
+                log::symbol(Status::Remove),
+                Paint::green("Removed"),
+                installed.link.display()
+            )),
+            Err(e) => {
+                log::error(format!(
+                    "{} {}: {}",
+                    Paint::red("Failed to remove"),
+                    installed.link.display(),
+                    e
+                ));
+                pruned = false;
+            }
+        }
+    }
+    Ok(pruned)
+}
+
+/// Wait this long after a change for further changes before installing again, so that e.g. saving
+/// several files at once only causes one installation.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Install the links, then install them again whenever a symlink list or anything in the dotfiles
+/// directory changes, until the installation is interrupted. Installations don't ask for
+/// confirmation, replace identical files without backing them up and don't retry with sudo, and
+/// failed installations are reported without ending the loop.
+///
+/// # Errors
+/// + [`Error::WatchError`] if the files cannot be watched.
+pub fn watch(options: &Options) -> Result<Outcome> {
+    let dotfiles_dir = fs::canonicalize(&options.dotfiles_dir)?;
+    let symlink_list_paths = options
+        .symlink_list_paths
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<io::Result<Vec<_>>>()?;
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&dotfiles_dir, RecursiveMode::Recursive)?;
+    for parent in symlink_list_paths.iter().filter_map(|path| path.parent()) {
+        // Watch the directory rather than the file itself, since editors often replace the file
+        // when saving it.
+        if !parent.starts_with(&dotfiles_dir) {
+            watcher.watch(parent, RecursiveMode::NonRecursive)?;
+        }
+    }
+    // Writing the manifest must not count as a change, or every installation would cause another.
+    let manifest_path = options.manifest.as_ref().and_then(|path| {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+    });
+    let is_relevant = |event: &notify::Event| {
+        !event.kind.is_access()
+            && event.paths.iter().any(|path| {
+                symlink_list_paths.contains(path)
+                    || (path.starts_with(&dotfiles_dir)
+                        && !path.starts_with(dotfiles_dir.join(".git"))
+                        && !path.ends_with(cache::FILE_NAME)
+                        && !path.starts_with(dotfiles_dir.join(template::DIR_NAME))
+                        && Some(path) != manifest_path.as_ref())
+            })
+    };
+    let options = Options {
+        dotfiles_dir: dotfiles_dir.clone(),
+        symlink_list_paths: symlink_list_paths.clone(),
+        ..options.clone()
+    };
+
+    loop {
+        match run(&options, true) {
+            Ok(Outcome::Interrupted) => return Ok(Outcome::Interrupted),
+            Ok(_) => {}
+            Err(e) => log::error(e.painted()),
+        }
+        log::info(Paint::blue("Watching for changes. Press Ctrl-C to stop."));
+        loop {
+            let event = events
+                .recv()
+                .map_err(|_| notify::Error::generic("the watcher stopped"))??;
+            if is_relevant(&event) {
+                break;
+            }
+        }
+        while events.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+        log::info(format!(
+            "{} {}",
+            Paint::blue(format!("[{}]", chrono::Local::now().format("%H:%M:%S"))),
+            Paint::blue("Change detected. Installing again...")
+        ));
+    }
+}
+
+/// The number of links listed by [`print_timings`].
+const SLOWEST_LINKS: usize = 10;
+
+/// Print how long planning took, and the links that took longest to plan and install, slowest
+/// first. Links that took longer than `slow_threshold` are marked as slow.
+///
+/// # Params
+/// + `timings` - Each link, with the time it took to plan and to install it.
+fn print_timings(
+    options: &Options,
+    planning_time: Duration,
+    timings: &[(PathBuf, Duration, Duration)],
+) {
+    let ms = |duration: Duration| format!("{:.1} ms", duration.as_secs_f64() * 1000.0);
+    let threshold = options.slow_threshold;
+    let mut timings: Vec<_> = timings.iter().collect();
+    timings.sort_by_key(|(_, planning, installing)| std::cmp::Reverse(*planning + *installing));
+    let slow = timings
+        .iter()
+        .filter(|(_, planning, installing)| *planning + *installing > threshold)
+        .count();
+    log::info(format!(
+        "Planned {} in {}. {} took longer than {}.",
+        count(timings.len(), "link"),
+        ms(planning_time),
+        count(slow, "link"),
+        ms(threshold)
+    ));
+    log::info("Slowest links:");
+    for (link, planning, installing) in timings.into_iter().take(SLOWEST_LINKS) {
+        let total = *planning + *installing;
+        log::info(format!(
+            "  {:>10}  {} {}{}",
+            ms(total),
+            link.display(),
+            Paint::new(format!(
+                "(planning {}, installing {})",
+                ms(*planning),
+                ms(*installing)
+            ))
+            .dimmed(),
+            if total > threshold {
+                format!(" {}", Paint::yellow("(slow)"))
+            } else {
+                String::new()
+            }
+        ));
+    }
+}
+
+/// Returns `plan` as a pretty-printed JSON array, for [`Output::DryRunJson`].
+///
+/// # Errors
+/// + [`Error::JsonError`] if the plan cannot be serialized.
+fn plan_json(plan: &[PlannedAction]) -> Result<String> {
+    let links: Vec<_> = plan
+        .iter()
+        .map(|planned| {
+            serde_json::json!({
+                "link": planned.link,
+                "origin": planned.origin,
+                "action": planned.action.to_string(),
+                "mode": match planned.mode {
+                    LinkMode::Symlink => "symlink",
+                    LinkMode::Hardlink => "hardlink",
+                    LinkMode::Copy => "copy",
+                    LinkMode::Decrypt => "decrypt",
+                },
+                "group": planned.group,
+                "description": planned.description,
+                "reload": planned.reload,
+                "writable": planned.writable,
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&links)?)
+}
+
+/// Returns a one-line summary of the changes that installing `plan` will make, e.g. "This will
+/// create 3 links, back up 1 existing file and create 2 directories." Backups are highlighted, and
+/// deletions are only mentioned if there are any. Directories created in the trash for backups
+/// are not counted.
+///
+/// # Errors
+/// + [`Error::LinkError`] if a link is not a valid path.
+fn summary(plan: &[PlannedAction]) -> Result<String> {
+    let (mut links, mut backups, mut removals) = (0, 0, 0);
+    let skipped = plan
+        .iter()
+        .filter(|p| p.action == InstallAction::Skip)
+        .count();
+    let kept = plan
+        .iter()
+        .filter(|p| p.action == InstallAction::KeepExisting)
+        .count();
+    let mut dirs = BTreeSet::new();
+    for PlannedAction {
+        origin,
+        link,
+        action,
+        mode,
+        policy,
+        ..
+    } in plan
+    {
+        for operation in planner::install_operations(origin, link, *action, *mode, policy)? {
+            match operation {
+                Operation::CreateDir { path } if link.starts_with(&path) => {
+                    dirs.insert(path);
+                }
+                Operation::CreateDir { .. } => {}
+                Operation::Backup { .. } => backups += 1,
+                Operation::Remove { .. } => removals += 1,
+                Operation::Link { .. }
+                | Operation::HardLink { .. }
+                | Operation::Copy { .. }
+                | Operation::Decrypt { .. } => links += 1,
+            }
+        }
+    }
+
+    let backups = match backups {
+        0 => Paint::default(count(0, "existing file")),
+        n => Paint::yellow(count(n, "existing file")),
+    };
+    let mut parts = vec![
+        format!("create {}", count(links, "link")),
+        format!("back up {}", backups),
+    ];
+    if removals > 0 {
+        parts.push(format!(
+            "delete {}",
+            Paint::red(count(removals, "existing file"))
+        ));
+    }
+    if kept > 0 {
+        parts.push(format!(
+            "leave {} in place",
+            Paint::yellow(count(kept, "existing file"))
+        ));
+    }
+    parts.push(format!("create {}", count(dirs.len(), "directory")));
+    let last = parts.pop().unwrap_or_default();
+    let mut summary = format!("This will {} and {}.", parts.join(", "), last);
+    if skipped > 0 {
+        summary.push_str(&format!(
+            " {} already installed.",
+            Paint::green(match skipped {
+                1 => "1 link is".to_owned(),
+                n => format!("{} links are", n),
+            })
+        ));
+    }
+    Ok(summary)
+}
+
+/// Returns the question to confirm the installation with, which names the files that will be
+/// deleted or backed up, if there are any.
+fn question(plan: &[PlannedAction]) -> String {
+    let (mut removals, mut backups) = (0, 0);
+    for planned in plan {
+        match planned.action {
+            InstallAction::OverwriteAndLink => removals += 1,
+            InstallAction::BackupAndLink => backups += 1,
+            _ => {}
+        }
+    }
+    if removals > 0 {
+        format!(
+            "Proceed, and {} without a backup?",
+            Paint::red(format!("delete {}", count(removals, "existing file"))).bold()
+        )
+    } else if backups > 0 {
+        format!(
+            "Proceed, and move {} out of the way?",
+            Paint::yellow(count(backups, "existing file"))
+        )
+    } else {
+        "Proceed with installation?".to_owned()
+    }
+}
+
+/// Warn if `dotfiles_dir` is not tracked by git, or if it has uncommitted changes.
+///
+/// # Errors
+/// + [`Error::UncommittedChanges`] if there are uncommitted changes and `require_clean` is set.
+/// + [`Error::GitError`] if the status of the repository cannot be determined.
+pub fn check_git(dotfiles_dir: &Path, require_clean: bool) -> Result<()> {
+    if !git::is_work_tree(dotfiles_dir) {
+        log::warn(format!(
+            "{} {} {}",
+            Paint::yellow("Warning:"),
+            dotfiles_dir.display(),
+            Paint::yellow("is not a git repository.")
+        ));
+        return Ok(());
+    }
+    let changes = git::uncommitted_changes(dotfiles_dir)?;
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let mut warning = log::Buffer::new();
+    warning.warn(format!(
+        "{} {} {}",
+        Paint::yellow("Warning:"),
+        dotfiles_dir.display(),
+        Paint::yellow("has uncommitted changes:")
+    ));
+    for change in changes {
+        warning.warn(format!("    {}", change));
+    }
+    warning.flush();
+    if require_clean {
+        return Err(Error::UncommittedChanges(dotfiles_dir.to_owned()));
+    }
+    Ok(())
+}
+
+/// Warn about each origin in `plan` that git ignores, with the rule that ignores it. Origins
+/// outside of the dotfiles directory are not checked.
+///
+/// # Errors
+/// + [`Error::GitError`] if git cannot tell which origins are ignored.
+fn check_git_ignored(dotfiles_dir: &Path, plan: &[PlannedAction]) -> Result<()> {
+    if !git::is_work_tree(dotfiles_dir) {
+        log::warn(format!(
+            "{} {} {}",
+            Paint::yellow("Warning:"),
+            dotfiles_dir.display(),
+            Paint::yellow("is not a git repository, so no origins are ignored.")
+        ));
+        return Ok(());
+    }
+    let canonical_dotfiles_dir = fs::canonicalize(dotfiles_dir)?;
+    let mut origins: Vec<_> = plan
+        .iter()
+        .filter_map(|planned| planned.origin.strip_prefix(&canonical_dotfiles_dir).ok())
+        .map(Path::to_owned)
+        .collect();
+    origins.sort();
+    origins.dedup();
+    for (origin, rule) in git::ignored(&canonical_dotfiles_dir, &origins)? {
+        log::warn(format!(
+            "{} {} {} {}{}",
+            Paint::yellow("Warning: The origin"),
+            origin.display(),
+            Paint::yellow("is ignored by"),
+            rule,
+            Paint::yellow(", so it isn't under version control.")
+        ));
+    }
+    Ok(())
+}
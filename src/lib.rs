@@ -0,0 +1,588 @@
+//! Symlinks configuration files from a central location to wherever they need to be on the system,
+//! so that those config files can be maintained under version control.
+//!
+//! Installation happens in two steps. [`planner::plan`] resolves every [`config::Link`] in a
+//! [`config::SymlinkList`] and decides what needs to be done for it, without touching the
+//! filesystem. [`executor::apply`] then carries out a plan, creating directories, backing up
+//! existing files and creating symlinks as needed.
+//!
+//! Each step lives in its own module: [`config`] reads symlink lists, [`planner`] makes plans,
+//! [`executor`] applies them and [`backup`] moves aside the files that links replace.
+
+// Hard links and the cache tell files apart by their file index on Windows
+#![cfg_attr(windows, feature(windows_by_handle))]
+
+pub mod archive;
+pub mod backup;
+pub mod cache;
+pub mod config;
+pub mod decrypt;
+pub mod doctor;
+pub mod edit;
+pub mod executor;
+pub mod explain;
+pub mod format;
+pub mod git;
+pub mod install;
+pub mod lock;
+pub mod log;
+pub mod manifest;
+pub mod planner;
+pub mod platform;
+mod progress;
+pub mod prompt;
+pub mod root;
+pub mod script;
+pub mod state;
+pub mod status;
+pub mod template;
+pub mod trash;
+pub mod tree;
+pub mod uninstall;
+
+use std::{
+    env::{self, VarError},
+    io,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+use thiserror::Error;
+use yansi::Paint;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the name of this host, as `uname -n` prints it, or `None` if it can't be found. On
+/// Windows, the name is taken from `%COMPUTERNAME%`.
+pub fn hostname() -> Option<String> {
+    if cfg!(windows) {
+        return env::var("COMPUTERNAME")
+            .ok()
+            .filter(|name| !name.is_empty());
+    }
+    let output = Command::new("uname").arg("-n").output().ok()?;
+    let hostname = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    (output.status.success() && !hostname.is_empty()).then_some(hostname)
+}
+
+/// Returns an [`Error::LinkError`] for the link from `link` to `origin`.
+fn link_error(origin: &Path, link: &Path, cause: LinkFailure) -> Error {
+    Error::LinkError {
+        origin: origin.to_owned(),
+        link: link.to_owned(),
+        cause,
+    }
+}
+
+/// Returns the path to the symlink with all shell variables expanded.
+///
+/// # Params
+/// + `link` - The path to the link file.
+///
+/// # Errors
+/// + [Error::ShellexpandLookupError] if the path contains a shell variable that does not exist in
+///   the environment.
+pub fn expand_link_file<P>(link: &P) -> Result<PathBuf>
+where
+    P: AsRef<str>,
+{
+    expand_path(link)
+}
+
+/// Returns `path` with `~` and all shell variables expanded. On Windows, `%VAR%` variables such as
+/// `%APPDATA%` are expanded too.
+///
+/// `$HOME` falls back to the home directory from the user database when it is unset or empty. The
+/// XDG base directory variables (`$XDG_CONFIG_HOME`, `$XDG_DATA_HOME`, `$XDG_STATE_HOME` and
+/// `$XDG_CACHE_HOME`) fall back to their default locations under the home directory when they are
+/// unset or empty.
+///
+/// # Errors
+/// + [Error::UnknownHomeDir] if the path uses `$HOME` or an XDG base directory variable, and the
+///   home directory cannot be determined.
+/// + [Error::ShellexpandLookupError] if the path contains a shell variable that does not exist in
+///   the environment.
+pub fn expand_path<P>(path: &P) -> Result<PathBuf>
+where
+    P: AsRef<str>,
+{
+    #[cfg(windows)]
+    let path = &expand_percent_vars(path.as_ref(), lookup_var)?;
+    match shellexpand::full_with_context(path, dirs_next::home_dir, lookup_var) {
+        Ok(expanded) => Ok(expanded.into_owned().into()),
+        // These variables are only missing if the home directory cannot be found.
+        Err(e)
+            if e.cause == VarError::NotPresent
+                && [
+                    "HOME",
+                    "XDG_CONFIG_HOME",
+                    "XDG_DATA_HOME",
+                    "XDG_STATE_HOME",
+                    "XDG_CACHE_HOME",
+                ]
+                .contains(&e.var_name.as_str()) =>
+        {
+            Err(Error::UnknownHomeDir(e.var_name))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns `path` with every `%VAR%` replaced by the value `var` looks up for `VAR`. A `%` that
+/// doesn't start a variable name ending in another `%` is kept as it is.
+///
+/// # Errors
+/// + [`shellexpand::LookupError`] if `var` fails to look up a variable, or finds no value for it.
+#[cfg(any(windows, test))]
+fn expand_percent_vars(
+    path: &str,
+    var: impl Fn(&str) -> Result<Option<String>, VarError>,
+) -> Result<String, shellexpand::LookupError<VarError>> {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let name = after.find('%').map(|end| &after[..end]).filter(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '(' || c == ')')
+        });
+        let Some(name) = name else {
+            expanded.push('%');
+            rest = after;
+            continue;
+        };
+        let value = var(name).and_then(|value| value.ok_or(VarError::NotPresent));
+        match value {
+            Ok(value) => expanded.push_str(&value),
+            Err(cause) => {
+                return Err(shellexpand::LookupError {
+                    var_name: name.to_owned(),
+                    cause,
+                })
+            }
+        }
+        rest = &after[name.len() + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Looks up an environment variable for [`expand_path`]. `$HOME` falls back to the home directory
+/// from the user database when it is unset or empty, and the XDG base directory variables fall back
+/// to their default locations under the home directory.
+fn lookup_var(name: &str) -> Result<Option<String>, VarError> {
+    lookup_var_with(name, |name| env::var(name), dirs_next::home_dir)
+}
+
+/// Looks up a variable as [`lookup_var`] does, with `var` reading variables from the environment
+/// and `home_dir` returning the home directory from the user database.
+fn lookup_var_with(
+    name: &str,
+    var: impl Fn(&str) -> Result<String, VarError>,
+    home_dir: impl FnOnce() -> Option<PathBuf>,
+) -> Result<Option<String>, VarError> {
+    let default = match name {
+        "HOME" => None,
+        "XDG_CONFIG_HOME" => Some(".config"),
+        "XDG_DATA_HOME" => Some(".local/share"),
+        "XDG_STATE_HOME" => Some(".local/state"),
+        "XDG_CACHE_HOME" => Some(".cache"),
+        _ => return var(name).map(Some),
+    };
+    match var(name) {
+        Ok(value) if !value.is_empty() => Ok(Some(value)),
+        Ok(_) | Err(VarError::NotPresent) => home_dir()
+            .map(|home| match default {
+                Some(default) => home.join(default),
+                None => home,
+            })
+            .map(|path| Some(path.to_string_lossy().into_owned()))
+            .ok_or(VarError::NotPresent),
+        Err(e) => Err(e),
+    }
+}
+
+/// A directory that stands in for `/` when resolving link paths, such as the mounted root
+/// filesystem of a container image that is being built.
+#[derive(Clone, Debug)]
+pub struct TargetRoot {
+    /// The directory that is treated as `/`.
+    pub dir: PathBuf,
+    /// The home directory of the user the links are for, as a path inside of the root, e.g.
+    /// `/home/me`.
+    pub home: PathBuf,
+}
+
+impl TargetRoot {
+    /// Returns `path` with `~` and all shell variables expanded, inside of [`TargetRoot::dir`].
+    ///
+    /// `~`, `$HOME` and the XDG base directory variables are taken from [`TargetRoot::home`] rather
+    /// than from the environment, since they name directories on this machine. Other variables are
+    /// taken from the environment. Relative paths are relative to the root, and `..` can't leave
+    /// it.
+    ///
+    /// # Errors
+    /// + [Error::ShellexpandLookupError] if the path contains a shell variable that does not exist
+    ///   in the environment.
+    pub fn expand<P>(&self, path: &P) -> Result<PathBuf>
+    where
+        P: AsRef<str>,
+    {
+        let lookup = |name: &str| -> Result<Option<String>, VarError> {
+            let dir = match name {
+                "HOME" => "",
+                "XDG_CONFIG_HOME" => ".config",
+                "XDG_DATA_HOME" => ".local/share",
+                "XDG_STATE_HOME" => ".local/state",
+                "XDG_CACHE_HOME" => ".cache",
+                _ => return env::var(name).map(Some),
+            };
+            Ok(Some(self.home.join(dir).to_string_lossy().into_owned()))
+        };
+        let expanded = shellexpand::full_with_context(path, || Some(&self.home), lookup)?;
+        let inside = normalize(&Path::new("/").join(expanded.as_ref()));
+        Ok(self.dir.join(inside.strip_prefix("/").unwrap_or(&inside)))
+    }
+}
+
+/// Removes `.` and `..` components from `path` without touching the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Parses a duration made up of a whole number and a unit, such as `30d`. The units are `s`
+/// (seconds), `m` (minutes), `h` (hours), `d` (days) and `w` (weeks).
+///
+/// The error is a message describing what is wrong with `s`, suitable for showing to the user.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("'{}' does not start with a number", s))?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "'{}' has an unknown unit. Use one of s, m, h, d or w, e.g. 30d",
+                s
+            ))
+        }
+    };
+    amount
+        .checked_mul(unit_seconds)
+        .and_then(|seconds| chrono::Duration::from_std(Duration::from_secs(seconds)).ok())
+        .ok_or_else(|| format!("'{}' is too long", s))
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("The dotfiles directory ({0}) does not exist.")]
+    MissingDotfilesDir(PathBuf),
+    #[error("The symlink list file ({0}) does not exist.")]
+    MissingSymlinkListFile(PathBuf),
+    #[error("The root directory ({0}) does not exist.")]
+    MissingRoot(PathBuf),
+    #[error("The symlink list file ({0}) already exists. Pass --force to overwrite it.")]
+    SymlinkListExists(PathBuf),
+    #[error("{0} does not exist, so it can't be added.")]
+    MissingFile(PathBuf),
+    #[error("{0} is a symlink, so it can't be added. Add the file it points to instead.")]
+    AddingSymlink(PathBuf),
+    #[error("The origin ({0}) already exists. Pass --as to add the file under another name.")]
+    OriginExists(PathBuf),
+    #[error("{0} is not a link in the symlink list.")]
+    NotLinked(PathBuf),
+    #[error("Can't add a link to {0}, because only YAML symlink lists can be added to.")]
+    NotYaml(PathBuf),
+    #[error(
+        "Can't remove {0}, because it isn't in a YAML symlink list. Only YAML can be removed from."
+    )]
+    NotInYaml(PathBuf),
+    #[error(
+        "The template {} uses the variable '{name}', which isn't set. Set it under vars: in the \
+         symlink list, or in the environment for env.<NAME>.",
+        .origin.display()
+    )]
+    UndefinedTemplateVariable { origin: PathBuf, name: String },
+    #[error("Failed to link {} -> {}: {cause}. Skipping...", .origin.display(), .link.display())]
+    LinkError {
+        origin: PathBuf,
+        link: PathBuf,
+        cause: LinkFailure,
+    },
+    #[error(
+        "The origin {0} is outside of the dotfiles directory. Pass --allow-external-origins if this \
+         is intentional."
+    )]
+    OriginOutsideDotfilesDir(PathBuf),
+    #[error(
+        "The link {0} is inside of the dotfiles directory, where it could replace your config \
+         files. Pass --allow-links-inside-dotfiles-dir if this is intentional."
+    )]
+    LinkInsideDotfilesDir(PathBuf),
+    #[error("The dotfiles directory ({0}) has uncommitted changes.")]
+    UncommittedChanges(PathBuf),
+    #[error("Git error: {0}")]
+    GitError(String),
+    #[error(
+        "Another instance of dotconfig is already running (lock file: {0}). Pass --no-lock to run \
+         anyway."
+    )]
+    AlreadyRunning(PathBuf),
+    #[error(
+        "Standard input is not a terminal, so the confirmation prompt cannot be answered. Pass \
+         --yes to proceed without confirmation."
+    )]
+    NotATerminal,
+    #[error("Permission denied: {0}. Skipping...")]
+    PermissionDenied(PathBuf),
+    #[error("There is no {0}.")]
+    UnknownOwner(String),
+    #[error("The reload command '{0}' exited unsuccessfully.")]
+    ReloadFailed(String),
+    #[error("The build command '{0}' failed, so its origin may be missing or out of date.")]
+    BuildFailed(String),
+    #[error("There is no backup of {0}.")]
+    NoBackup(PathBuf),
+    #[error(
+        "{0} already exists, so its backup can't be restored there. Move it out of the way first."
+    )]
+    RestoreBlocked(PathBuf),
+    #[error("The hook '{0}' failed.")]
+    HookFailed(String),
+    #[error(
+        "The reload command '{command}' was stopped after running for {} seconds.",
+        .timeout.as_secs_f64()
+    )]
+    ReloadTimedOut { command: String, timeout: Duration },
+    #[error(
+        "${0} cannot be expanded, because the home directory could not be determined. Set $HOME to \
+         your home directory."
+    )]
+    UnknownHomeDir(String),
+    #[error("{0} is not supported on this platform.")]
+    UnsupportedPlatform(&'static str),
+    #[error("IoError: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Unknown field `{field}` in {entry} of {file}. Expected {expected}.")]
+    UnknownField {
+        file: PathBuf,
+        entry: String,
+        field: String,
+        expected: String,
+    },
+    #[error(
+        "{} requires dotconfig {required} or newer, but this is dotconfig {}. Please upgrade.",
+        .file.display(),
+        env!("CARGO_PKG_VERSION")
+    )]
+    UnsupportedVersion { file: PathBuf, required: String },
+    #[error("Eror in YAML ({0})")]
+    YamlError(#[from] serde_yaml::Error),
+    #[error("Error in TOML ({0})")]
+    TomlError(#[from] toml::de::Error),
+    #[error("Error writing TOML ({0})")]
+    TomlSerializeError(#[from] toml::ser::Error),
+    #[error("Failed to watch for changes ({0})")]
+    WatchError(#[from] notify::Error),
+    #[error("Error in JSON ({0})")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Could not expand ${}: {}.", .0.var_name, .0.cause)]
+    ShellexpandLookupError(#[from] shellexpand::LookupError<VarError>),
+    #[error("In link for origin '{origin}': environment variable ${var} is not set.")]
+    UnsetVariable { var: String, origin: String },
+}
+
+impl Error {
+    /// Returns the message of the error for printing to a terminal, with the parts of an
+    /// [`Error::LinkError`] highlighted in color. Its [`Display`](std::fmt::Display) is the same
+    /// message without color.
+    pub fn painted(&self) -> String {
+        match self {
+            Error::LinkError {
+                origin,
+                link,
+                cause,
+            } => format!(
+                "{} {} -> {}: {}. {}",
+                Paint::red("Failed to link"),
+                origin.display(),
+                link.display(),
+                Paint::yellow(cause),
+                Paint::red("Skipping...")
+            ),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// Why a link could not be installed. See [`Error::LinkError`].
+#[derive(Error, Debug)]
+pub enum LinkFailure {
+    #[error("the origin does not exist")]
+    MissingOrigin,
+    #[error("the link is not a valid path")]
+    InvalidPath,
+    #[error("rename must be a filename, not a path like '{0}'")]
+    InvalidRename(String),
+    #[error(
+        "the path already includes a filename, so it can't be combined with rename. Add a trailing \
+         '/' if it is a directory"
+    )]
+    RenamedFilename,
+    #[error(
+        "the path is a directory, and the origin has no filename to name the link after. Add the \
+         link's filename, or set rename"
+    )]
+    UnnamedLink,
+    #[error("the origin and link point at the same location")]
+    SameLocation,
+    #[error("a file is already in the way")]
+    Conflict,
+    #[error("the parent directory does not exist")]
+    MissingParentDir,
+    #[error("{} is in the way of the parent directory, but is not a directory", .0.display())]
+    ParentNotADirectory(PathBuf),
+    #[error(
+        "it is inside of {}, which links to a directory in the dotfiles directory, so it would be \
+         created there. Pass --allow-links-inside-dotfiles-dir if this is intentional",
+        .0.display()
+    )]
+    InsideLinkedDirectory(PathBuf),
+    #[error(
+        "the origin is a glob, so each link is named after the file it matches, and rename can't \
+         be set"
+    )]
+    RenamedGlob,
+    #[error("the origin is a directory, which can't be hard linked")]
+    HardLinkedDirectory,
+    #[error(
+        "the origin is a directory, which can't be copied as a whole. Set mode: recurse to copy \
+         each file inside of it"
+    )]
+    CopiedDirectory,
+    #[error("the origin is named {} on disk, which differs in case", .0.display())]
+    OriginCaseMismatch(PathBuf),
+    #[error("the origin is a directory, which can't be decrypted")]
+    EncryptedDirectory,
+    #[error("the origin could not be decrypted ({0})")]
+    DecryptFailed(io::Error),
+    #[error("hard links can only be created on the same filesystem as their origin")]
+    CrossDevice,
+    #[error("the file in the way could not be backed up ({0})")]
+    BackupFailed(io::Error),
+    #[error("sudo exited unsuccessfully")]
+    SudoFailed,
+    #[error("its owner could not be changed ({0})")]
+    ChownFailed(io::Error),
+    #[error("verification failed, the link points to {}", .0.display())]
+    WrongTarget(PathBuf),
+    #[error("verification failed, the link is not a symlink")]
+    NotASymlink,
+    #[error("verification failed, the link is not a hard link to the origin")]
+    NotAHardLink,
+    #[error("verification failed, the file doesn't have the contents of the origin")]
+    NotCopied,
+    #[error("verification failed, the file doesn't hold the decrypted origin")]
+    NotDecrypted,
+    #[error("{0}")]
+    Io(io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_variables_are_expanded() {
+        let var =
+            |name: &str| Ok((name == "APPDATA").then(|| r"C:\Users\me\AppData\Roaming".to_owned()));
+
+        assert_eq!(
+            expand_percent_vars(r"%APPDATA%\nvim", var).unwrap(),
+            r"C:\Users\me\AppData\Roaming\nvim"
+        );
+        assert_eq!(expand_percent_vars("100% %", var).unwrap(), "100% %");
+        assert_eq!(
+            expand_percent_vars("%LOCALAPPDATA%", var)
+                .unwrap_err()
+                .var_name,
+            "LOCALAPPDATA"
+        );
+    }
+
+    #[test]
+    fn home_falls_back_to_the_user_database_when_unset() {
+        let home = || Some(PathBuf::from("/home/me"));
+        let unset = |_: &str| Err(VarError::NotPresent);
+        let empty = |_: &str| Ok(String::new());
+        let set = |_: &str| Ok("/home/other".to_owned());
+
+        assert_eq!(
+            lookup_var_with("HOME", unset, home),
+            Ok(Some("/home/me".to_owned()))
+        );
+        assert_eq!(
+            lookup_var_with("HOME", empty, home),
+            Ok(Some("/home/me".to_owned()))
+        );
+        assert_eq!(
+            lookup_var_with("HOME", set, home),
+            Ok(Some("/home/other".to_owned()))
+        );
+        assert_eq!(
+            lookup_var_with("XDG_STATE_HOME", unset, home),
+            Ok(Some("/home/me/.local/state".to_owned()))
+        );
+        assert_eq!(
+            lookup_var_with("HOME", unset, || None),
+            Err(VarError::NotPresent)
+        );
+        assert_eq!(
+            lookup_var_with("EDITOR", unset, home),
+            Err(VarError::NotPresent)
+        );
+    }
+
+    #[test]
+    fn link_errors_are_only_colored_when_painted() {
+        let error = link_error(
+            Path::new("/cfg/zshrc"),
+            Path::new("/home/me/.zshrc"),
+            LinkFailure::Conflict,
+        );
+        let message = error.to_string();
+        assert!(!message.contains('\x1b'));
+        assert!(message.starts_with("Failed to link /cfg/zshrc -> /home/me/.zshrc: "));
+        assert_eq!(log::strip_color(&error.painted()), message);
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("30d"), Ok(chrono::Duration::days(30)));
+        assert_eq!(parse_duration("12h"), Ok(chrono::Duration::hours(12)));
+        assert_eq!(parse_duration("2w"), Ok(chrono::Duration::weeks(2)));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("30y").is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! Prevents several instances of dotconfig from installing links at the same time.
+
+use crate::{Error, Result};
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    fs::{File, TryLockError},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+/// An exclusive lock held by this process. The lock is released when it is dropped, or when the
+/// process exits.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Take the lock for the current user, without waiting for it.
+///
+/// The lock file is created in `$XDG_RUNTIME_DIR`, or in the temporary directory if that is not
+/// set. Its name includes a hash of the home directory, so that users sharing a temporary directory
+/// don't lock each other out.
+///
+/// # Errors
+/// + [`Error::AlreadyRunning`] if another process holds the lock.
+/// + [`Error::IoError`] if the lock file cannot be created or locked.
+pub fn acquire() -> Result<Lock> {
+    let path = lock_path();
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Lock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(Error::AlreadyRunning(path)),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Returns the path of the lock file for the current user.
+fn lock_path() -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    dirs_next::home_dir().hash(&mut hasher);
+    dirs_next::runtime_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join(format!("dotconfig-{:016x}.lock", hasher.finish()))
+}
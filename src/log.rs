@@ -0,0 +1,266 @@
+//! Console output.
+//!
+//! Messages about a single link are collected in a [`Buffer`] and written all at once when the link
+//! is done, so that output about one link is never interrupted by output about another, and a
+//! message is never split across lines by an error that occurs halfway through an operation.
+//!
+//! Status lines can start with a symbol for what they are about (see [`set_symbols`]), so that
+//! they can be told apart without color.
+//!
+//! Messages can also be written to a report file (see [`set_report`]), without color and with the
+//! time each line was written.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{stderr, stdout, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+use yansi::Paint;
+
+/// How important a message is. Each level includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Something failed. Written to stderr.
+    Error,
+    /// Something looks wrong, but didn't fail. Written to stderr.
+    Warn,
+    /// What is being done. Written to stdout.
+    Info,
+    /// Details useful for figuring out why something is being done. Written to stdout.
+    Debug,
+}
+
+/// What a status line is about. See [`set_symbols`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// A link is already installed.
+    Skip,
+    /// A link is created.
+    Link,
+    /// A directory is created.
+    CreateDir,
+    /// A file is backed up.
+    Backup,
+    /// A file is deleted.
+    Remove,
+    /// A file in the way of a link is left alone.
+    Keep,
+}
+
+impl Status {
+    fn symbol(self) -> &'static str {
+        match self {
+            Status::Skip => "[=] ",
+            Status::Link => "[+] ",
+            Status::CreateDir => "[/] ",
+            Status::Backup => "[~] ",
+            Status::Remove => "[-] ",
+            Status::Keep => "[.] ",
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static SYMBOLS: AtomicBool = AtomicBool::new(false);
+static OUTPUT: Mutex<()> = Mutex::new(());
+static REPORT: Mutex<Option<File>> = Mutex::new(None);
+
+/// Only write messages at `level` or more important from now on.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns whether messages at `level` are being written.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Enable or disable colored output.
+pub fn set_color(enabled: bool) {
+    if enabled {
+        Paint::enable();
+    } else {
+        Paint::disable();
+    }
+}
+
+/// Start status lines with a symbol for what they are about from now on, e.g. `[+]` when a link is
+/// created, and errors and warnings with `[!]` and `[*]`.
+pub fn set_symbols(enabled: bool) {
+    SYMBOLS.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns the symbol and space that start a status line about `status`, or nothing if symbols are
+/// disabled.
+pub fn symbol(status: Status) -> &'static str {
+    if SYMBOLS.load(Ordering::Relaxed) {
+        status.symbol()
+    } else {
+        ""
+    }
+}
+
+/// Also write every message to `file` from now on, whatever its level, with colors removed and
+/// each line prefixed with the time it was written.
+pub fn set_report(file: File) {
+    *REPORT.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+}
+
+/// Returns whether messages are being written to a report file.
+fn reporting() -> bool {
+    REPORT.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Write a message to the report file only, if there is one.
+pub fn report(message: impl Display) {
+    let _guard = lock();
+    write_report(&message.to_string());
+}
+
+fn write_report(message: &str) {
+    let mut report = REPORT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = report.as_mut() {
+        let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        for line in strip_color(message).lines() {
+            writeln!(file, "[{}] {}", time, line).ok();
+        }
+    }
+}
+
+/// Returns `s` without the ANSI escape sequences that color it.
+pub(crate) fn strip_color(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip to the end of the sequence, e.g. "\x1b[1;31m".
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Take exclusive access to the console, so that nothing else is written until the guard is
+/// dropped.
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    OUTPUT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Write a single message immediately.
+pub fn log(level: Level, message: impl Display) {
+    let mut buffer = Buffer::new();
+    buffer.log(level, message);
+}
+
+pub fn error(message: impl Display) {
+    log(Level::Error, message);
+}
+
+pub fn warn(message: impl Display) {
+    log(Level::Warn, message);
+}
+
+pub fn info(message: impl Display) {
+    log(Level::Info, message);
+}
+
+pub fn debug(message: impl Display) {
+    log(Level::Debug, message);
+}
+
+/// Returns e.g. "1 link" or "2 links", for messages. Nouns ending in "y" are pluralized as "ies".
+pub fn count(n: usize, noun: &str) -> String {
+    match (n, noun.strip_suffix('y')) {
+        (1, _) => format!("1 {}", noun),
+        (n, Some(stem)) => format!("{} {}ies", n, stem),
+        (n, None) => format!("{} {}s", n, noun),
+    }
+}
+
+/// A group of messages that are written together when the buffer is flushed or dropped.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    lines: Vec<(Level, String)>,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn log(&mut self, level: Level, message: impl Display) {
+        if enabled(level) || reporting() {
+            let symbol = match level {
+                _ if !SYMBOLS.load(Ordering::Relaxed) => "",
+                Level::Error => "[!] ",
+                Level::Warn => "[*] ",
+                Level::Info | Level::Debug => "",
+            };
+            self.lines.push((level, format!("{}{}", symbol, message)));
+        }
+    }
+
+    pub fn error(&mut self, message: impl Display) {
+        self.log(Level::Error, message);
+    }
+
+    pub fn warn(&mut self, message: impl Display) {
+        self.log(Level::Warn, message);
+    }
+
+    pub fn info(&mut self, message: impl Display) {
+        self.log(Level::Info, message);
+    }
+
+    pub fn debug(&mut self, message: impl Display) {
+        self.log(Level::Debug, message);
+    }
+
+    /// Write all buffered messages, in order, without letting other output in between.
+    pub fn flush(&mut self) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let _guard = lock();
+        let (mut out, mut err) = (stdout().lock(), stderr().lock());
+        for (level, line) in self.lines.drain(..) {
+            write_report(&line);
+            if !enabled(level) {
+                continue;
+            }
+            match level {
+                Level::Error | Level::Warn => writeln!(err, "{}", line).ok(),
+                Level::Info | Level::Debug => writeln!(out, "{}", line).ok(),
+            };
+        }
+        out.flush().ok();
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_stripped() {
+        let colored = format!(
+            "{} {}",
+            Paint::red("Failed to link"),
+            Paint::yellow("zshrc").bold()
+        );
+        assert_eq!(strip_color(&colored), "Failed to link zshrc");
+        assert_eq!(strip_color("plain"), "plain");
+    }
+}
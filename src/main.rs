@@ -1,408 +1,1179 @@
-use clap::Parser;
-use serde::Deserialize;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use dotconfig::{
+    config::{ConfigFormat, OriginBase, Source, SymlinkList},
+    doctor, edit,
+    executor::Owner,
+    format::{self, SortKey},
+    git,
+    install::{self, Outcome, Output},
+    lock,
+    log::{self, Level},
+    manifest::Manifest,
+    planner::{LinkMode, OnConflict, PlanOptions},
+    root, status,
+    trash::Trash,
+    uninstall, Error, LinkFailure, Result, TargetRoot,
+};
 use std::{
-    ffi::{OsStr, OsString},
-    fs::{self, read_link, File},
-    io::{stdin, stdout, BufReader, Write},
-    os::unix,
+    env, fs,
+    io::{stdout, IsTerminal},
     path::{Path, PathBuf},
+    process::{self, ExitCode},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
-use thiserror::Error;
 use yansi::Paint;
 
-type Result<T, E = Error> = std::result::Result<T, E>;
-
 /// Symlinks configuration files from a central location to wherever they need to be on the system,
 /// so that those config files can be maintained under version control.
 #[derive(Parser, Debug)]
-#[clap(about, author, version)]
+#[clap(about, author, version, after_help = EXIT_CODES_HELP)]
 pub struct Cli {
-    /// Specify the directory that holds your config files
-    #[clap(short, long, default_value = "$HOME/.cfg")]
-    dir: String,
-    /// Specify the YAML file that lists your desired symlinks
+    /// Specify the directory that holds your config files, or the URL of a git repository to clone
+    /// it from [default: the nearest ancestor of the current directory that contains the symlink
+    /// list, or $HOME/.cfg]
+    #[clap(short, long)]
+    dir: Option<String>,
+    /// Specify the YAML, TOML or JSON file that lists your desired symlinks. May be given more
+    /// than once, in which case links in later files override links in earlier files with the
+    /// same path
     #[clap(short, long, default_value = "symlinks.yml")]
-    config: String,
+    config: Vec<String>,
+    /// Read the symlink lists in this format, whatever their extension [default: from the
+    /// extension, or yaml]
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    config_format: Option<ConfigFormat>,
+    /// Look for origins in this directory rather than the dotfiles directory, e.g. when the
+    /// symlink list is kept in a different repository from the files it links. Overrides
+    /// `origin_base` in the symlink list
+    #[clap(long, value_name = "DIR")]
+    origin_base: Option<PathBuf>,
+    /// What the origins in each symlink list are relative to
+    #[clap(long, value_enum, value_name = "BASE", default_value_t = OriginBase::DotfilesDir)]
+    origins_relative_to: OriginBase,
+    /// Install the links under this host in `hosts:` in the symlink list [default: the name of
+    /// this host]
+    #[clap(long, value_name = "NAME")]
+    host: Option<String>,
+    /// Leave out the links in a group. May be given more than once
+    #[clap(long, value_name = "GROUP")]
+    exclude_group: Vec<String>,
+    /// Only install the links with this tag, and those without tags. May be given more than once
+    #[clap(long, value_name = "TAG")]
+    only: Vec<String>,
+    /// Leave out the links with this tag. May be given more than once
+    #[clap(long, value_name = "TAG")]
+    exclude: Vec<String>,
+    /// Print the links as a single symlink list, after combining the symlink lists, applying
+    /// defaults and leaving out excluded groups and tags, then exit. It is written in the format
+    /// of the symlink lists
+    #[clap(long)]
+    print_effective_config: bool,
+    /// Print the resolved origin, link and install action of every link, then exit
+    #[clap(long)]
+    print_config: bool,
+    /// Print the order links will be installed in, and the links each one must be installed after,
+    /// then exit
+    #[clap(long)]
+    print_plan_graph: bool,
+    /// Print how a single link is resolved: its fields, its expanded path and origin, what is at
+    /// the path now, and which install action is chosen and why, then exit. Matches links by path
+    /// or origin, as written or once expanded
+    #[clap(long, value_name = "PATH-OR-ORIGIN")]
+    explain: Option<String>,
+    /// Print the dotfiles directory as a tree, with where each file is linked to, and every
+    /// directory links are created in, with where each link points to, then exit. Files that
+    /// aren't linked and origins that don't exist are marked
+    #[clap(long)]
+    print_origins_tree: bool,
+    /// Print the files in the dotfiles directory that no link refers to, then exit
+    #[clap(long)]
+    find_orphans: bool,
+    /// Leave a file or directory, relative to the directory the origins are in, out of
+    /// --find-orphans. May be given more than once
+    #[clap(long, value_name = "PATH", requires = "find-orphans")]
+    ignore_orphan: Vec<PathBuf>,
+    /// Only look this many directories deep into the dotfiles directory for --find-orphans and
+    /// --print-origins-tree [default: no limit]
+    #[clap(long, value_name = "N")]
+    max_depth: Option<usize>,
+    /// Cancel the installation if the confirmation prompt is answered with an empty line
+    ///
+    /// How the answer to the confirmation prompt is interpreted:
+    ///
+    ///     Answer        Default   --default-no
+    ///     (empty)       proceed   cancel
+    ///     y, yes        proceed   proceed
+    ///     (other)       cancel    cancel
+    #[clap(long, verbatim_doc_comment)]
+    default_no: bool,
+    /// Proceed without asking for confirmation. Required when stdin is not a terminal, e.g. when
+    /// dotconfig is run from a script. Takes precedence over --default-no
+    #[clap(short, long, alias = "no-confirm")]
+    yes: bool,
+    /// Allow origins that resolve to a path outside of the dotfiles directory
+    #[clap(long)]
+    allow_external_origins: bool,
+    /// Fail if an origin is named differently from the file on disk in case only, e.g. `Config`
+    /// for a file named `config`, rather than warn and link to the file on disk
+    #[clap(long)]
+    case_sensitive: bool,
+    /// Link to origins that are symlinks themselves, rather than to the files they point to
+    #[clap(long)]
+    no_follow_origin: bool,
+    /// Allow links to be created inside of the dotfiles directory
+    #[clap(long)]
+    allow_links_inside_dotfiles_dir: bool,
+    /// Offer to retry links that fail due to missing permissions with sudo, all at once. With
+    /// --yes, they are retried without asking
+    #[clap(long)]
+    allow_sudo: bool,
+    /// Warn if the dotfiles directory is not a git repository or has uncommitted changes
+    #[clap(long)]
+    check_git: bool,
+    /// Warn about origins that git ignores, and so aren't kept under version control
+    #[clap(long)]
+    check_git_ignored: bool,
+    /// Refuse to install if the dotfiles directory has uncommitted changes. Implies --check-git
+    #[clap(long)]
+    require_clean: bool,
+    /// Replace files that are identical to their origin without backing them up
+    #[clap(long)]
+    skip_identical: bool,
+    /// What to do with files that are in the way of links: back them up, delete them, leave them
+    /// and don't link, or install nothing. A link's `backup` setting takes precedence
+    #[clap(long, value_enum, value_name = "POLICY", default_value_t = OnConflict::Backup)]
+    on_conflict: OnConflict,
+    /// Back up files that are in the way of links by moving them into
+    /// $XDG_DATA_HOME/dotconfig/trash instead of the backup directory
+    #[clap(long, conflicts_with_all = &["backup-dir", "backup-in-place"])]
+    trash: bool,
+    /// Move files that are in the way of links into this directory, and record where they came
+    /// from in its index.json. Files on another filesystem are backed up in place
+    /// [default: $XDG_DATA_HOME/dotconfig/backups]
+    #[clap(long, value_name = "DIR")]
+    backup_dir: Option<PathBuf>,
+    /// Back up files that are in the way of links next to the link, as
+    /// `<filename>-backup-<date>`, instead of in the backup directory
+    #[clap(long, conflicts_with = "backup-dir")]
+    backup_in_place: bool,
+    /// Create symlinks, or hard links or copies for programs that don't follow symlinks. Hard
+    /// links only work for files on the same filesystem as the dotfiles directory. A link's
+    /// `strategy` takes precedence
+    #[clap(long, value_enum, default_value_t = LinkMode::Symlink)]
+    mode: LinkMode,
+    /// Don't prevent other instances of dotconfig from running at the same time
+    #[clap(long)]
+    no_lock: bool,
+    /// Skip links that were installed by an earlier run with --only-changed without checking them,
+    /// if neither they nor their origins have been modified since. Links are recorded in
+    /// .dotconfig-cache.json in the directory the origins are in
+    #[clap(long)]
+    only_changed: bool,
+    /// With --only-changed, check every link anyway, and record them in a new cache
+    #[clap(long, requires = "only-changed")]
+    no_cache: bool,
+    /// Record the symlinks that are installed in this file, so that uninstall and doctor can find
+    /// links that have since been removed from the symlink list
+    /// [default: $XDG_STATE_HOME/dotconfig/state.yml]
+    #[clap(long, value_name = "FILE")]
+    state: Option<PathBuf>,
+    /// Remove symlinks installed by an earlier run whose links have since been removed from the
+    /// symlink list, without asking. Otherwise they are only removed if you confirm
+    #[clap(long)]
+    prune: bool,
+    /// Read each symlink back after creating it, and report links that don't point to their origin
+    #[clap(long)]
+    verify: bool,
+    /// After installing, plan the installation again and fail if anything is left to do, e.g. to
+    /// check in CI that a second run would change nothing
+    #[clap(
+        long,
+        conflicts_with_all = &["print-config", "print-plan-graph", "emit-script", "watch"]
+    )]
+    assert_idempotent: bool,
+    /// After installing, print how long each of the slowest links took to plan and install
+    #[clap(long)]
+    timings: bool,
+    /// Mark links that took longer than this many milliseconds as slow in --timings
+    #[clap(long, value_name = "MS", default_value_t = 1000, requires = "timings")]
+    slow_threshold: u64,
+    /// Run the build command of every link, not only of those whose origin doesn't exist
+    #[clap(long)]
+    rebuild: bool,
+    /// Stop build and reload commands and hooks that are still running after this many seconds
+    /// [default: no limit]
+    #[clap(long, value_name = "SECS")]
+    command_timeout: Option<u64>,
+    /// Give created links and directories to this user and group, e.g. when installing into
+    /// another user's home as root
+    #[clap(long, value_name = "USER[:GROUP]")]
+    chown: Option<String>,
+    /// Create links inside of this directory as if it were /, e.g. the mounted root filesystem of
+    /// a container image. ~ and $HOME in link paths refer to --home inside of it
+    #[clap(long, value_name = "DIR")]
+    root: Option<PathBuf>,
+    /// The home directory that ~ and $HOME in link paths refer to with --root, as a path inside of
+    /// the root [default: the current user's home directory in the root's /etc/passwd, or $HOME]
+    #[clap(long, value_name = "DIR", requires = "root")]
+    home: Option<PathBuf>,
+    /// Don't list links that are already installed, unless --verbose is passed. They are still
+    /// counted in the summary
+    #[clap(long)]
+    quiet_skip: bool,
+    /// Write a JSON record of every change made to the filesystem to a file
+    #[clap(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+    /// Also write a timestamped, plain-text log of everything that happens to a file, including
+    /// messages hidden by --quiet
+    #[clap(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    /// Before installing, write every file that links will replace to a .tar.gz archive
+    #[clap(long, value_name = "FILE")]
+    archive: Option<PathBuf>,
+    /// Write a shell script that performs the installation to a file instead of installing
+    #[clap(long, value_name = "FILE")]
+    emit_script: Option<PathBuf>,
+    /// Show what would be installed, then exit without changing anything or asking for
+    /// confirmation. Build commands aren't run
+    #[clap(long, conflicts_with = "watch")]
+    dry_run: bool,
+    /// How --dry-run shows the plan
+    #[clap(long, value_enum, value_name = "FORMAT", default_value_t = PlanFormat::Text, requires = "dry-run")]
+    format: PlanFormat,
+    /// Keep running, and install again whenever the symlink list or the dotfiles directory
+    /// changes. Implies --skip-identical, and installs without asking for confirmation
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "print-config",
+            "print-plan-graph",
+            "print-origins-tree",
+            "print-effective-config",
+            "find-orphans",
+            "emit-script"
+        ]
+    )]
+    watch: bool,
+    /// Only print warnings and errors. Overrides $DOTCONFIG_LOG
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also print why each install action was chosen. Overrides $DOTCONFIG_LOG
+    #[clap(short, long)]
+    verbose: bool,
+    /// Start each status line with a symbol for what it is about, e.g. [+] for a new link, so that
+    /// it can be told apart without color
+    #[clap(long)]
+    symbols: bool,
+    /// When to use colored output [default: $DOTCONFIG_COLOR, or auto]
+    #[clap(long, value_enum, value_name = "WHEN")]
+    color: Option<ColorChoice>,
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<()> {
-    if cfg!(windows) {
-        return Err(Error::UnsupportedPlatform);
-    }
-    let cli = Cli::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create the dotfiles directory and a starter symlink list
+    Init {
+        /// Overwrite the symlink list if it already exists
+        #[clap(long)]
+        force: bool,
+        /// Also run `git init` in the dotfiles directory
+        #[clap(long)]
+        git: bool,
+    },
+    /// Move a file or directory into the dotfiles directory, add a link to it to the symlink list,
+    /// and link it back in its place
+    Add {
+        /// The file or directory to add
+        #[clap(value_name = "FILE")]
+        file: String,
+        /// Where to move it, relative to the directory the origins are in [default: its filename,
+        /// without a leading dot]
+        #[clap(long = "as", value_name = "ORIGIN")]
+        origin: Option<String>,
+    },
+    /// Replace a link with a copy of its origin, and remove it from the symlink list it is in
+    Remove {
+        /// The path of the link
+        #[clap(value_name = "FILE")]
+        file: String,
+    },
+    /// Check that dotconfig will work on this system and with this dotfiles directory
+    SelfCheck,
+    /// Sort the links in the symlink list, and put their fields in a consistent order
+    Fmt {
+        /// What to sort links by
+        #[clap(long, value_enum, value_name = "KEY", default_value_t = SortKey::Path)]
+        by: SortKey,
+        /// Don't write anything, but exit with an error if the symlink list isn't formatted
+        #[clap(long)]
+        check: bool,
+    },
+    /// Report whether each link in the symlink list is installed, missing, broken, linked
+    /// elsewhere or shadowed by a file, without changing anything
+    Status {
+        /// How to show the status of the links
+        #[clap(long, value_enum, value_name = "FORMAT", default_value_t = StatusFormat::Text)]
+        format: StatusFormat,
+    },
+    /// Remove the links in the symlink list that are symlinks into the dotfiles directory, and the
+    /// links installed earlier that have since been removed from it, after asking for
+    /// confirmation. Anything else at a link's path is left alone
+    Uninstall {
+        /// Move the most recent backup of each removed link back in its place
+        #[clap(long)]
+        restore: bool,
+    },
+    /// List, prune or restore the backups in the backup directory
+    Backup {
+        #[clap(subcommand)]
+        command: BackupCommand,
+    },
+    /// Look for dangling symlinks into the dotfiles directory, links whose origin doesn't exist,
+    /// links installed at the same path, links removed from the symlink list that are still
+    /// installed and old backups, and suggest how to fix them
+    Doctor {
+        /// Fix the problems that can be fixed: remove dangling symlinks, leftover links and old
+        /// backups, and remove links whose origin doesn't exist from the symlink list, after
+        /// asking for confirmation
+        #[clap(long)]
+        fix: bool,
+    },
+    /// Report links from a --manifest that have been removed, re-pointed or replaced since
+    Drift {
+        /// The manifest written by the installation to compare against
+        #[clap(value_name = "MANIFEST")]
+        manifest: PathBuf,
+    },
+    /// Print a completion script for a shell
+    #[clap(hide = true)]
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    /// Print a JSON Schema of the symlink list, for editors that validate YAML
+    #[clap(hide = true)]
+    Schema,
+}
 
-    // Get the paths of the dotfiles directory and the symlink list
-    let dotfiles_dir = PathBuf::from(shellexpand::full(&cli.dir)?.into_owned());
-    let symlink_list_rel_path = PathBuf::from(shellexpand::full(&cli.config)?.into_owned());
-    let symlink_list_full_path = dotfiles_dir.join(symlink_list_rel_path);
+#[derive(Subcommand, Debug)]
+enum BackupCommand {
+    /// List the backups, oldest first, with where each came from
+    List {
+        /// Only list the backups made within this long, e.g. 7d. The units are s, m, h, d and w
+        #[clap(long, value_name = "DURATION", value_parser = dotconfig::parse_duration)]
+        since: Option<chrono::Duration>,
+    },
+    /// Delete all but the most recent backups of each file
+    Prune {
+        /// How many backups of each file to keep [default: 3, or 0 with --older-than]
+        #[clap(long, value_name = "N")]
+        keep: Option<usize>,
+        /// Only delete the backups made longer ago than this, e.g. 30d. The units are s, m, h, d
+        /// and w
+        #[clap(long, value_name = "DURATION", value_parser = dotconfig::parse_duration)]
+        older_than: Option<chrono::Duration>,
+    },
+    /// Move the most recent backup of a file back in its place
+    Restore {
+        /// The path the file was backed up from
+        #[clap(value_name = "FILE")]
+        path: String,
+    },
+}
 
-    if !dotfiles_dir.exists() {
-        return Err(Error::MissingDotfilesDir(dotfiles_dir));
-    }
-    if !symlink_list_full_path.exists() {
-        return Err(Error::MissingSymlinkListFile(symlink_list_full_path));
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PlanFormat {
+    /// The list that is shown before asking for confirmation
+    Text,
+    /// A JSON array with the link, origin, install action, mode, group, description, reload
+    /// command and writability of each link
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StatusFormat {
+    /// A line for each link, followed by how many links aren't installed
+    Text,
+    /// A JSON array with the link, origin, health, description and symlink target of each link
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    /// Use color if stdout is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+const EXIT_FAILURE: u8 = 1;
+const EXIT_INVALID_CONFIG: u8 = 2;
+const EXIT_MISSING_DOTFILES_DIR: u8 = 3;
+const EXIT_LINK_FAILED: u8 = 4;
+const EXIT_CANCELLED: u8 = 5;
+const EXIT_GIT_CHECK_FAILED: u8 = 6;
+const EXIT_SELF_CHECK_FAILED: u8 = 7;
+const EXIT_ALREADY_RUNNING: u8 = 8;
+const EXIT_NOT_IDEMPOTENT: u8 = 9;
+const EXIT_NOT_FORMATTED: u8 = 10;
+const EXIT_DRIFT: u8 = 11;
+const EXIT_OUT_OF_SYNC: u8 = 12;
+const EXIT_PROBLEMS_FOUND: u8 = 13;
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Whether links are being installed, in which case Ctrl-C waits for the current link to finish.
+static INSTALLING: AtomicBool = AtomicBool::new(false);
+/// Whether Ctrl-C was pressed while links were being installed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    An unexpected error occurred, or there is no backup to restore (backup restore)
+    2    The symlink list is missing or invalid, or the command line arguments are invalid
+    3    The dotfiles directory does not exist
+    4    One or more links could not be installed or removed, or a reload command or hook failed
+    5    The installation was cancelled at the confirmation prompt, or stdin is not a terminal
+         and --yes was not given
+    6    The dotfiles directory failed the git checks (--require-clean), or could not be cloned
+    7    One or more checks failed (self-check)
+    8    Another instance of dotconfig is already running
+    9    Some links would still be changed by another installation (--assert-idempotent)
+    10   A symlink list is not formatted (fmt --check)
+    11   Links have changed since the manifest was written (drift)
+    12   Some links are not installed (status)
+    13   Problems were found and not fixed (doctor)
+    130  The installation was interrupted with Ctrl-C";
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let color = cli.color.or_else(env_color).unwrap_or(ColorChoice::Auto);
+    log::set_color(match color {
+        ColorChoice::Auto => stdout().is_terminal(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    });
+    log::set_symbols(cli.symbols);
+    log::set_max_level(if cli.quiet {
+        Level::Warn
+    } else if cli.verbose {
+        Level::Debug
+    } else {
+        env_log_level().unwrap_or(Level::Info)
+    });
+    if let Some(path) = &cli.report {
+        match fs::File::create(path) {
+            Ok(file) => log::set_report(file),
+            Err(e) => {
+                log::error(format!(
+                    "Failed to create the report {}: {}",
+                    path.display(),
+                    e
+                ));
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+        log::report(format!(
+            "dotconfig {}: {}",
+            env!("CARGO_PKG_VERSION"),
+            env::args().collect::<Vec<_>>().join(" ")
+        ));
     }
-    let reader = BufReader::new(File::open(symlink_list_full_path)?);
-    let symlink_list: SymlinkList = serde_yaml::from_reader(reader)?;
-
-    let symlink_list: Vec<(PathBuf, PathBuf)> = symlink_list
-        .links
-        .into_iter()
-        .map(|Link { origin, path }| {
-            let origin = dotfiles_dir.join(origin);
-            let origin = canonicalize_origin(&origin)?;
-            let path = expand_link_file(&path)?;
-            Ok((origin, path))
-        })
-        .collect::<Result<_, Error>>()?;
-    let symlink_list: Vec<(PathBuf, PathBuf, InstallAction)> = symlink_list
-        .into_iter()
-        .map(|(origin, path)| {
-            let action = choose_install_action(&origin, &path)?;
-            Ok((origin, path, action))
-        })
-        .collect::<Result<_, Error>>()?;
-
-    // Display a list of files that will be symlinked
-    for (origin, link, action) in &symlink_list {
-        match action {
-            InstallAction::Link | InstallAction::CreateDirAndLink => println!(
-                "{} {} {} {}",
-                Paint::yellow("Will link:           "),
-                link.display(),
-                Paint::yellow("->"),
-                origin.display()
-            ),
-            InstallAction::BackupAndLink => println!(
-                "{} {} {} {}",
-                Paint::yellow("Will backup and link:"),
-                link.display(),
-                Paint::yellow("->"),
-                origin.display()
-            ),
-            InstallAction::Skip => println!(
-                "{} {} {} {}",
-                Paint::green("Already linked:      "),
-                link.display(),
-                Paint::green("->"),
-                origin.display(),
-            ),
+    // Ctrl-C exits at once, except while links are being installed: then the link being installed
+    // is finished first, so that a file that was backed up is also replaced by its link. Pressing
+    // it again exits at once anyway.
+    let handler = ctrlc::set_handler(|| {
+        if !INSTALLING.load(Ordering::SeqCst) || INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(EXIT_INTERRUPTED.into());
         }
+    });
+    if let Err(e) = handler {
+        log::debug(format!("Failed to handle Ctrl-C: {}", e));
     }
+    let code = match run(cli) {
+        Ok(code) => code,
+        Err(e) => {
+            log::error(e.painted());
+            exit_code(&e)
+        }
+    };
+    log::report(format!("Exited with code {}", code));
+    ExitCode::from(code)
+}
 
-    if symlink_list.iter().all(|(_, _, a)| match a {
-        InstallAction::Skip => true,
-        _ => false,
-    }) {
-        // All actions are `Skip`.
-        println!("{}", Paint::green("No action needed."));
-        return Ok(());
+/// Returns the log level set by `$DOTCONFIG_LOG`, if it is set to one of `error`, `warn`, `info`
+/// or `debug`.
+fn env_log_level() -> Option<Level> {
+    let value = env::var("DOTCONFIG_LOG").ok()?;
+    match value.to_lowercase().as_str() {
+        "error" => Some(Level::Error),
+        "warn" => Some(Level::Warn),
+        "info" => Some(Level::Info),
+        "debug" => Some(Level::Debug),
+        "" => None,
+        _ => {
+            log::warn(format!(
+                "{} {}",
+                Paint::yellow("Warning: ignoring invalid value of DOTCONFIG_LOG:"),
+                value
+            ));
+            None
+        }
     }
+}
 
-    // Ask for permission to proceed
-    print!("Proceed with installation? [Y/n] ");
-    stdout().flush().ok();
-    let mut s = String::new();
-    stdin().read_line(&mut s)?;
-    let s = s.trim().to_lowercase();
-    if s != "" && s != "y" && s != "yes" {
-        println!("Installation cancelled.");
-        return Ok(());
+/// Returns the color choice set by `$DOTCONFIG_COLOR`, if it is set to one of `auto`, `always` or
+/// `never`.
+fn env_color() -> Option<ColorChoice> {
+    let value = env::var("DOTCONFIG_COLOR").ok()?;
+    if value.is_empty() {
+        return None;
     }
-
-    // Symlink each file listed in config.links
-    for (origin, link, _) in symlink_list {
-        if let Err(e) = symlink(&origin, &link) {
-            println!("{}", e);
+    match ColorChoice::from_str(&value, true) {
+        Ok(color) => Some(color),
+        Err(_) => {
+            log::warn(format!(
+                "{} {}",
+                Paint::yellow("Warning: ignoring invalid value of DOTCONFIG_COLOR:"),
+                value
+            ));
+            None
         }
     }
-    Ok(())
 }
 
-enum InstallAction {
-    Skip,
-    BackupAndLink,
-    CreateDirAndLink,
-    Link,
+/// Returns the exit code that the process should exit with after failing with `error`.
+fn exit_code(error: &Error) -> u8 {
+    match error {
+        Error::MissingSymlinkListFile(_)
+        | Error::YamlError(_)
+        | Error::TomlError(_)
+        | Error::JsonError(_)
+        | Error::UnknownField { .. }
+        | Error::UnsupportedVersion { .. }
+        | Error::ShellexpandLookupError(_)
+        | Error::UnsetVariable { .. }
+        | Error::UndefinedTemplateVariable { .. }
+        | Error::OriginOutsideDotfilesDir(_)
+        | Error::LinkInsideDotfilesDir(_)
+        | Error::UnknownOwner(_) => EXIT_INVALID_CONFIG,
+        // Links that the symlink list describes wrongly are a config error, but links that fail
+        // because of what is on disk are not
+        Error::LinkError { cause, .. } => match cause {
+            LinkFailure::MissingOrigin
+            | LinkFailure::InvalidPath
+            | LinkFailure::InvalidRename(_)
+            | LinkFailure::RenamedFilename
+            | LinkFailure::UnnamedLink
+            | LinkFailure::SameLocation
+            | LinkFailure::InsideLinkedDirectory(_)
+            | LinkFailure::RenamedGlob
+            | LinkFailure::HardLinkedDirectory
+            | LinkFailure::CopiedDirectory
+            | LinkFailure::OriginCaseMismatch(_)
+            | LinkFailure::EncryptedDirectory => EXIT_INVALID_CONFIG,
+            LinkFailure::Conflict
+            | LinkFailure::MissingParentDir
+            | LinkFailure::ParentNotADirectory(_)
+            | LinkFailure::DecryptFailed(_)
+            | LinkFailure::CrossDevice
+            | LinkFailure::BackupFailed(_)
+            | LinkFailure::SudoFailed
+            | LinkFailure::ChownFailed(_)
+            | LinkFailure::WrongTarget(_)
+            | LinkFailure::NotASymlink
+            | LinkFailure::NotAHardLink
+            | LinkFailure::NotCopied
+            | LinkFailure::NotDecrypted
+            | LinkFailure::Io(_) => EXIT_LINK_FAILED,
+        },
+        Error::MissingDotfilesDir(_) => EXIT_MISSING_DOTFILES_DIR,
+        Error::PermissionDenied(_) => EXIT_LINK_FAILED,
+        Error::UncommittedChanges(_) | Error::GitError(_) => EXIT_GIT_CHECK_FAILED,
+        Error::AlreadyRunning(_) => EXIT_ALREADY_RUNNING,
+        Error::NotATerminal => EXIT_CANCELLED,
+        Error::UnknownHomeDir(_)
+        | Error::MissingRoot(_)
+        | Error::SymlinkListExists(_)
+        | Error::MissingFile(_)
+        | Error::AddingSymlink(_)
+        | Error::OriginExists(_)
+        | Error::NotLinked(_)
+        | Error::NotYaml(_)
+        | Error::NotInYaml(_)
+        | Error::UnsupportedPlatform(_)
+        | Error::IoError(_)
+        | Error::TomlSerializeError(_)
+        | Error::WatchError(_)
+        | Error::ReloadFailed(_)
+        | Error::BuildFailed(_)
+        | Error::HookFailed(_)
+        | Error::NoBackup(_)
+        | Error::RestoreBlocked(_)
+        | Error::ReloadTimedOut { .. } => EXIT_FAILURE,
+    }
 }
 
-/// Choose an install action for a pending link.
+/// Returns the root directory to install into for `--root`, with the home directory from
+/// `--home` (see [`root::target_root`]).
 ///
-/// If the parent directory of `link` does not exist, return `BackupAndLink`.
-/// If `link` exists and is already a symlink to `origin`, return `Skip`.
-/// If `link` exists, but is not a symlink to `origin`, return `BackupAndLink`.
-/// If `link` does not exist but its parent directory does, return `Link`.
+/// # Errors
+/// + Any error from [`root::target_root`].
+fn target_root(cli: &Cli) -> Result<Option<TargetRoot>> {
+    cli.root
+        .as_deref()
+        .map(|dir| root::target_root(dir, cli.home.as_deref()))
+        .transpose()
+}
+
+/// Returns the directory to move files that are in the way of links into: the trash with
+/// `--trash`, none with `--backup-in-place`, or else `--backup-dir` or the default backup
+/// directory.
 ///
-/// # Params
-/// + `origin` - The fully canonicalizd path to the file that will be installed at `link`.
-/// + `link` - The path that `origin` is to be installed at. Shell variables and special symbols
-/// (e.g. `~`) will not be resolved.
-fn choose_install_action(origin: &PathBuf, link: &PathBuf) -> Result<InstallAction> {
-    let link_parent = link_parent(&link)?;
-
-    if !link_parent.exists() {
-        // The file's parent directory does not exist.
-        Ok(InstallAction::CreateDirAndLink)
-    } else if link.exists() {
-        if let Ok(existing_link_origin) = read_link(&link) {
-            // The file exists, and is a symlink.
-            if *origin == fs::canonicalize(&existing_link_origin)? {
-                // The file is already linked to origin.
-                Ok(InstallAction::Skip)
-            } else {
-                // The file is linked to something other than origin.
-                Ok(InstallAction::BackupAndLink)
-            }
-        } else {
-            // The file exists but is not a symlink.
-            Ok(InstallAction::BackupAndLink)
+/// # Errors
+/// + [`Error::UnknownHomeDir`] if `--backup-dir` isn't given, and the data directory cannot be
+///   determined.
+fn backup_dir(cli: &Cli) -> Result<Option<PathBuf>> {
+    Ok(if cli.backup_in_place {
+        None
+    } else if cli.trash {
+        Some(Trash::new()?.dir)
+    } else if let Some(dir) = &cli.backup_dir {
+        Some(dotconfig::normalize(&env::current_dir()?.join(dir)))
+    } else {
+        Some(Trash::backups()?.dir)
+    })
+}
+
+/// Run the program, returning the exit code to exit with.
+fn run(cli: Cli) -> Result<u8> {
+    match cli.command {
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), "dotconfig", &mut stdout());
+            return Ok(0);
         }
+        Some(Command::Schema) => {
+            let schema = schemars::schema_for!(SymlinkList);
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(0);
+        }
+        Some(Command::Init { force, git }) => return init(&cli, force, git),
+        Some(Command::SelfCheck) => return Ok(self_check(&cli)),
+        Some(Command::Add {
+            ref file,
+            ref origin,
+        }) => return add(&cli, file, origin.as_deref()),
+        Some(Command::Remove { ref file }) => return remove(&cli, file),
+        Some(Command::Fmt { by, check }) => return fmt(&cli, by, check),
+        Some(Command::Drift { ref manifest }) => return drift(manifest),
+        Some(Command::Status { format }) => return status(&cli, format),
+        Some(Command::Doctor { fix }) => return doctor(&cli, fix),
+        Some(Command::Backup { ref command }) => return backup(&cli, command),
+        Some(Command::Uninstall { restore }) => return uninstall(&cli, restore),
+        None => {}
+    }
+
+    let output = output(&cli);
+    // Printing the config or plan and writing a script don't change anything, so they don't need
+    // the lock
+    let _lock = if output.is_read_only() {
+        None
     } else {
-        // The file does not exist, but its parent directory does.
-        Ok(InstallAction::Link)
+        acquire_lock(&cli)?
+    };
+
+    let (dotfiles_dir, symlink_list_paths) = resolve_paths(&cli)?;
+    log::report(format!("Dotfiles directory: {}", dotfiles_dir.display()));
+    for path in &symlink_list_paths {
+        log::report(format!("Symlink list: {}", path.display()));
     }
+
+    if !dotfiles_dir.exists() {
+        return Err(Error::MissingDotfilesDir(dotfiles_dir));
+    }
+    if cli.check_git || cli.require_clean {
+        install::check_git(&dotfiles_dir, cli.require_clean)?;
+    }
+    let options = install::Options {
+        dotfiles_dir,
+        symlink_list_paths,
+        config_format: cli.config_format,
+        origins_relative_to: cli.origins_relative_to,
+        origin_base: cli.origin_base.clone(),
+        host: host(&cli),
+        exclude_groups: cli.exclude_group.clone(),
+        only: cli.only.clone(),
+        exclude: cli.exclude.clone(),
+        output,
+        ignore_orphans: cli.ignore_orphan.clone(),
+        max_depth: cli.max_depth,
+        plan: PlanOptions {
+            allow_external_origins: cli.allow_external_origins,
+            skip_identical: cli.skip_identical,
+            backup_dir: backup_dir(&cli)?,
+            mode: cli.mode,
+            on_conflict: cli.on_conflict,
+            no_follow_origin: cli.no_follow_origin,
+            allow_links_inside_dotfiles_dir: cli.allow_links_inside_dotfiles_dir,
+            case_sensitive: cli.case_sensitive,
+            root: target_root(&cli)?,
+            cache: None,
+        },
+        only_changed: cli.only_changed,
+        no_cache: cli.no_cache,
+        yes: cli.yes,
+        default_no: cli.default_no,
+        allow_sudo: cli.allow_sudo,
+        check_git_ignored: cli.check_git_ignored,
+        rebuild: cli.rebuild,
+        command_timeout: cli.command_timeout.map(Duration::from_secs),
+        owner: cli.chown.as_deref().map(Owner::parse).transpose()?,
+        verify: cli.verify,
+        quiet_skip: cli.quiet_skip,
+        state: cli.state.clone(),
+        prune: cli.prune,
+        manifest: cli.manifest.clone(),
+        archive: cli.archive.clone(),
+        assert_idempotent: cli.assert_idempotent,
+        timings: cli.timings,
+        slow_threshold: Duration::from_millis(cli.slow_threshold),
+        installing: Some(&INSTALLING),
+        interrupt: Some(&INTERRUPTED),
+    };
+    let outcome = if cli.watch {
+        install::watch(&options)?
+    } else {
+        install::install(&options)?
+    };
+    Ok(outcome_code(outcome))
 }
 
-/// Create a symlink from `link` to `origin`. If `origin` already exists, back it up (rename it to
-/// `<filename>-backup-<date>`) first. If the symlink already exists, do nothing. If either `link`
-/// or `origin` are invalid paths, do nothing.
-///
-/// # Params
-/// + `link` - The path where the symlink will be created.
-/// + `origin` - The path that the symlink will point to. Relative to `dotfiles_dir`.
-/// + `dotfiles_dir` - The dotfiles directory that contains `origin`.
+/// Returns the exit code that the process should exit with after an installation ends with
+/// `outcome`.
+fn outcome_code(outcome: Outcome) -> u8 {
+    match outcome {
+        Outcome::Done => 0,
+        Outcome::NotFound => EXIT_FAILURE,
+        Outcome::Failed => EXIT_LINK_FAILED,
+        Outcome::Cancelled => EXIT_CANCELLED,
+        Outcome::Interrupted => EXIT_INTERRUPTED,
+        Outcome::NotIdempotent => EXIT_NOT_IDEMPOTENT,
+    }
+}
+
+/// Returns what to do instead of installing, from the first of `--print-effective-config`,
+/// `--print-origins-tree`, `--find-orphans`, `--explain`, `--print-config`, `--print-plan-graph`,
+/// `--emit-script` and `--dry-run` that is given.
+fn output(cli: &Cli) -> Output {
+    if cli.print_effective_config {
+        Output::PrintEffectiveConfig
+    } else if cli.print_origins_tree {
+        Output::PrintOriginsTree
+    } else if cli.find_orphans {
+        Output::FindOrphans
+    } else if let Some(query) = &cli.explain {
+        Output::Explain(query.clone())
+    } else if cli.print_config {
+        Output::PrintConfig
+    } else if cli.print_plan_graph {
+        Output::PrintPlanGraph
+    } else if cli.format == PlanFormat::Json {
+        Output::DryRunJson
+    } else if let Some(path) = &cli.emit_script {
+        Output::EmitScript(path.clone())
+    } else if cli.dry_run {
+        Output::DryRun
+    } else {
+        Output::Install
+    }
+}
+
+/// Returns the path of the dotfiles directory and the full paths of the symlink lists. If `--dir`
+/// is the URL of a git repository, it is cloned or updated first. Without `--dir`, the dotfiles
+/// directory is found by looking for the first symlink list.
 ///
 /// # Errors
-/// + [`Error::LinkError`]
-///     + If the path `link` does not exist. Either:
-///         + the parent directory does not exist, or
-///         + the path is invalid in some other way, such as not being relative to root (`/`).
-///     + If the symlink failed for some other reason (probably a bug).
-///     + If `origin` does not exist as a path within the `dotfiles_dir` directory.
-fn symlink(origin: &PathBuf, link: &PathBuf) -> Result<()> {
-    let link_filename = link_filename(&link)?;
-    let link_parent = link_parent(&link)?;
-
-    let action = choose_install_action(&origin, &link)?;
-
-    match action {
-        InstallAction::CreateDirAndLink => {
-            println!(
-                "{} {} {}",
-                Paint::yellow("The directory"),
-                link_parent.display(),
-                Paint::yellow("does not exist. Creating...")
-            );
-            fs::create_dir_all(&link_parent)?;
-        }
-        InstallAction::BackupAndLink => {
-            let link_parent = canonicalize_link_parent(&link_parent, &link_filename)?;
-            backup(&link_parent, &link_filename)?;
-        }
-        InstallAction::Skip => {
-            println!(
-                "{} '{}' {} '{}'{}",
-                Paint::green("Skipping"),
-                origin.display(),
-                Paint::green("->"),
-                link.display(),
-                Paint::green(". File already linked.")
-            );
-            return Ok(());
-        }
-        InstallAction::Link => {}
-    }
+/// + [`Error::ShellexpandLookupError`] if any path references an unset shell variable.
+/// + [`Error::GitError`] if the dotfiles directory cannot be cloned or updated.
+fn resolve_paths(cli: &Cli) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let symlink_list_rel_paths = cli
+        .config
+        .iter()
+        .map(dotconfig::expand_path)
+        .collect::<Result<Vec<_>>>()?;
+    let dotfiles_dir = match &cli.dir {
+        Some(url) if git::is_url(url) => git::clone_or_pull(url)?,
+        Some(dir) => dotconfig::expand_path(dir)?,
+        None => match env::current_dir()
+            .ok()
+            .and_then(|cwd| dotconfig::config::find_dotfiles_dir(&cwd, &symlink_list_rel_paths[0]))
+        {
+            Some(dir) => dir,
+            None => dotconfig::expand_path(&"$HOME/.cfg")?,
+        },
+    };
+    let symlink_list_full_paths = symlink_list_rel_paths
+        .iter()
+        .map(|path| dotfiles_dir.join(path))
+        .collect();
+    Ok((dotfiles_dir, symlink_list_full_paths))
+}
+
+/// Returns the host whose links apply: `--host`, or the name of this host. If neither is known,
+/// only the links for every host apply.
+fn host(cli: &Cli) -> String {
+    cli.host
+        .clone()
+        .or_else(dotconfig::hostname)
+        .unwrap_or_default()
+}
 
-    print!(
-        "{} '{}' {} '{}'...",
-        Paint::yellow("Linking"),
-        link.display(),
-        Paint::yellow("->"),
-        origin.display()
-    );
-    unix::fs::symlink(&origin, &link)
-        .map(|_| println!("{}", Paint::green("done.")))
-        .map_err(|e| {
-            Error::LinkError(format!(
-                "\n{} {} -> {}. {}. {}",
-                Paint::red("Failed to link"),
-                origin.display(),
-                link.display(),
-                Paint::yellow(e),
-                Paint::red("Skipping...")
-            ))
-        })
+/// Returns where the commands that don't install read the symlink lists from, with the links for
+/// the host from [`host`].
+///
+/// # Errors
+/// + Any error from [`resolve_paths`].
+fn source(cli: &Cli) -> Result<Source> {
+    let (dotfiles_dir, symlink_list_paths) = resolve_paths(cli)?;
+    Ok(Source {
+        dotfiles_dir,
+        symlink_list_paths,
+        config_format: cli.config_format,
+        origins_relative_to: cli.origins_relative_to,
+        origin_base: cli.origin_base.clone(),
+        host: Some(host(cli)),
+    })
 }
 
-/// Returns the path to the symlink with all shell variables expanded.
+/// Returns `path` with `~` and shell variables expanded, relative to the current directory.
 ///
-/// # Params
-/// + `link` - The path to the link file.
+/// # Errors
+/// + [`Error::ShellexpandLookupError`] if `path` references an unset shell variable.
+fn absolute(path: &str) -> Result<PathBuf> {
+    Ok(env::current_dir()?.join(dotconfig::expand_path(&path)?))
+}
+
+/// Returns the lock that commands which change anything hold, unless `--no-lock` is given.
 ///
 /// # Errors
-/// + [Error::ShellexpandLookupError] if the path contains a shell variable that does not exist in
-/// the environment.
-fn expand_link_file<P>(link: &P) -> Result<PathBuf>
-where
-    P: AsRef<str>,
-{
-    Ok(shellexpand::full(&link)?.into_owned().into())
+/// + [`Error::AlreadyRunning`] if another instance holds the lock.
+fn acquire_lock(cli: &Cli) -> Result<Option<lock::Lock>> {
+    Ok(if cli.no_lock {
+        None
+    } else {
+        Some(lock::acquire()?)
+    })
 }
 
-/// Returns the path to the folder the symlink will go in.
+/// Create the dotfiles directory if it doesn't exist, and write a starter symlink list to it (see
+/// [`edit::init`]).
 ///
-/// # Params
-/// + `link` - The path to the symlink.
+/// Unlike an installation, `init` doesn't search for an existing dotfiles directory: it uses
+/// `--dir`, or `$HOME/.cfg`. If `--config` is given more than once, only the first symlink list is
+/// written.
 ///
 /// # Errors
-/// + [Error::LinkError] if `link` does not have a valid parent directory.
-fn link_parent<P>(link: &P) -> Result<PathBuf>
-where
-    P: AsRef<Path>,
-{
-    Ok(link
-        .as_ref()
-        .parent()
-        .ok_or(Error::LinkError(format!(
-            "{} '{}' {}",
-            Paint::red("Invalid path {}",),
-            link.as_ref().display(),
-            Paint::red("Skipping...")
-        )))?
-        .into())
+/// + Any error from [`edit::init`].
+fn init(cli: &Cli, force: bool, git: bool) -> Result<u8> {
+    let dotfiles_dir = dotconfig::expand_path(&cli.dir.as_deref().unwrap_or("$HOME/.cfg"))?;
+    let symlink_list_path = dotfiles_dir.join(dotconfig::expand_path(&cli.config[0])?);
+    edit::init(&dotfiles_dir, &symlink_list_path, force, git)?;
+    Ok(0)
 }
 
-/// Returns the symlink's filename.
+/// Format each symlink list (see [`format::format_files`]).
 ///
-/// # Params
-/// + `link` - The path to the symlink.
+/// Returns the exit code to exit with: [`EXIT_FAILURE`] if a symlink list isn't YAML,
+/// [`EXIT_NOT_FORMATTED`] if `check` is set and a symlink list isn't formatted, or 0.
 ///
 /// # Errors
-/// + [Error::LinkError] if `link` is not a valid path.
-fn link_filename<P>(link: &P) -> Result<OsString>
-where
-    P: AsRef<Path>,
-{
-    Ok(link
-        .as_ref()
-        .file_name()
-        .ok_or(Error::LinkError(format!(
-            "{} '{}'. {}",
-            Paint::red("Invalid path"),
-            link.as_ref().display(),
-            Paint::red("Skipping...")
-        )))?
-        .to_owned()
-        .into())
+/// + Any error from [`format::format_files`].
+fn fmt(cli: &Cli, sort_by: SortKey, check: bool) -> Result<u8> {
+    let unformatted = format::format_files(&source(cli)?, sort_by, check)?;
+    Ok(if !unformatted.not_yaml.is_empty() {
+        EXIT_FAILURE
+    } else if !unformatted.unformatted.is_empty() {
+        EXIT_NOT_FORMATTED
+    } else {
+        0
+    })
 }
 
-/// Returns the symlink's parent directory in canonical, absolute form with all intermediate
-/// components normalized and symbolic links resolved. See [`fs::canonicalize`].
+/// Report the [`Health`] of every link in the symlink list, in `format`. Returns
+/// [`EXIT_OUT_OF_SYNC`] if any link isn't installed.
 ///
-/// # Params
-/// + `link_parent` - The path to the symlink's parent directory.
-/// + `link_filename` - The symlink's filename.
+/// # Errors
+/// + Any error from [`status::check`].
+fn status(cli: &Cli, format: StatusFormat) -> Result<u8> {
+    let statuses = status::check(&source(cli)?, target_root(cli)?.as_ref(), cli.mode)?;
+    match format {
+        StatusFormat::Text => status::report(&statuses, cli.quiet_skip),
+        StatusFormat::Json => println!("{}", status::json(&statuses)?),
+    }
+    if statuses.iter().all(|status| status.health.is_linked()) {
+        Ok(0)
+    } else {
+        Ok(EXIT_OUT_OF_SYNC)
+    }
+}
+
+/// Report every problem with the symlink list and the links installed from it, and if `fix` is
+/// set, fix those that can be fixed once the user confirms (see [`doctor::doctor`]).
+///
+/// Returns [`EXIT_PROBLEMS_FOUND`] if any problem is left unfixed.
 ///
 /// # Errors
-/// + [Error::LinkError] if `link_parent` does not exist as a path on the system.
-fn canonicalize_link_parent<P, S>(link_parent: &P, link_filename: &S) -> Result<PathBuf>
-where
-    P: AsRef<Path>,
-    S: AsRef<OsStr>,
-{
-    Ok(fs::canonicalize(link_parent).map_err(|_| {
-        Error::LinkError(format!(
-            "{} '{}' {}",
-            Paint::red("Cannot create link"),
-            link_parent.as_ref().join(link_filename.as_ref()).display(),
-            Paint::red("because the parent directory does not exist. Skipping...")
-        ))
-    })?)
+/// + Any error from [`doctor::doctor`].
+fn doctor(cli: &Cli, fix: bool) -> Result<u8> {
+    let _lock = if fix { acquire_lock(cli)? } else { None };
+    let options = doctor::Options {
+        fix,
+        yes: cli.yes,
+        default_no: cli.default_no,
+        state: cli.state.clone(),
+        root: target_root(cli)?,
+    };
+    Ok(match doctor::doctor(&source(cli)?, &options)? {
+        doctor::Outcome::Healthy => 0,
+        doctor::Outcome::ProblemsLeft => EXIT_PROBLEMS_FOUND,
+        doctor::Outcome::Cancelled => EXIT_CANCELLED,
+    })
 }
 
-/// Returns the path to the file that should be linked to in canonical, absolute form with all
-/// intermediate components normalized and symbolic links resolved. See [`fs::canonicalize`].
+/// Remove every link in the symlink list, and every symlink that the state file records but that
+/// has since been removed from the symlink list, once the user confirms. If `restore` is set, move
+/// the most recent backup of each back in its place (see [`uninstall::uninstall`]).
 ///
-/// # Params
-/// + `origin` - The path to the file that should be linked to.
+/// Returns [`EXIT_LINK_FAILED`] if a link could not be removed.
 ///
 /// # Errors
-/// + [Error::LinkError] if `origin` does not exist as a path on the system.
-fn canonicalize_origin<P>(origin: &P) -> Result<PathBuf>
-where
-    P: AsRef<Path>,
-{
-    Ok(fs::canonicalize(&origin).map_err(|_| {
-        Error::LinkError(format!(
-            "{} '{}' {}",
-            Paint::red("The path"),
-            origin.as_ref().display(),
-            Paint::red("does not exist. Skipping...")
-        ))
-    })?)
+/// + Any error from [`uninstall::uninstall`].
+fn uninstall(cli: &Cli, restore: bool) -> Result<u8> {
+    let _lock = acquire_lock(cli)?;
+    let options = uninstall::Options {
+        restore,
+        backup_dir: backup_dir(cli)?,
+        yes: cli.yes,
+        default_no: cli.default_no,
+        state: cli.state.clone(),
+        root: target_root(cli)?,
+    };
+    Ok(outcome_code(uninstall::uninstall(&source(cli)?, &options)?))
 }
 
-/// Rename a file to `<filename>-backup-<date>`.
+/// List the backups in the backup directory, delete all but the most recent of each file, or move
+/// the most recent backup of a file back in its place.
+///
+/// Returns [`EXIT_FAILURE`] with `--backup-in-place`, since there is no backup directory.
 ///
 /// # Errors
-/// + [Error::LinkError] if the renaming fails for some reason.
-fn backup<P, S>(parent_dir: &P, file_name: &S) -> Result<()>
-where
-    P: AsRef<Path>,
-    S: AsRef<OsStr>,
-{
-    let path = parent_dir.as_ref().join(file_name.as_ref());
-    let mut backup_file = file_name.as_ref().to_owned();
-    let date = chrono::Local::now()
-        .format("-backup-%Y-%m-%d-%H-%M-%S")
-        .to_string();
-    backup_file.push(date);
-    let backup = parent_dir.as_ref().join(backup_file);
-    print!(
-        "{} {} {} {}...",
-        Paint::yellow("Backing up"),
-        path.display(),
-        Paint::yellow("->"),
-        backup.display()
-    );
-    match fs::rename(&path, backup) {
-        Ok(_) => {
-            println!("{}", Paint::green("done."));
-            Ok(())
+/// + [`Error::NoBackup`] if there is no backup of the file to restore.
+/// + [`Error::RestoreBlocked`] if something other than a symlink is where the file was.
+/// + [`Error::JsonError`] if the index of the backup directory is not valid.
+fn backup(cli: &Cli, command: &BackupCommand) -> Result<u8> {
+    let Some(dir) = backup_dir(cli)? else {
+        log::error(Paint::red(
+            "Backups made with --backup-in-place aren't kept in a backup directory.",
+        ));
+        return Ok(EXIT_FAILURE);
+    };
+    let backups = Trash::at(dir);
+    let _lock = if matches!(command, BackupCommand::List { .. }) {
+        None
+    } else {
+        acquire_lock(cli)?
+    };
+    match command {
+        BackupCommand::List { since } => dotconfig::backup::list(&backups, *since)?,
+        BackupCommand::Prune { keep, older_than } => {
+            // Without a count, --older-than alone decides what is deleted
+            let keep = keep.unwrap_or(if older_than.is_some() { 0 } else { 3 });
+            dotconfig::backup::prune(&backups, keep, *older_than)?;
+        }
+        BackupCommand::Restore { path } => {
+            let original = dotconfig::normalize(&absolute(path)?);
+            dotconfig::backup::restore(&backups, &original)?;
         }
-        Err(e) => Err(Error::LinkError(format!(
-            "{} {}",
-            Paint::red("Backup failed."),
-            Paint::yellow(e)
-        ))),
     }
+    Ok(0)
 }
 
-#[derive(Deserialize, Debug)]
-struct SymlinkList {
-    links: Vec<Link>,
+/// Move `file` into the dotfiles directory, and link it to where it was from the first symlink
+/// list (see [`edit::add`]).
+///
+/// # Errors
+/// + Any error from [`edit::add`].
+fn add(cli: &Cli, file: &str, origin: Option<&str>) -> Result<u8> {
+    let _lock = acquire_lock(cli)?;
+    // A file can be added for any host, so the links for every host are read
+    let source = Source {
+        host: None,
+        ..source(cli)?
+    };
+    edit::add(&source, &absolute(file)?, origin)?;
+    Ok(0)
 }
 
-#[derive(Deserialize, Debug)]
-struct Link {
-    path: String,
-    origin: String,
+/// Replace the link at `file` with a copy of its origin, and remove it from its symlink list (see
+/// [`edit::remove`]).
+///
+/// # Errors
+/// + Any error from [`edit::remove`].
+fn remove(cli: &Cli, file: &str) -> Result<u8> {
+    let _lock = acquire_lock(cli)?;
+    edit::remove(&source(cli)?, &absolute(file)?)?;
+    Ok(0)
 }
 
-#[derive(Error, Debug)]
-enum Error {
-    #[error("The dotfiles directory ({0}) does not exist.")]
-    MissingDotfilesDir(PathBuf),
-    #[error("The symlink list file ({0}) does not exist.")]
-    MissingSymlinkListFile(PathBuf),
-    #[error("{0}")]
-    LinkError(String),
-    #[error("Windows is not supported.")]
-    UnsupportedPlatform,
-    #[error("IoError: {0}")]
-    IoError(#[from] std::io::Error),
-    #[error("Eror in YAML ({0})")]
-    YamlError(#[from] serde_yaml::Error),
-    #[error("Unknown variable ({0})")]
-    ShellexpandLookupError(#[from] shellexpand::LookupError<std::env::VarError>),
+/// Report every link from the manifest at `path` that has changed since it was written. Returns
+/// [`EXIT_DRIFT`] if any has.
+///
+/// # Errors
+/// + [`Error::IoError`] if the manifest or a link cannot be read.
+/// + [`Error::JsonError`] if the manifest is invalid.
+fn drift(path: &Path) -> Result<u8> {
+    let drifted = Manifest::from_file(&path)?.report_drift()?;
+    Ok(if drifted { EXIT_DRIFT } else { 0 })
+}
+
+/// Run the checks of [`doctor::self_check`]. Returns [`EXIT_SELF_CHECK_FAILED`] if any fails.
+fn self_check(cli: &Cli) -> u8 {
+    if doctor::self_check(resolve_paths(cli), cli.config_format) {
+        0
+    } else {
+        EXIT_SELF_CHECK_FAILED
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_error(cause: LinkFailure) -> Error {
+        Error::LinkError {
+            origin: PathBuf::from("/cfg/zshrc"),
+            link: PathBuf::from("/home/me/.zshrc"),
+            cause,
+        }
+    }
+
+    #[test]
+    fn errors_exit_with_their_code() {
+        let path = PathBuf::from("/cfg");
+        assert_eq!(exit_code(&Error::NoBackup(path.clone())), EXIT_FAILURE);
+        assert_eq!(
+            exit_code(&Error::UnknownOwner("nobody".to_owned())),
+            EXIT_INVALID_CONFIG
+        );
+        assert_eq!(
+            exit_code(&Error::MissingDotfilesDir(path.clone())),
+            EXIT_MISSING_DOTFILES_DIR
+        );
+        assert_eq!(
+            exit_code(&Error::PermissionDenied(path.clone())),
+            EXIT_LINK_FAILED
+        );
+        assert_eq!(exit_code(&Error::NotATerminal), EXIT_CANCELLED);
+        assert_eq!(
+            exit_code(&Error::UncommittedChanges(path.clone())),
+            EXIT_GIT_CHECK_FAILED
+        );
+        assert_eq!(
+            exit_code(&Error::AlreadyRunning(path)),
+            EXIT_ALREADY_RUNNING
+        );
+    }
+
+    #[test]
+    fn link_errors_exit_with_a_config_error_only_if_the_link_is_invalid() {
+        for cause in [
+            LinkFailure::MissingOrigin,
+            LinkFailure::InvalidPath,
+            LinkFailure::UnnamedLink,
+            LinkFailure::SameLocation,
+            LinkFailure::HardLinkedDirectory,
+        ] {
+            assert_eq!(exit_code(&link_error(cause)), EXIT_INVALID_CONFIG);
+        }
+        for cause in [
+            LinkFailure::Conflict,
+            LinkFailure::MissingParentDir,
+            LinkFailure::CrossDevice,
+            LinkFailure::SudoFailed,
+            LinkFailure::NotASymlink,
+            LinkFailure::Io(std::io::ErrorKind::Other.into()),
+        ] {
+            assert_eq!(exit_code(&link_error(cause)), EXIT_LINK_FAILED);
+        }
+    }
+
+    #[test]
+    fn commands_exit_with_their_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        let link = dir.path().join(".zshrc");
+        let symlink_list = format!("links:\n- origin: zshrc\n  path: {}\n", link.display());
+        fs::write(dotfiles_dir.join("symlinks.yml"), &symlink_list).unwrap();
+        let state = dir.path().join("state.yml");
+        let manifest = dir.path().join("manifest.json");
+        let run_with = |args: &[&str]| {
+            let mut argv = vec![
+                "dotconfig".to_owned(),
+                "--dir".to_owned(),
+                dotfiles_dir.display().to_string(),
+                "--state".to_owned(),
+                state.display().to_string(),
+                "--no-lock".to_owned(),
+                "--quiet".to_owned(),
+            ];
+            argv.extend(args.iter().map(|arg| arg.to_string()));
+            run(Cli::parse_from(argv)).unwrap_or_else(|e| exit_code(&e))
+        };
+
+        assert_eq!(run_with(&["status"]), EXIT_OUT_OF_SYNC);
+        assert_eq!(run_with(&["fmt", "--check"]), EXIT_NOT_FORMATTED);
+        assert_eq!(
+            run_with(&["--yes", "--manifest", &manifest.display().to_string()]),
+            0
+        );
+        assert_eq!(run_with(&["status"]), 0);
+        fs::remove_file(&link).unwrap();
+        assert_eq!(
+            run_with(&["drift", &manifest.display().to_string()]),
+            EXIT_DRIFT
+        );
+        fs::remove_file(dotfiles_dir.join("zshrc")).unwrap();
+        assert_eq!(run_with(&["doctor"]), EXIT_PROBLEMS_FOUND);
+        fs::write(dotfiles_dir.join("symlinks.yml"), "links: [").unwrap();
+        assert_eq!(run_with(&["self-check"]), EXIT_SELF_CHECK_FAILED);
+        assert_eq!(run_with(&[]), EXIT_INVALID_CONFIG);
+        fs::remove_dir_all(&dotfiles_dir).unwrap();
+        assert_eq!(run_with(&[]), EXIT_MISSING_DOTFILES_DIR);
+    }
+
+    #[test]
+    fn yes_can_be_given_with_default_no() {
+        for args in [
+            ["dotconfig", "--yes", "--default-no"],
+            ["dotconfig", "--default-no", "--yes"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(cli.yes);
+        }
+    }
+
+    #[test]
+    fn installations_exit_with_the_code_of_their_outcome() {
+        assert_eq!(outcome_code(Outcome::Done), 0);
+        assert_eq!(outcome_code(Outcome::NotFound), EXIT_FAILURE);
+        assert_eq!(outcome_code(Outcome::Failed), EXIT_LINK_FAILED);
+        assert_eq!(outcome_code(Outcome::Cancelled), EXIT_CANCELLED);
+        assert_eq!(outcome_code(Outcome::NotIdempotent), EXIT_NOT_IDEMPOTENT);
+        assert_eq!(outcome_code(Outcome::Interrupted), EXIT_INTERRUPTED);
+    }
 }
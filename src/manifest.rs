@@ -0,0 +1,245 @@
+//! A machine-readable record of what an installation did to the filesystem.
+
+use crate::{
+    log::{self, count},
+    planner::{choose_install_action, ConflictPolicy, InstallAction, LinkMode},
+    Error, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// A single change made to the filesystem while installing a link.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "operation", rename_all = "kebab-case")]
+pub enum Operation {
+    /// A directory was created. Only directories that did not exist before are recorded, starting
+    /// with the outermost one.
+    CreateDir { path: PathBuf },
+    /// A file that was in the way of a link was moved to `to`.
+    Backup { from: PathBuf, to: PathBuf },
+    /// A file that was in the way of a link was deleted.
+    Remove { path: PathBuf },
+    /// A symlink was created at `link`, pointing to `origin`.
+    Link { link: PathBuf, origin: PathBuf },
+    /// A hard link to `origin` was created at `link`.
+    HardLink { link: PathBuf, origin: PathBuf },
+    /// `origin` was copied to a new file at `link`.
+    Copy { link: PathBuf, origin: PathBuf },
+    /// `origin` was decrypted into a new file at `link`.
+    Decrypt { link: PathBuf, origin: PathBuf },
+}
+
+/// A link recorded in a [`Manifest`] that has since been changed by something other than dotconfig.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// Nothing is at the link's path any more.
+    Removed { link: PathBuf },
+    /// The link is a symlink to `target` rather than to `origin`.
+    Repointed {
+        link: PathBuf,
+        origin: PathBuf,
+        target: PathBuf,
+    },
+    /// A file or directory that isn't linked to the origin is at the link's path.
+    Replaced { link: PathBuf },
+}
+
+/// Every [`Operation`] performed during one run of dotconfig, in order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// The version of dotconfig that performed the operations.
+    pub version: String,
+    /// When the manifest was written, in RFC 3339 format.
+    pub date: String,
+    /// The dotfiles directory the links were installed from.
+    pub dotfiles_dir: PathBuf,
+    pub operations: Vec<Operation>,
+}
+
+impl Manifest {
+    /// Returns a manifest of `operations`, dated now.
+    pub fn new(dotfiles_dir: PathBuf, operations: Vec<Operation>) -> Self {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            date: chrono::Local::now().to_rfc3339(),
+            dotfiles_dir,
+            operations,
+        }
+    }
+
+    /// Read a manifest from a JSON file.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the file cannot be read.
+    /// + [`Error::JsonError`](crate::Error::JsonError) if the file is not a valid manifest.
+    pub fn from_file<P>(path: &P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Returns every link, hard link, copy and decrypted file the manifest records as created that
+    /// is no longer as it was created. A copy has drifted if its contents differ from its
+    /// origin's. The contents of decrypted files are not compared, since they may be edited,
+    /// and the origin would have to be decrypted.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if a link cannot be read.
+    pub fn drift(&self) -> Result<Vec<Drift>> {
+        let mut drift = vec![];
+        for operation in &self.operations {
+            let (link, origin, mode) = match operation {
+                Operation::Link { link, origin } => (link, origin, LinkMode::Symlink),
+                Operation::HardLink { link, origin } => (link, origin, LinkMode::Hardlink),
+                Operation::Copy { link, origin } => (link, origin, LinkMode::Copy),
+                Operation::Decrypt { link, .. } => {
+                    if fs::symlink_metadata(link).is_err() {
+                        drift.push(Drift::Removed { link: link.clone() });
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let Ok(metadata) = fs::symlink_metadata(link) else {
+                drift.push(Drift::Removed { link: link.clone() });
+                continue;
+            };
+            let action = match choose_install_action(origin, link, mode, &ConflictPolicy::default())
+            {
+                Ok(action) => action,
+                // The link's parent directory has been replaced by a file.
+                Err(Error::LinkError { .. }) => InstallAction::Link,
+                Err(e) => return Err(e),
+            };
+            if action == InstallAction::Skip {
+                continue;
+            }
+            drift.push(if metadata.file_type().is_symlink() {
+                Drift::Repointed {
+                    link: link.clone(),
+                    origin: origin.clone(),
+                    target: fs::read_link(link)?,
+                }
+            } else {
+                Drift::Replaced { link: link.clone() }
+            });
+        }
+        Ok(drift)
+    }
+
+    /// Report every link that has changed since the manifest was written (see
+    /// [`Manifest::drift`]), and return whether any has.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if a link cannot be read.
+    pub fn report_drift(&self) -> Result<bool> {
+        let drift = self.drift()?;
+        for drift in &drift {
+            match drift {
+                Drift::Removed { link } => log::warn(format!(
+                    "{} {}",
+                    Paint::yellow("Removed:   "),
+                    link.display()
+                )),
+                Drift::Repointed {
+                    link,
+                    origin,
+                    target,
+                } => log::warn(format!(
+                    "{} {} {} {} {} {}",
+                    Paint::yellow("Re-pointed:"),
+                    link.display(),
+                    Paint::yellow("->"),
+                    target.display(),
+                    Paint::yellow("instead of"),
+                    origin.display()
+                )),
+                Drift::Replaced { link } => log::warn(format!(
+                    "{} {}",
+                    Paint::yellow("Replaced:  "),
+                    link.display()
+                )),
+            }
+        }
+        if drift.is_empty() {
+            log::info(format!(
+                "{} {}",
+                Paint::green("Nothing has changed since"),
+                self.date
+            ));
+        } else {
+            log::info(format!(
+                "{} {} {}",
+                count(drift.len(), "link"),
+                Paint::yellow("changed since"),
+                self.date
+            ));
+        }
+        Ok(!drift.is_empty())
+    }
+
+    /// Write the manifest to a JSON file, replacing the file if it exists.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the file cannot be written.
+    pub fn write<P>(&self, path: &P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(fs::write(path, json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::symlink;
+
+    #[test]
+    fn links_changed_since_the_manifest_are_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = |name: &str| dir.path().join("cfg").join(name);
+        let link = |name: &str| dir.path().join(name);
+        fs::create_dir(dir.path().join("cfg")).unwrap();
+        let mut operations = vec![];
+        for name in ["zshrc", "vimrc", "gitconfig", "tmux.conf"] {
+            fs::write(origin(name), "").unwrap();
+            symlink(origin(name), link(name)).unwrap();
+            operations.push(Operation::Link {
+                link: link(name),
+                origin: origin(name),
+            });
+        }
+        let manifest = Manifest::new(dir.path().join("cfg"), operations);
+        assert_eq!(manifest.drift().unwrap(), []);
+
+        fs::remove_file(link("vimrc")).unwrap();
+        fs::remove_file(link("gitconfig")).unwrap();
+        symlink(origin("zshrc"), link("gitconfig")).unwrap();
+        fs::remove_file(link("tmux.conf")).unwrap();
+        fs::write(link("tmux.conf"), "").unwrap();
+        assert_eq!(
+            manifest.drift().unwrap(),
+            [
+                Drift::Removed {
+                    link: link("vimrc")
+                },
+                Drift::Repointed {
+                    link: link("gitconfig"),
+                    origin: origin("gitconfig"),
+                    target: origin("zshrc")
+                },
+                Drift::Replaced {
+                    link: link("tmux.conf")
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,1369 @@
+//! Deciding what installing each link takes, without touching the filesystem.
+
+use crate::{
+    backup::{backup_path, backup_trash},
+    cache::Cache,
+    config::{Link, SymlinkList},
+    decrypt, expand_link_file, expand_path, link_error, log,
+    manifest::Operation,
+    normalize, platform, Error, LinkFailure, Result, TargetRoot,
+};
+use std::{
+    collections::HashMap,
+    env::{self, VarError},
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, read_link, File},
+    io::{self, BufRead, BufReader},
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
+};
+use yansi::Paint;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstallAction {
+    Skip,
+    BackupAndLink,
+    OverwriteAndLink,
+    ReplaceIdenticalAndLink,
+    CreateDirAndLink,
+    Link,
+    /// Leave the file that is in the way of the link alone, and don't install the link.
+    KeepExisting,
+    /// Decrypt the origin, and compare it to the file where it is to be decrypted to. The file is
+    /// kept if it already has the decrypted contents, and is in the way like any other file if it
+    /// doesn't. See [`compare_decrypted`].
+    DecryptAndCompare,
+}
+
+impl InstallAction {
+    /// Returns whether the action leaves the filesystem as it is.
+    pub fn is_noop(self) -> bool {
+        matches!(self, InstallAction::Skip | InstallAction::KeepExisting)
+    }
+}
+
+impl fmt::Display for InstallAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InstallAction::Skip => "skip",
+            InstallAction::BackupAndLink => "backup-and-link",
+            InstallAction::OverwriteAndLink => "overwrite-and-link",
+            InstallAction::ReplaceIdenticalAndLink => "replace-identical-and-link",
+            InstallAction::CreateDirAndLink => "create-dir-and-link",
+            InstallAction::Link => "link",
+            InstallAction::KeepExisting => "keep-existing",
+            InstallAction::DecryptAndCompare => "decrypt-and-compare",
+        })
+    }
+}
+
+/// How a link is created.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    /// A symbolic link to the origin.
+    #[default]
+    Symlink,
+    /// A hard link to the origin, for programs that don't follow symlinks. The origin must be a
+    /// file on the same filesystem as the link.
+    Hardlink,
+    /// A copy of the origin, for programs that don't follow symlinks. It counts as installed
+    /// while its contents match the origin's.
+    Copy,
+    /// A new file with the decrypted contents of the origin. This is the mode of every link that
+    /// is [`Link::encrypted`], and can't be chosen for all links.
+    #[clap(skip)]
+    Decrypt,
+}
+
+/// What to do with a file that is in the way of a link.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Rename the file, or move it into the trash, then link.
+    #[default]
+    Backup,
+    /// Delete the file, then link.
+    Overwrite,
+    /// Leave the file where it is, and don't link.
+    Skip,
+    /// Don't install anything.
+    Fail,
+}
+
+/// The actions to take to install a symlink list, in the order to take them in, as returned by
+/// [`plan`] and carried out by [`apply`](crate::executor::apply).
+pub type Plan = Vec<PlannedAction>;
+
+/// A fully resolved link, along with the action that will be taken to install it.
+#[derive(Debug)]
+pub struct PlannedAction {
+    /// The canonical path to the file the symlink will point to.
+    pub origin: PathBuf,
+    /// The path the symlink will be created at, with all shell variables expanded.
+    pub link: PathBuf,
+    pub action: InstallAction,
+    pub policy: ConflictPolicy,
+    pub mode: LinkMode,
+    /// The [`Link::description`] of the link.
+    pub description: Option<String>,
+    /// The [`Link::reload`] command of the link.
+    pub reload: Option<String>,
+    /// The [`Link::post_link`] hook of the link.
+    pub post_link: Option<String>,
+    /// The [`Link::group`] of the link.
+    pub group: Option<String>,
+    /// Whether the directory the link will be created in can be written to, as far as could be
+    /// told when planning. Always `true` for links that are already installed.
+    pub writable: bool,
+    /// How long it took to resolve the link and choose its action.
+    pub planning_time: Duration,
+    /// Whether the link was skipped because [`PlanOptions::cache`] records it as installed, rather
+    /// than because it was checked.
+    pub cached: bool,
+}
+
+/// What to do with a file that is in the way of a link.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConflictPolicy {
+    /// Whether to back the file up, delete it, keep it, or fail.
+    pub on_conflict: OnConflict,
+    /// Delete the file without backing it up if it is identical to the origin.
+    pub skip_identical: bool,
+    /// Back the file up by moving it into the [`Trash`](crate::trash::Trash) in this directory,
+    /// rather than renaming it in place.
+    pub backup_dir: Option<PathBuf>,
+}
+
+/// Settings that change how [`plan`] resolves links.
+#[derive(Clone, Debug, Default)]
+pub struct PlanOptions {
+    /// Allow origins that resolve to a path outside of the dotfiles directory, e.g. `../secrets`.
+    pub allow_external_origins: bool,
+    /// Replace regular files that are identical to their origin without backing them up.
+    pub skip_identical: bool,
+    /// Link to origins that are symlinks themselves, rather than to the files they point to.
+    pub no_follow_origin: bool,
+    /// Allow links to be created inside of the dotfiles directory, where they could replace the
+    /// files they are meant to link to.
+    pub allow_links_inside_dotfiles_dir: bool,
+    /// Back files up by moving them into the [`Trash`](crate::trash::Trash) in this directory,
+    /// rather than renaming them in place.
+    pub backup_dir: Option<PathBuf>,
+    /// How to create the links.
+    pub mode: LinkMode,
+    /// What to do with files that are in the way of links, unless a link sets `backup` itself.
+    pub on_conflict: OnConflict,
+    /// Fail, rather than warn and use the name on disk, if an origin is named differently from the
+    /// file on disk in case only.
+    pub case_sensitive: bool,
+    /// Resolve link paths inside of this directory, as if it were `/`.
+    pub root: Option<TargetRoot>,
+    /// Links recorded as installed by an earlier run. Those that haven't changed since are
+    /// skipped without being checked.
+    pub cache: Option<Cache>,
+}
+
+/// Resolve every link in `symlink_list` and choose an install action for it.
+///
+/// Links are returned in the order they are listed in, except that a link inside of another link's
+/// path comes after it (see [`dependencies`]).
+///
+/// This only reads from the filesystem; nothing is created, moved or linked.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory that holds the config files. Origins are relative to it.
+/// + `symlink_list` - The links to resolve.
+/// + `options` - Settings that change how links are resolved.
+///
+/// # Errors
+/// + [`Error::LinkError`] if an origin does not exist, unless its link is [`Link::optional`], or a
+///   link path is invalid.
+/// + [`Error::OriginOutsideDotfilesDir`] if an origin resolves to a path outside of `dotfiles_dir`,
+///   unless [`PlanOptions::allow_external_origins`] is set.
+/// + [`Error::LinkInsideDotfilesDir`] if a link would be created inside of `dotfiles_dir`, unless
+///   [`PlanOptions::allow_links_inside_dotfiles_dir`] is set The same goes for a link inside of
+///   another link's path, if that link points to a directory in `dotfiles_dir`; this is reported as
+///   an [`Error::LinkError`].
+/// + [`Error::UnsetVariable`] if a link path references an unset shell variable.
+pub fn plan<P>(dotfiles_dir: &P, symlink_list: &SymlinkList, options: &PlanOptions) -> Result<Plan>
+where
+    P: AsRef<Path>,
+{
+    let canonical_dotfiles_dir = fs::canonicalize(dotfiles_dir)
+        .map_err(|_| Error::MissingDotfilesDir(dotfiles_dir.as_ref().to_owned()))?;
+    let symlink_list = &symlink_list.expand_origins(dotfiles_dir)?;
+    let mut listings = DirListings::new();
+    // Every link with an existing origin, so that links inside of another link's path can be found.
+    let origins: Vec<(PathBuf, PathBuf)> = symlink_list
+        .links
+        .iter()
+        .filter(|entry| matches!(unmet_condition(entry, options.root.as_ref()), Ok(None)))
+        .flat_map(|entry| entry.path.iter().map(move |path| (entry, path)))
+        .filter_map(|(entry, path)| {
+            let origin = dotfiles_dir.as_ref().join(&entry.origin);
+            let link = resolve_link(
+                path,
+                entry.rename.as_deref(),
+                &origin,
+                options.root.as_ref(),
+            )
+            .ok()?;
+            Some((link, fs::canonicalize(&origin).ok()?))
+        })
+        .collect();
+    symlink_list
+        .links
+        .iter()
+        .flat_map(|entry| entry.path.iter().map(move |path| (entry, path)))
+        .map(|(entry, path)| {
+            let start = Instant::now();
+            let mut unresolved_origin = dotfiles_dir.as_ref().join(&entry.origin);
+            if let Some(on_disk) =
+                origin_case_on_disk(dotfiles_dir.as_ref(), &entry.origin, &mut listings)
+            {
+                let on_disk = dotfiles_dir.as_ref().join(on_disk);
+                if options.case_sensitive {
+                    return Err(link_error(
+                        &unresolved_origin,
+                        Path::new(path),
+                        LinkFailure::OriginCaseMismatch(on_disk),
+                    ));
+                }
+                log::warn(format!(
+                    "{} {} {} {}{}",
+                    Paint::yellow("The origin"),
+                    entry.origin,
+                    Paint::yellow("is named"),
+                    on_disk.display(),
+                    Paint::yellow(
+                        " on disk, which differs in case. Linking to that instead. Pass \
+                         --case-sensitive to make this an error."
+                    )
+                ));
+                unresolved_origin = on_disk;
+            }
+            if entry.optional() && fs::symlink_metadata(&unresolved_origin).is_err() {
+                log::debug(format!(
+                    "{} {} {}",
+                    Paint::blue("Leaving out"),
+                    path,
+                    Paint::blue("because its optional origin does not exist.")
+                ));
+                return Ok(None);
+            }
+            if let Some(reason) = unmet_condition(entry, options.root.as_ref())? {
+                log::info(format!(
+                    "{} {} {} {}{}",
+                    Paint::blue("Leaving out"),
+                    path,
+                    Paint::blue("because"),
+                    reason,
+                    Paint::blue(".")
+                ));
+                return Ok(None);
+            }
+            let link = resolve_link(
+                path,
+                entry.rename.as_deref(),
+                &unresolved_origin,
+                options.root.as_ref(),
+            )
+            .map_err(|e| match e {
+                Error::ShellexpandLookupError(e) if e.cause == VarError::NotPresent => {
+                    Error::UnsetVariable {
+                        var: e.var_name,
+                        origin: entry.origin.clone(),
+                    }
+                }
+                e => e,
+            })?;
+            let origin = if options.no_follow_origin {
+                absolute_origin(&canonical_dotfiles_dir.join(&entry.origin), &link)?
+            } else {
+                canonicalize_origin(&unresolved_origin, &link)?
+            };
+            if !options.allow_external_origins && !origin.starts_with(&canonical_dotfiles_dir) {
+                return Err(Error::OriginOutsideDotfilesDir(origin));
+            }
+            let mode = entry.link_mode(options.mode);
+            if mode == LinkMode::Hardlink && origin.is_dir() {
+                return Err(link_error(&origin, &link, LinkFailure::HardLinkedDirectory));
+            }
+            if mode == LinkMode::Copy && origin.is_dir() {
+                return Err(link_error(&origin, &link, LinkFailure::CopiedDirectory));
+            }
+            if mode == LinkMode::Decrypt && origin.is_dir() {
+                return Err(link_error(&origin, &link, LinkFailure::EncryptedDirectory));
+            }
+            if mode == LinkMode::Hardlink && !same_filesystem(&origin, &link) {
+                return Err(link_error(&origin, &link, LinkFailure::CrossDevice));
+            }
+            let enclosing = enclosing_link(&origins, &link);
+            if let Some((enclosing_link, enclosing_origin)) = enclosing {
+                if !options.allow_links_inside_dotfiles_dir
+                    && enclosing_origin.is_dir()
+                    && enclosing_origin.starts_with(&canonical_dotfiles_dir)
+                {
+                    // Once the enclosing link is installed, this link would be created inside of
+                    // the dotfiles directory.
+                    return Err(link_error(
+                        &origin,
+                        &link,
+                        LinkFailure::InsideLinkedDirectory(enclosing_link.to_owned()),
+                    ));
+                }
+            }
+            if !options.allow_links_inside_dotfiles_dir
+                && resolve_parent_dirs(&link).starts_with(&canonical_dotfiles_dir)
+            {
+                return Err(Error::LinkInsideDotfilesDir(link));
+            }
+            let policy = ConflictPolicy {
+                on_conflict: entry.on_conflict(options.on_conflict),
+                skip_identical: options.skip_identical,
+                backup_dir: options.backup_dir.clone(),
+            };
+            let cached = options
+                .cache
+                .as_ref()
+                .is_some_and(|cache| cache.is_installed(&origin, &link));
+            let action = if cached {
+                InstallAction::Skip
+            } else {
+                choose_install_action(&origin, &link, mode, &policy)?
+            };
+            // A link inside of another link's path is created wherever that link points to.
+            let parent = match enclosing {
+                Some((enclosing_link, enclosing_origin)) => enclosing_origin.join(
+                    link_parent(&origin, &link)?
+                        .strip_prefix(enclosing_link)
+                        .unwrap(),
+                ),
+                None => link_parent(&origin, &link)?,
+            };
+            let writable = action.is_noop() || is_writable(&parent);
+            Ok(Some(PlannedAction {
+                origin,
+                link,
+                action,
+                policy,
+                mode,
+                description: entry.description.clone(),
+                reload: entry.reload.clone(),
+                post_link: entry.post_link.clone(),
+                group: entry.group.clone(),
+                writable,
+                planning_time: start.elapsed(),
+                cached,
+            }))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<_>>()
+        .map(order_by_dependencies)
+}
+
+/// Returns why `entry` is left out because of its [`Link::os`], [`Link::arch`],
+/// [`Link::if_exists`] or [`Link::unless_exists`], e.g. "~/.local/share/nvim does not exist", or
+/// `None` if it isn't.
+///
+/// # Errors
+/// + [`Error::UnsetVariable`] if one of the paths references an unset shell variable.
+pub(crate) fn unmet_condition(entry: &Link, root: Option<&TargetRoot>) -> Result<Option<String>> {
+    let exists = |path: &String| -> Result<bool> {
+        let expanded = match root {
+            Some(root) => root.expand(path),
+            None => expand_path(path),
+        };
+        match expanded {
+            Ok(expanded) => Ok(expanded.exists()),
+            Err(Error::ShellexpandLookupError(e)) if e.cause == VarError::NotPresent => {
+                Err(Error::UnsetVariable {
+                    var: e.var_name,
+                    origin: entry.origin.clone(),
+                })
+            }
+            Err(e) => Err(e),
+        }
+    };
+    let only_for = |names: &Vec<String>, current: &str| {
+        (!names.iter().any(|name| name.eq_ignore_ascii_case(current)))
+            .then(|| format!("it is only for {}", names.join(", ")))
+    };
+    if let Some(reason) = entry
+        .os
+        .as_ref()
+        .and_then(|os| only_for(os, env::consts::OS))
+    {
+        return Ok(Some(reason));
+    }
+    if let Some(reason) = entry
+        .arch
+        .as_ref()
+        .and_then(|arch| only_for(arch, env::consts::ARCH))
+    {
+        return Ok(Some(reason));
+    }
+    if let Some(path) = &entry.if_exists {
+        if !exists(path)? {
+            return Ok(Some(format!("{} does not exist", path)));
+        }
+    }
+    if let Some(path) = &entry.unless_exists {
+        if exists(path)? {
+            return Ok(Some(format!("{} exists", path)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns, for each link in `plan`, the indices of the links it depends on: those whose path is
+/// one of its ancestors. A link must be installed after its dependencies, since installing one of
+/// them replaces whatever is at its path, including a directory created for the link inside it.
+pub fn dependencies(plan: &[PlannedAction]) -> Vec<Vec<usize>> {
+    plan.iter()
+        .map(|planned| {
+            plan.iter()
+                .enumerate()
+                .filter(|(_, other)| {
+                    other.link != planned.link && planned.link.starts_with(&other.link)
+                })
+                .map(|(i, _)| i)
+                .collect()
+        })
+        .collect()
+}
+
+/// The names of the files in each directory that [`origin_case_on_disk`] has read, or `None` if the
+/// directory can't be read. Origins share most of their directories, so each is only read once.
+pub(crate) type DirListings = HashMap<PathBuf, Option<Vec<OsString>>>;
+
+/// Returns `origin` with each of its components named as they are on disk, if that differs from
+/// `origin` in case only, e.g. `Config` for an origin written as `config`. Returns `None` if
+/// `origin` is named exactly as on disk, or doesn't exist in any case.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory that `origin` is relative to.
+/// + `origin` - A [`Link::origin`].
+/// + `listings` - The directories read so far, which directories that this reads are added to.
+pub(crate) fn origin_case_on_disk(
+    dotfiles_dir: &Path,
+    origin: &str,
+    listings: &mut DirListings,
+) -> Option<PathBuf> {
+    let mut dir = dotfiles_dir.to_owned();
+    let mut on_disk = PathBuf::new();
+    let mut differs = false;
+    for component in Path::new(origin).components() {
+        let name = match component {
+            Component::Normal(name) => {
+                let names = listings
+                    .entry(dir.clone())
+                    .or_insert_with(|| {
+                        let entries = fs::read_dir(&dir).ok()?;
+                        Some(
+                            entries
+                                .filter_map(|entry| Some(entry.ok()?.file_name()))
+                                .collect(),
+                        )
+                    })
+                    .as_ref()?;
+                if names.iter().any(|n| n == name) {
+                    name.to_owned()
+                } else {
+                    let lowercase = name.to_string_lossy().to_lowercase();
+                    differs = true;
+                    names
+                        .iter()
+                        .find(|n| n.to_string_lossy().to_lowercase() == lowercase)?
+                        .clone()
+                }
+            }
+            component => component.as_os_str().to_owned(),
+        };
+        dir.push(&name);
+        on_disk.push(&name);
+    }
+    differs.then_some(on_disk)
+}
+
+/// Returns the innermost of `links` whose path is an ancestor of `link`, with its origin.
+fn enclosing_link<'a>(
+    links: &'a [(PathBuf, PathBuf)],
+    link: &Path,
+) -> Option<(&'a Path, &'a Path)> {
+    links
+        .iter()
+        .filter(|(other, _)| other != link && link.starts_with(other))
+        .max_by_key(|(other, _)| other.components().count())
+        .map(|(other, origin)| (other.as_path(), origin.as_path()))
+}
+
+/// Reorders `plan` so that every link comes after its [`dependencies`]. Links keep their order
+/// otherwise.
+fn order_by_dependencies(plan: Plan) -> Plan {
+    let dependencies = dependencies(&plan);
+    let mut placed = vec![false; plan.len()];
+    let mut order = Vec::with_capacity(plan.len());
+    while order.len() < plan.len() {
+        // Ancestry can't be circular, so there is always a link whose dependencies are placed.
+        let next = (0..plan.len())
+            .find(|&i| !placed[i] && dependencies[i].iter().all(|&j| placed[j]))
+            .expect("links can't depend on each other in a cycle");
+        placed[next] = true;
+        order.push(next);
+    }
+    let mut plan: Vec<_> = plan.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| plan[i].take()).collect()
+}
+
+/// Returns the path a symlink will be created at, with all shell variables expanded.
+///
+/// # Params
+/// + `path` - One of the entry's [`Link::path`]s.
+/// + `rename` - The entry's [`Link::rename`]. If set, `path` is the directory the symlink is
+///   created in, and must either end with a `/` or name an existing directory. Otherwise, if `path`
+///   ends with a `/`, or names an existing directory while `origin` is a file, the symlink is
+///   created in that directory and named after `origin`.
+/// + `origin` - The entry's [`Link::origin`], joined onto the dotfiles directory but not resolved.
+/// + `root` - The directory to resolve `path` inside of, if any (see [`TargetRoot::expand`]).
+///
+/// # Errors
+/// + [`Error::LinkError`] if `path` and `rename` don't specify exactly one filename.
+/// + [`Error::ShellexpandLookupError`] if `path` references an unset shell variable.
+pub(crate) fn resolve_link(
+    path: &str,
+    rename: Option<&str>,
+    origin: &Path,
+    root: Option<&TargetRoot>,
+) -> Result<PathBuf> {
+    let expanded = match root {
+        Some(root) => root.expand(&path)?,
+        None => expand_link_file(&path)?,
+    };
+    let names_dir = path.ends_with('/');
+    match rename {
+        Some(rename) if rename.contains('/') || rename.is_empty() || rename == ".." => {
+            Err(link_error(
+                origin,
+                &expanded,
+                LinkFailure::InvalidRename(rename.to_owned()),
+            ))
+        }
+        Some(rename) if names_dir || expanded.is_dir() => Ok(expanded.join(rename)),
+        Some(_) => Err(link_error(origin, &expanded, LinkFailure::RenamedFilename)),
+        None if names_dir || (expanded.is_dir() && !origin.is_dir()) => match origin.file_name() {
+            Some(origin_name) => Ok(expanded.join(origin_name)),
+            None => Err(link_error(origin, &expanded, LinkFailure::UnnamedLink)),
+        },
+        None => Ok(expanded),
+    }
+}
+
+/// Choose an install action for a pending link.
+///
+/// If the parent directory of `link` does not exist, return `CreateDirAndLink`.
+/// If `link` exists and is already a symlink to `origin`, return `Skip`.
+/// If `link` exists, but is not a symlink to `origin`, return `BackupAndLink`, `OverwriteAndLink`
+/// or `KeepExisting`, as chosen by `policy.on_conflict`. If `policy.skip_identical` is set and
+/// `link` is a regular file with the same contents as `origin`, return `ReplaceIdenticalAndLink`
+/// instead.
+/// If `link` does not exist but its parent directory does, return `Link`.
+///
+/// In [`LinkMode::Hardlink`], `link` is already installed if it is the same file as `origin` (the
+/// same inode on the same device), and a symlink at `link` is in the way like any other file. In
+/// [`LinkMode::Copy`], `link` is already installed if it is a file with the same contents as
+/// `origin`, so a copy that either has been edited or is of an older version of `origin` is in the
+/// way. In [`LinkMode::Decrypt`], `link` is already installed if it is a file with the decrypted
+/// contents of `origin`. Since decrypting may ask for a passphrase, `origin` isn't decrypted to
+/// find out: a file at `link` gets `DecryptAndCompare`, unless `origin` has already been decrypted
+/// since it last changed.
+///
+/// # Params
+/// + `origin` - The absolute path to the file that will be installed at `link`. It is canonical,
+///   unless [`PlanOptions::no_follow_origin`] is set.
+/// + `link` - The path that `origin` is to be installed at. Shell variables and special symbols
+///   (e.g. `~`) will not be resolved.
+/// + `mode` - How the link will be created.
+/// + `policy` - What to do with a file that is in the way of the link.
+///
+/// # Errors
+/// + [`Error::LinkError`] if `link` is not a valid path, if it refers to `origin` itself, if a file
+///   that is not a directory is where its parent directory, or one of that directory's ancestors,
+///   should be, or if a file is in the way and `policy.on_conflict` is [`OnConflict::Fail`].
+/// + [`Error::IoError`] if `link` cannot be compared to `origin`.
+pub fn choose_install_action(
+    origin: &Path,
+    link: &Path,
+    mode: LinkMode,
+    policy: &ConflictPolicy,
+) -> Result<InstallAction> {
+    let replace = || conflict_action(origin, link, policy);
+
+    let link_parent = link_parent(origin, link)?;
+
+    if let Ok(canonical_link_parent) = fs::canonicalize(&link_parent) {
+        if canonical_link_parent.join(link_filename(origin, link)?) == origin {
+            // Linking the file to itself would replace it with a circular symlink.
+            return Err(link_error(origin, link, LinkFailure::SameLocation));
+        }
+    }
+
+    if let Some(file) = link_parent
+        .ancestors()
+        .find(|path| path.exists())
+        .filter(|path| !path.is_dir())
+    {
+        // Neither the link nor any missing directories could be created inside a file.
+        return Err(link_error(
+            origin,
+            link,
+            LinkFailure::ParentNotADirectory(file.to_owned()),
+        ));
+    }
+
+    if !link_parent.exists() {
+        // The file's parent directory does not exist.
+        Ok(InstallAction::CreateDirAndLink)
+    } else if link.exists() {
+        if let Ok(existing_link_origin) = read_link(link) {
+            // The file exists, and is a symlink. Its target may be relative to its directory, and
+            // may either be `origin` itself or resolve to it.
+            let existing_link_origin = normalize(&link_parent.join(existing_link_origin));
+            if mode == LinkMode::Symlink
+                && (*origin == existing_link_origin
+                    || fs::canonicalize(&existing_link_origin).ok().as_deref() == Some(origin))
+            {
+                // The file is already linked to origin.
+                Ok(InstallAction::Skip)
+            } else {
+                // The file is linked to something other than origin, or a hard link is wanted.
+                replace()
+            }
+        } else if mode == LinkMode::Hardlink && same_file(origin, link)? {
+            // The file is already a hard link to origin.
+            Ok(InstallAction::Skip)
+        } else if mode == LinkMode::Copy && same_contents(origin, link)? {
+            // The file is already a copy of origin.
+            Ok(InstallAction::Skip)
+        } else if mode == LinkMode::Symlink
+            && platform::SYMLINKS_MAY_BE_COPIES
+            && link.is_file()
+            && same_contents(origin, link)?
+        {
+            // The file is a copy of origin, which is how a symlink to it is installed where
+            // symlinks can't be created.
+            Ok(InstallAction::Skip)
+        } else if mode == LinkMode::Decrypt && link.is_file() {
+            // Whether the file is already a decrypted copy of origin is only known once origin is
+            // decrypted, which waits until the link is installed.
+            match decrypt::cached(origin) {
+                Some(decrypted) if fs::read(link)? == decrypted => Ok(InstallAction::Skip),
+                Some(_) => replace(),
+                None => Ok(InstallAction::DecryptAndCompare),
+            }
+        } else if policy.skip_identical && same_contents(origin, link)? {
+            // The file is a copy of origin, so there is nothing worth backing up.
+            Ok(InstallAction::ReplaceIdenticalAndLink)
+        } else {
+            // The file exists but is not a symlink.
+            replace()
+        }
+    } else {
+        // The file does not exist, but its parent directory does.
+        Ok(InstallAction::Link)
+    }
+}
+
+/// Returns the action that installs the link from `link` to the encrypted `origin`, once
+/// [`choose_install_action`] has chosen [`InstallAction::DecryptAndCompare`] for it. `origin` is
+/// decrypted, which may ask for a passphrase.
+///
+/// Returns `Skip` if the file at `link` already has the decrypted contents of `origin`, or the
+/// action `policy` chooses for a file that is in the way if it doesn't.
+///
+/// # Errors
+/// + [`Error::LinkError`] if `origin` can't be decrypted, or if the file is in the way and
+///   `policy.on_conflict` is [`OnConflict::Fail`].
+/// + [`Error::IoError`] if `link` cannot be read.
+pub fn compare_decrypted(
+    origin: &Path,
+    link: &Path,
+    policy: &ConflictPolicy,
+) -> Result<InstallAction> {
+    let decrypted = decrypt::decrypt(origin)
+        .map_err(|e| link_error(origin, link, LinkFailure::DecryptFailed(e)))?;
+    if fs::read(link)? == decrypted {
+        Ok(InstallAction::Skip)
+    } else {
+        conflict_action(origin, link, policy)
+    }
+}
+
+/// Returns the action `policy` chooses for a file that is in the way of the link from `link` to
+/// `origin`.
+///
+/// # Errors
+/// + [`Error::LinkError`] if `policy.on_conflict` is [`OnConflict::Fail`].
+fn conflict_action(origin: &Path, link: &Path, policy: &ConflictPolicy) -> Result<InstallAction> {
+    match policy.on_conflict {
+        OnConflict::Backup => Ok(InstallAction::BackupAndLink),
+        OnConflict::Overwrite => Ok(InstallAction::OverwriteAndLink),
+        OnConflict::Skip => Ok(InstallAction::KeepExisting),
+        OnConflict::Fail => Err(link_error(origin, link, LinkFailure::Conflict)),
+    }
+}
+
+/// Returns the operations that carry out `action` for the link from `link` to `origin`, without
+/// performing them. `mode` decides the kind of link, and `policy` where a backup goes.
+///
+/// Since whether a decrypted file is in the way isn't known until its origin is decrypted,
+/// [`InstallAction::DecryptAndCompare`] gets the operations that replace a file that is in the way,
+/// and none if `policy` keeps it.
+///
+/// # Errors
+/// + [`Error::LinkError`] if `link` is not a valid path.
+pub fn install_operations(
+    origin: &Path,
+    link: &Path,
+    action: InstallAction,
+    mode: LinkMode,
+    policy: &ConflictPolicy,
+) -> Result<Vec<Operation>> {
+    let link_parent = link_parent(origin, link)?;
+    // A path without a filename, like `/`, can't be linked.
+    link_filename(origin, link)?;
+    let mut operations = match action {
+        InstallAction::Skip | InstallAction::KeepExisting => return Ok(vec![]),
+        InstallAction::DecryptAndCompare => {
+            let action = match policy.on_conflict {
+                OnConflict::Backup => InstallAction::BackupAndLink,
+                OnConflict::Overwrite => InstallAction::OverwriteAndLink,
+                OnConflict::Skip | OnConflict::Fail => return Ok(vec![]),
+            };
+            return install_operations(origin, link, action, mode, policy);
+        }
+        InstallAction::Link => vec![],
+        InstallAction::CreateDirAndLink => missing_dirs(&link_parent)
+            .into_iter()
+            .map(|path| Operation::CreateDir { path })
+            .collect(),
+        InstallAction::ReplaceIdenticalAndLink | InstallAction::OverwriteAndLink => {
+            vec![Operation::Remove {
+                path: link.to_owned(),
+            }]
+        }
+        InstallAction::BackupAndLink => match backup_trash(link, policy.backup_dir.as_deref()) {
+            Some(trash) => {
+                let to = trash.path_for(link);
+                let mut operations: Vec<_> = missing_dirs(to.parent().unwrap_or(&to))
+                    .into_iter()
+                    .map(|path| Operation::CreateDir { path })
+                    .collect();
+                operations.push(Operation::Backup {
+                    from: link.to_owned(),
+                    to,
+                });
+                operations
+            }
+            None => vec![Operation::Backup {
+                from: link.to_owned(),
+                to: backup_path(link),
+            }],
+        },
+    };
+    operations.push(match mode {
+        LinkMode::Symlink => Operation::Link {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+        LinkMode::Hardlink => Operation::HardLink {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+        LinkMode::Copy => Operation::Copy {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+        LinkMode::Decrypt => Operation::Decrypt {
+            link: link.to_owned(),
+            origin: origin.to_owned(),
+        },
+    });
+    Ok(operations)
+}
+
+/// Returns whether `a` and `b` are the same file, i.e. hard links to the same inode. Symlinks are
+/// not followed.
+pub(crate) fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    let (a, b) = (fs::symlink_metadata(a)?, fs::symlink_metadata(b)?);
+    Ok(platform::same_file(&a, &b))
+}
+
+/// Returns whether `link` would be created on the same filesystem as `origin`, judging by the
+/// nearest existing ancestor of `link`. Returns `true` if either can't be inspected, so that the
+/// problem is reported when installing.
+pub(crate) fn same_filesystem(origin: &Path, link: &Path) -> bool {
+    let device = |path: &Path| fs::metadata(path).ok().and_then(|m| platform::device(&m));
+    match (
+        device(origin),
+        link.ancestors()
+            .skip(1)
+            .find(|dir| dir.exists())
+            .and_then(device),
+    ) {
+        (Some(origin), Some(link)) => origin == link,
+        _ => true,
+    }
+}
+
+/// Returns whether files can be created in `dir`, or in its nearest existing ancestor if it doesn't
+/// exist, without changing anything (see [`platform::is_writable`]).
+fn is_writable(dir: &Path) -> bool {
+    match dir.ancestors().find(|dir| dir.exists()) {
+        Some(dir) => platform::is_writable(dir),
+        None => true,
+    }
+}
+
+/// Returns `dir` and those of its ancestors that do not exist, starting with the outermost one.
+pub(crate) fn missing_dirs(dir: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<_> = dir
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_owned)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+/// Returns the path to the folder the symlink will go in.
+///
+/// # Params
+/// + `origin` - The path the symlink will point to.
+/// + `link` - The path to the symlink.
+///
+/// # Errors
+/// + [Error::LinkError] if `link` does not have a valid parent directory.
+pub(crate) fn link_parent(origin: &Path, link: &Path) -> Result<PathBuf> {
+    link.parent()
+        .map(Path::to_owned)
+        .ok_or_else(|| link_error(origin, link, LinkFailure::InvalidPath))
+}
+
+/// Returns the symlink's filename.
+///
+/// # Params
+/// + `origin` - The path the symlink will point to.
+/// + `link` - The path to the symlink.
+///
+/// # Errors
+/// + [Error::LinkError] if `link` is not a valid path.
+pub(crate) fn link_filename(origin: &Path, link: &Path) -> Result<OsString> {
+    link.file_name()
+        .map(OsStr::to_owned)
+        .ok_or_else(|| link_error(origin, link, LinkFailure::InvalidPath))
+}
+
+/// Returns the path to the file that should be linked to in canonical, absolute form with all
+/// intermediate components normalized and symbolic links resolved. See [`fs::canonicalize`].
+///
+/// # Params
+/// + `origin` - The path to the file that should be linked to.
+/// + `link` - The path the symlink will be created at.
+///
+/// # Errors
+/// + [Error::LinkError] if `origin` does not exist as a path on the system.
+fn canonicalize_origin(origin: &Path, link: &Path) -> Result<PathBuf> {
+    fs::canonicalize(origin).map_err(|_| link_error(origin, link, LinkFailure::MissingOrigin))
+}
+
+/// Returns the path to the file that should be linked to in absolute form with `.` and `..`
+/// components removed, but without resolving symbolic links. The origin itself may be a symlink.
+///
+/// # Params
+/// + `origin` - The absolute path to the file that should be linked to.
+/// + `link` - The path the symlink will be created at.
+///
+/// # Errors
+/// + [Error::LinkError] if `origin` does not exist as a path on the system.
+fn absolute_origin(origin: &Path, link: &Path) -> Result<PathBuf> {
+    let origin = normalize(origin);
+    match fs::symlink_metadata(&origin) {
+        Ok(_) => Ok(origin),
+        Err(_) => Err(link_error(&origin, link, LinkFailure::MissingOrigin)),
+    }
+}
+
+/// Returns `path` with `.` and `..` components removed and symbolic links resolved in every
+/// directory leading up to it. The last component is left as it is, since it may itself be a
+/// symlink, and directories that don't exist yet are kept as they are written.
+fn resolve_parent_dirs(path: &Path) -> PathBuf {
+    let path = normalize(path);
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return path;
+    };
+    for dir in parent.ancestors() {
+        if let Ok(canonical_dir) = fs::canonicalize(dir) {
+            let rest = parent.strip_prefix(dir).unwrap_or(Path::new(""));
+            return canonical_dir.join(rest).join(file_name);
+        }
+    }
+    path
+}
+
+/// Returns whether `a` and `b` are both regular files with the same contents.
+pub(crate) fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (a_meta, b_meta) = (fs::metadata(a)?, fs::symlink_metadata(b)?);
+    if !a_meta.is_file() || !b_meta.is_file() || a_meta.len() != b_meta.len() {
+        return Ok(false);
+    }
+    let (mut a, mut b) = (
+        BufReader::new(File::open(a)?),
+        BufReader::new(File::open(b)?),
+    );
+    loop {
+        let (a_buf, b_buf) = (a.fill_buf()?, b.fill_buf()?);
+        if a_buf.is_empty() || b_buf.is_empty() {
+            return Ok(a_buf.is_empty() && b_buf.is_empty());
+        }
+        let len = a_buf.len().min(b_buf.len());
+        if a_buf[..len] != b_buf[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_and_link_at_same_location_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("zshrc");
+        fs::write(&origin, "").unwrap();
+        let origin = fs::canonicalize(origin).unwrap();
+        let link = dir.path().join(".").join("zshrc");
+
+        assert!(matches!(
+            choose_install_action(
+                &origin,
+                &link,
+                LinkMode::Symlink,
+                &ConflictPolicy::default()
+            ),
+            Err(Error::LinkError {
+                cause: LinkFailure::SameLocation,
+                ..
+            })
+        ));
+        // The file must not have been touched.
+        assert!(fs::symlink_metadata(&origin).unwrap().is_file());
+    }
+
+    #[test]
+    fn file_in_place_of_parent_directory_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("init.vim");
+        let file = dir.path().join("nvim");
+        fs::write(&origin, "").unwrap();
+        fs::write(&file, "").unwrap();
+
+        for link in [file.join("init.vim"), file.join("lua").join("init.vim")] {
+            match choose_install_action(
+                &origin,
+                &link,
+                LinkMode::Symlink,
+                &ConflictPolicy::default(),
+            ) {
+                Err(Error::LinkError {
+                    cause: LinkFailure::ParentNotADirectory(path),
+                    ..
+                }) => assert_eq!(path, file),
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn identical_files_are_replaced_without_backup_when_requested() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("zshrc");
+        let link = dir.path().join(".zshrc");
+        fs::write(&origin, "export EDITOR=vim\n").unwrap();
+        fs::write(&link, "export EDITOR=vim\n").unwrap();
+        let origin = fs::canonicalize(origin).unwrap();
+        let policy = ConflictPolicy {
+            skip_identical: true,
+            ..ConflictPolicy::default()
+        };
+
+        assert_eq!(
+            choose_install_action(&origin, &link, LinkMode::Symlink, &policy).unwrap(),
+            InstallAction::ReplaceIdenticalAndLink
+        );
+        fs::write(&link, "export EDITOR=nano\n").unwrap();
+        assert_eq!(
+            choose_install_action(&origin, &link, LinkMode::Symlink, &policy).unwrap(),
+            InstallAction::BackupAndLink
+        );
+    }
+
+    #[test]
+    fn symlinked_origins_are_kept_when_not_following_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        let dotfiles_dir = dir.join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("secrets"), "").unwrap();
+        platform::symlink(
+            dotfiles_dir.join("secrets"),
+            dotfiles_dir.join("secrets-link"),
+        )
+        .unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}, origin: secrets-link}}\n",
+            dir.join(".secrets").display()
+        ))
+        .unwrap();
+        let options = PlanOptions {
+            no_follow_origin: true,
+            ..PlanOptions::default()
+        };
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &options).unwrap();
+        assert_eq!(planned[0].origin, dotfiles_dir.join("secrets-link"));
+        // A link to the final target is replaced, and a link to the origin itself is kept.
+        platform::symlink(dotfiles_dir.join("secrets"), dir.join(".secrets")).unwrap();
+        let policy = ConflictPolicy::default();
+        assert_eq!(
+            choose_install_action(
+                &planned[0].origin,
+                &planned[0].link,
+                LinkMode::Symlink,
+                &policy
+            )
+            .unwrap(),
+            InstallAction::BackupAndLink
+        );
+        fs::remove_file(dir.join(".secrets")).unwrap();
+        platform::symlink("cfg/secrets-link", dir.join(".secrets")).unwrap();
+        assert_eq!(
+            choose_install_action(
+                &planned[0].origin,
+                &planned[0].link,
+                LinkMode::Symlink,
+                &policy
+            )
+            .unwrap(),
+            InstallAction::Skip
+        );
+    }
+
+    #[test]
+    fn files_linked_into_a_directory_are_named_after_the_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        let home = dir.path().join("home");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        fs::create_dir_all(home.join("existing")).unwrap();
+        let origin = dotfiles_dir.join("init.lua");
+        fs::write(&origin, "").unwrap();
+        let home = home.to_string_lossy();
+
+        // A trailing slash names a directory, whether it exists or not.
+        assert_eq!(
+            resolve_link(&format!("{}/nvim/", home), None, &origin, None).unwrap(),
+            dir.path().join("home/nvim/init.lua")
+        );
+        // So does the path of an existing directory.
+        assert_eq!(
+            resolve_link(&format!("{}/existing", home), None, &origin, None).unwrap(),
+            dir.path().join("home/existing/init.lua")
+        );
+        // Otherwise the path is the path of the link itself.
+        assert_eq!(
+            resolve_link(&format!("{}/init.lua", home), None, &origin, None).unwrap(),
+            dir.path().join("home/init.lua")
+        );
+        // A directory origin replaces an existing directory rather than being linked into it.
+        assert_eq!(
+            resolve_link(&format!("{}/existing", home), None, &dotfiles_dir, None).unwrap(),
+            dir.path().join("home/existing")
+        );
+    }
+
+    #[test]
+    fn directories_are_only_read_once_when_matching_origin_case() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("Nvim")).unwrap();
+        fs::write(dir.path().join("Nvim/init.lua"), "").unwrap();
+
+        let mut listings = DirListings::new();
+        assert_eq!(
+            origin_case_on_disk(dir.path(), "nvim/init.lua", &mut listings),
+            Some(PathBuf::from("Nvim/init.lua"))
+        );
+        assert_eq!(listings.len(), 2);
+        // The dotfiles directory was already read, so a directory created since isn't seen
+        fs::create_dir(dir.path().join("Vim")).unwrap();
+        assert_eq!(origin_case_on_disk(dir.path(), "vim", &mut listings), None);
+        assert_eq!(
+            origin_case_on_disk(dir.path(), "vim", &mut DirListings::new()),
+            Some(PathBuf::from("Vim"))
+        );
+    }
+
+    #[test]
+    fn origins_named_in_a_different_case_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("Nvim")).unwrap();
+        fs::write(dotfiles_dir.join("Nvim/init.lua"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}, origin: nvim/INIT.lua}}\n",
+            dir.path().join("init.lua").display()
+        ))
+        .unwrap();
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        assert_eq!(
+            planned[0].origin,
+            fs::canonicalize(dotfiles_dir.join("Nvim/init.lua")).unwrap()
+        );
+        let options = PlanOptions {
+            case_sensitive: true,
+            ..PlanOptions::default()
+        };
+        match plan(&dotfiles_dir, &symlink_list, &options) {
+            Err(Error::LinkError {
+                cause: LinkFailure::OriginCaseMismatch(path),
+                ..
+            }) => assert_eq!(path, dotfiles_dir.join("Nvim/init.lua")),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn links_are_resolved_inside_of_a_target_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        let rootfs = dir.path().join("rootfs");
+        fs::create_dir(&rootfs).unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "links:\n  - {path: ~/.zshrc, origin: zshrc}\n  - {path: $XDG_CONFIG_HOME/zsh/, \
+             origin: zshrc}\n  - {path: /etc/../../etc/zshrc, origin: zshrc}\n",
+        )
+        .unwrap();
+        let options = PlanOptions {
+            root: Some(TargetRoot {
+                dir: rootfs.clone(),
+                home: PathBuf::from("/home/builder"),
+            }),
+            ..PlanOptions::default()
+        };
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &options).unwrap();
+        let links: Vec<_> = planned.iter().map(|planned| &planned.link).collect();
+        assert_eq!(
+            links,
+            [
+                &rootfs.join("home/builder/.zshrc"),
+                &rootfs.join("home/builder/.config/zsh/zshrc"),
+                &rootfs.join("etc/zshrc"),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicts_are_handled_as_the_policy_says() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("zshrc");
+        let link = dir.path().join(".zshrc");
+        fs::write(&origin, "export EDITOR=vim\n").unwrap();
+        fs::write(&link, "export EDITOR=nano\n").unwrap();
+        let action = |on_conflict| {
+            let policy = ConflictPolicy {
+                on_conflict,
+                ..ConflictPolicy::default()
+            };
+            choose_install_action(&origin, &link, LinkMode::Symlink, &policy)
+        };
+
+        assert_eq!(
+            action(OnConflict::Backup).unwrap(),
+            InstallAction::BackupAndLink
+        );
+        assert_eq!(
+            action(OnConflict::Overwrite).unwrap(),
+            InstallAction::OverwriteAndLink
+        );
+        assert_eq!(
+            action(OnConflict::Skip).unwrap(),
+            InstallAction::KeepExisting
+        );
+        assert!(matches!(
+            action(OnConflict::Fail),
+            Err(Error::LinkError {
+                cause: LinkFailure::Conflict,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unset_variables_are_reported_with_their_link() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("init.lua"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "links:\n  - {path: $DOTCONFIG_TEST_UNSET/nvim/, origin: init.lua}\n",
+        )
+        .unwrap();
+
+        let error = plan(&dir.path(), &symlink_list, &PlanOptions::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "In link for origin 'init.lua': environment variable $DOTCONFIG_TEST_UNSET is not set."
+        );
+    }
+
+    #[test]
+    fn existing_hard_links_are_recognized_by_inode() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = fs::canonicalize(dir.path()).unwrap().join("gitconfig");
+        let hard_link = dir.path().join(".gitconfig");
+        let symlink = dir.path().join(".gitconfig-symlink");
+        fs::write(&origin, "[user]\n").unwrap();
+        fs::hard_link(&origin, &hard_link).unwrap();
+        platform::symlink(&origin, &symlink).unwrap();
+        let policy = ConflictPolicy::default();
+
+        let action =
+            |link: &Path, mode| choose_install_action(&origin, link, mode, &policy).unwrap();
+        assert_eq!(action(&hard_link, LinkMode::Hardlink), InstallAction::Skip);
+        assert_eq!(
+            action(&hard_link, LinkMode::Symlink),
+            InstallAction::BackupAndLink
+        );
+        assert_eq!(
+            action(&symlink, LinkMode::Hardlink),
+            InstallAction::BackupAndLink
+        );
+        assert_eq!(action(&symlink, LinkMode::Symlink), InstallAction::Skip);
+    }
+
+    #[test]
+    fn links_inside_the_dotfiles_dir_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("zshrc"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}/new-dir/.zshrc, origin: zshrc}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            plan(&dir.path(), &symlink_list, &PlanOptions::default()),
+            Err(Error::LinkInsideDotfilesDir(_))
+        ));
+        let options = PlanOptions {
+            allow_links_inside_dotfiles_dir: true,
+            ..PlanOptions::default()
+        };
+        assert!(plan(&dir.path(), &symlink_list, &options).is_ok());
+    }
+
+    #[test]
+    fn links_are_left_out_unless_their_condition_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("nvim")).unwrap();
+        fs::create_dir(dir.path().join("installed")).unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}/a, origin: nvim, if_exists: {0}/installed}}\n  - {{path: \
+             {0}/b, origin: nvim, if_exists: {0}/missing}}\n  - {{path: {0}/c, origin: nvim, \
+             unless_exists: {0}/installed}}\n  - {{path: {0}/d, origin: nvim, unless_exists: \
+             {0}/missing}}\n  - {{path: {0}/e, origin: nvim, os: [{1}], arch: [{2}]}}\n  - {{path: \
+             {0}/f, origin: nvim, os: [plan9]}}\n  - {{path: {0}/g, origin: nvim, arch: [pdp11]}}\n",
+            dir.path().display(),
+            env::consts::OS.to_uppercase(),
+            env::consts::ARCH
+        ))
+        .unwrap();
+
+        let planned = plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()).unwrap();
+        let links: Vec<_> = planned.iter().map(|planned| &planned.link).collect();
+        assert_eq!(
+            links,
+            [
+                &dir.path().join("a"),
+                &dir.path().join("d"),
+                &dir.path().join("e")
+            ]
+        );
+    }
+
+    #[test]
+    fn links_inside_other_links_are_installed_after_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("nvim")).unwrap();
+        fs::write(dotfiles_dir.join("extra.lua"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {0}/nvim/lua/extra.lua, origin: extra.lua}}\n  - {{path: \
+             {0}/zshrc, origin: extra.lua}}\n  - {{path: {0}/nvim, origin: nvim}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+
+        // Once nvim is linked, extra.lua would be created in the dotfiles directory.
+        match plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()) {
+            Err(Error::LinkError {
+                cause: LinkFailure::InsideLinkedDirectory(path),
+                ..
+            }) => assert_eq!(path, dir.path().join("nvim")),
+            result => panic!("unexpected result {:?}", result),
+        }
+        let options = PlanOptions {
+            allow_links_inside_dotfiles_dir: true,
+            ..PlanOptions::default()
+        };
+        let planned = plan(&dotfiles_dir, &symlink_list, &options).unwrap();
+        let links: Vec<_> = planned.iter().map(|p| p.link.clone()).collect();
+        assert_eq!(
+            links,
+            [
+                dir.path().join("zshrc"),
+                dir.path().join("nvim"),
+                dir.path().join("nvim/lua/extra.lua"),
+            ]
+        );
+    }
+
+    #[test]
+    fn origins_outside_the_dotfiles_dir_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dir.path().join("secrets"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}, origin: ../secrets}}\n",
+            dir.path().join("link").display()
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            plan(&dotfiles_dir, &symlink_list, &PlanOptions::default()),
+            Err(Error::OriginOutsideDotfilesDir(_))
+        ));
+        let options = PlanOptions {
+            allow_external_origins: true,
+            ..PlanOptions::default()
+        };
+        assert!(plan(&dotfiles_dir, &symlink_list, &options).is_ok());
+    }
+}
@@ -0,0 +1,241 @@
+//! The filesystem and process operations that work differently on Unix and on Windows.
+//!
+//! On Windows, creating a symlink takes Developer Mode or an elevated prompt. Without either,
+//! [`symlink`] links a directory with a junction, and copies a file.
+
+use std::{
+    fs::{self, File, Metadata},
+    io,
+    path::Path,
+    process::Command,
+};
+
+/// What [`symlink`] created at a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Linked {
+    /// A symlink to the origin.
+    Symlink,
+    /// A junction to the origin, which is a directory. It reads as a symlink to the origin.
+    Junction,
+    /// A copy of the origin, which is a file.
+    Copy,
+}
+
+/// Whether [`symlink`] copies files that it can't link, so that a copy of an origin may be how a
+/// symlink to it was installed.
+pub(crate) const SYMLINKS_MAY_BE_COPIES: bool = cfg!(windows);
+
+/// Create a symlink at `link` to `origin`. A relative `origin` is relative to the directory `link`
+/// is in.
+///
+/// On Windows, a directory symlink is created if `origin` is a directory, and a file symlink
+/// otherwise. If symlinks can't be created, a directory is linked with a junction instead, and a
+/// file is copied. Returns which of these was created.
+///
+/// # Errors
+/// + [`io::Error`] if the link cannot be created.
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(origin: P, link: Q) -> io::Result<Linked> {
+    let (origin, link) = (origin.as_ref(), link.as_ref());
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(origin, link).map(|()| Linked::Symlink)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::{symlink_dir, symlink_file};
+        // ERROR_PRIVILEGE_NOT_HELD, returned without Developer Mode or an elevated prompt
+        const PRIVILEGE_NOT_HELD: i32 = 1314;
+
+        let target = link.parent().unwrap_or(Path::new("")).join(origin);
+        let is_dir = target.is_dir();
+        let result = if is_dir {
+            symlink_dir(origin, link)
+        } else {
+            symlink_file(origin, link)
+        };
+        match result {
+            Ok(()) => Ok(Linked::Symlink),
+            Err(e) if e.raw_os_error() == Some(PRIVILEGE_NOT_HELD) && is_dir => {
+                junction(&target, link).map(|()| Linked::Junction)
+            }
+            Err(e) if e.raw_os_error() == Some(PRIVILEGE_NOT_HELD) => {
+                fs::copy(&target, link).map(|_| Linked::Copy)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Create a junction at `link` to the directory `target`, which takes no privileges.
+#[cfg(windows)]
+fn junction(target: &Path, link: &Path) -> io::Result<()> {
+    let status = Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(target)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "mklink /J exited with {}",
+            status
+        )))
+    }
+}
+
+/// Returns whether `a` and `b` are the metadata of the same file, i.e. hard links to the same
+/// inode, or on Windows to the same file record on the same volume.
+pub(crate) fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        a.dev() == b.dev() && a.ino() == b.ino()
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        a.volume_serial_number().is_some()
+            && a.volume_serial_number() == b.volume_serial_number()
+            && a.file_index() == b.file_index()
+    }
+}
+
+/// Returns the device, or on Windows the volume, that the file of `metadata` is on.
+pub(crate) fn device(metadata: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.dev())
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        metadata.volume_serial_number().map(u64::from)
+    }
+}
+
+/// Returns the inode and the modification time, in seconds and nanoseconds, of the file of
+/// `metadata`. On Windows, the file index stands in for the inode, and the last write time, in
+/// 100-nanosecond intervals, for the modification time.
+pub(crate) fn stamp(metadata: &Metadata) -> (u64, i64, i64) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.ino(), metadata.mtime(), metadata.mtime_nsec())
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        (
+            metadata.file_index().unwrap_or_default(),
+            metadata.last_write_time() as i64,
+            0,
+        )
+    }
+}
+
+/// Returns whether files can be created in the existing directory `dir`, as `access(2)` tells
+/// without changing anything, or on Windows, as the read-only attribute tells. Only a permission
+/// error counts as not writable, since other errors will likely be reported more clearly when
+/// installing.
+pub(crate) fn is_writable(dir: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::{ffi::CString, io::ErrorKind, os::unix::ffi::OsStrExt};
+        let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+            return true;
+        };
+        // SAFETY: `path` is a NUL-terminated string that outlives the call.
+        if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+            return true;
+        }
+        !matches!(
+            io::Error::last_os_error().kind(),
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+        )
+    }
+    #[cfg(windows)]
+    {
+        fs::metadata(dir).map_or(true, |metadata| !metadata.permissions().readonly())
+    }
+}
+
+/// Returns the permissions and owner of the file of `metadata`, e.g. `mode 644, owner 1000:1000`,
+/// or on Windows, whether it is read-only.
+pub(crate) fn describe_permissions(metadata: &Metadata) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        format!(
+            "mode {:o}, owner {}:{}",
+            metadata.mode() & 0o7777,
+            metadata.uid(),
+            metadata.gid()
+        )
+    }
+    #[cfg(windows)]
+    {
+        if metadata.permissions().readonly() {
+            "read-only".to_owned()
+        } else {
+            "writable".to_owned()
+        }
+    }
+}
+
+/// Create a new file at `path` that only its owner can read and write. On Windows, the file gets
+/// the permissions of the directory it is in.
+///
+/// # Errors
+/// + [`io::Error`] if something is already at `path`, or the file cannot be created.
+pub(crate) fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Let the file at `path` be run as a program. On Windows, whether a file can be run depends on its
+/// extension, so nothing is changed.
+///
+/// # Errors
+/// + [`io::Error`] if the permissions of the file cannot be changed.
+pub(crate) fn make_executable(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+    }
+    #[cfg(windows)]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Returns the name of the current user, or `None` if it can't be found.
+pub fn current_username() -> Option<String> {
+    #[cfg(unix)]
+    {
+        users::get_current_username().map(|name| name.to_string_lossy().into_owned())
+    }
+    #[cfg(windows)]
+    {
+        std::env::var("USERNAME").ok()
+    }
+}
+
+/// Returns a command that runs `command` with the shell: `sh -c` on Unix, and `cmd /C` on
+/// Windows.
+pub(crate) fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    let (program, flag) = ("sh", "-c");
+    #[cfg(windows)]
+    let (program, flag) = ("cmd", "/C");
+    let mut shell = Command::new(program);
+    shell.args([flag, command]);
+    shell
+}
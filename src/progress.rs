@@ -0,0 +1,70 @@
+//! Feedback for filesystem operations that may take a while.
+
+use crate::log::{self, Level};
+use std::{
+    io::{stdout, IsTerminal, Write},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How long an operation may run before the spinner appears. Most operations finish well within
+/// this, so they produce no extra output at all.
+const SPINNER_DELAY: Duration = Duration::from_millis(250);
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+const SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// A spinner drawn on its own line of stdout, after a short description of the operation that is
+/// running.
+///
+/// The spinner is only drawn when stdout is a terminal and informational output is enabled. The
+/// line is cleared when the spinner is dropped, so that the spinner leaves no trace in the output.
+pub(crate) struct Spinner {
+    done: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    pub(crate) fn start(label: String) -> Self {
+        if !stdout().is_terminal() || !log::enabled(Level::Info) {
+            return Spinner {
+                done: None,
+                handle: None,
+            };
+        }
+        let (done, waiting) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            if waiting.recv_timeout(SPINNER_DELAY) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                draw(&format!("\r{} {}", label, frame));
+                if waiting.recv_timeout(SPINNER_INTERVAL) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            draw("\r\x1b[2K");
+        });
+        Spinner {
+            done: Some(done),
+            handle: Some(handle),
+        }
+    }
+}
+
+fn draw(s: &str) {
+    let _guard = log::lock();
+    let mut out = stdout().lock();
+    write!(out, "{}", s).ok();
+    out.flush().ok();
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the spinner thread up.
+        self.done.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
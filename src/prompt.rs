@@ -0,0 +1,67 @@
+//! Asking the user to confirm before anything is changed.
+
+use crate::{log, Error, Result};
+use std::io::{self, stdin, stdout, BufRead, IsTerminal, Write};
+use yansi::Paint;
+
+/// Ask the user a yes or no question. An empty answer counts as `default`, but if stdin is closed
+/// before anything is answered, the answer is no, so that nothing is changed without an answer.
+///
+/// The question is asked even when the log level hides info messages, since an answer is required
+/// to continue.
+///
+/// # Errors
+/// + [`Error::NotATerminal`] if stdin is not a terminal, so that a script doesn't answer with
+///   whatever happens to be on its stdin.
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    if !stdin().is_terminal() {
+        return Err(Error::NotATerminal);
+    }
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    stdout().flush().ok();
+    let answer = match read_answer(stdin().lock(), default)? {
+        Some(answer) => answer,
+        None => {
+            println!();
+            log::warn(Paint::yellow(
+                "Standard input is closed, so the answer is no.",
+            ));
+            false
+        }
+    };
+    log::report(format!(
+        "{} {}",
+        question,
+        if answer { "yes" } else { "no" }
+    ));
+    Ok(answer)
+}
+
+/// Read a line from `input` as the answer to a yes or no question. An empty line counts as
+/// `default`.
+///
+/// Returns `None` if `input` ends before a line is read, which is different from an empty line.
+fn read_answer(mut input: impl BufRead, default: bool) -> io::Result<Option<bool>> {
+    let mut s = String::new();
+    if input.read_line(&mut s)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(match s.trim().to_lowercase().as_str() {
+        "" => default,
+        "y" | "yes" => true,
+        _ => false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_stdin_is_not_an_empty_answer() {
+        assert_eq!(read_answer(io::empty(), true).unwrap(), None);
+        assert_eq!(read_answer(&b"\n"[..], true).unwrap(), Some(true));
+        assert_eq!(read_answer(&b"n\n"[..], true).unwrap(), Some(false));
+        assert_eq!(read_answer(&b"yes"[..], false).unwrap(), Some(true));
+    }
+}
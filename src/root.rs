@@ -0,0 +1,91 @@
+//! Finding the home directory to install links into when installing into another root directory.
+
+use crate::{expand_path, platform, Error, Result, TargetRoot};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Returns the root directory to install into, with the home directory `home`, or else the home
+/// directory of the current user in the root's `/etc/passwd`, or else the same path as the home
+/// directory on this machine.
+///
+/// # Errors
+/// + [`Error::MissingRoot`] if `dir` is not a directory.
+/// + [`Error::UnknownHomeDir`] if `home` isn't given, and the home directory cannot be determined.
+pub fn target_root(dir: &Path, home: Option<&Path>) -> Result<TargetRoot> {
+    if !dir.is_dir() {
+        return Err(Error::MissingRoot(dir.to_owned()));
+    }
+    let from_passwd = || {
+        let user = platform::current_username()?;
+        let passwd = fs::read_to_string(dir.join("etc/passwd")).ok()?;
+        home_in_passwd(&passwd, &user)
+    };
+    let home = match home.map(Path::to_owned).or_else(from_passwd) {
+        Some(home) => home,
+        None => expand_path(&"$HOME")?,
+    };
+    Ok(TargetRoot {
+        dir: dir.to_owned(),
+        home,
+    })
+}
+
+/// Returns the home directory of `user` in `passwd`, the contents of an `/etc/passwd` file, if the
+/// user is in it.
+fn home_in_passwd(passwd: &str, user: &str) -> Option<PathBuf> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<_> = line.split(':').collect();
+        (fields.len() == 7 && fields[0] == user).then(|| PathBuf::from(fields[5]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn home_is_found_in_passwd() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n\
+                      # a comment\n\
+                      me:x:1000:1000:Me,,,:/home/me:/bin/zsh\n";
+        assert_eq!(
+            home_in_passwd(passwd, "me"),
+            Some(PathBuf::from("/home/me"))
+        );
+        assert_eq!(home_in_passwd(passwd, "root"), Some(PathBuf::from("/root")));
+        assert_eq!(home_in_passwd(passwd, "nobody"), None);
+        assert_eq!(home_in_passwd("me:/home/me", "me"), None);
+    }
+
+    #[test]
+    fn given_home_is_used_over_passwd() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("etc")).unwrap();
+        let user = platform::current_username().unwrap_or_default();
+        fs::write(
+            dir.path().join("etc/passwd"),
+            format!("{}:x:1000:1000::/home/passwd:/bin/sh\n", user),
+        )
+        .unwrap();
+
+        let root = target_root(dir.path(), Some(Path::new("/home/given"))).unwrap();
+        assert_eq!(root.dir, dir.path());
+        assert_eq!(root.home, PathBuf::from("/home/given"));
+        if !user.is_empty() {
+            let root = target_root(dir.path(), None).unwrap();
+            assert_eq!(root.home, PathBuf::from("/home/passwd"));
+        }
+    }
+
+    #[test]
+    fn missing_root_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            target_root(&missing, None),
+            Err(Error::MissingRoot(path)) if path == missing
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+//! Shell commands equivalent to installing a link.
+
+use crate::{
+    decrypt,
+    executor::reload_commands,
+    manifest::Operation,
+    planner::{install_operations, ConflictPolicy, InstallAction, PlannedAction},
+    Result,
+};
+use std::{borrow::Cow, path::Path};
+
+/// Returns an `sh` script that installs every link in `plan`, then runs the reload commands of the
+/// links it changes. Links that are already installed are listed in comments.
+///
+/// Files in the way are backed up next to their link, as with `--backup-in-place`, rather than
+/// into the backup directory, since the script can't record them in its index. `uninstall
+/// --restore` finds them there all the same.
+///
+/// # Errors
+/// + [`Error::LinkError`](crate::Error::LinkError) if a link is not a valid path.
+pub fn render(plan: &[PlannedAction]) -> Result<String> {
+    let mut script = format!(
+        "#!/bin/sh\n# Generated by dotconfig {} on {}.\nset -e\n",
+        env!("CARGO_PKG_VERSION"),
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    for PlannedAction {
+        origin,
+        link,
+        action,
+        mode,
+        policy,
+        ..
+    } in plan
+    {
+        script.push('\n');
+        if *action == InstallAction::Skip {
+            script.push_str(&format!(
+                "# Already linked: {} -> {}\n",
+                link.display(),
+                origin.display()
+            ));
+        } else if *action == InstallAction::KeepExisting {
+            script.push_str(&format!("# Kept existing file: {}\n", link.display()));
+        }
+        let policy = ConflictPolicy {
+            backup_dir: None,
+            ..policy.clone()
+        };
+        for operation in install_operations(origin, link, *action, *mode, &policy)? {
+            script.push_str(&command(&operation));
+            script.push('\n');
+        }
+    }
+    let reloads = reload_commands(plan.iter().filter(|p| !p.action.is_noop()));
+    if !reloads.is_empty() {
+        script.push_str("\n# Reload\n");
+        for command in reloads {
+            script.push_str(command);
+            script.push('\n');
+        }
+    }
+    Ok(script)
+}
+
+/// Returns the `sh` command that performs `operation`, with all paths quoted.
+pub fn command(operation: &Operation) -> String {
+    match operation {
+        Operation::CreateDir { path } => format!("mkdir {}", quote_path(path)),
+        Operation::Backup { from, to } => format!("mv {} {}", quote_path(from), quote_path(to)),
+        Operation::Remove { path } => format!("rm -rf {}", quote_path(path)),
+        Operation::Link { link, origin } => {
+            format!("ln -s {} {}", quote_path(origin), quote_path(link))
+        }
+        Operation::HardLink { link, origin } => {
+            format!("ln {} {}", quote_path(origin), quote_path(link))
+        }
+        Operation::Copy { link, origin } => {
+            format!("cp -p {} {}", quote_path(origin), quote_path(link))
+        }
+        Operation::Decrypt { link, origin } => {
+            let (program, args) = decrypt::program(origin);
+            format!(
+                "(umask 077 && {} {} --output {} {})",
+                program,
+                args.join(" "),
+                quote_path(link),
+                quote_path(origin)
+            )
+        }
+    }
+}
+
+/// Quotes `s` so that `sh` reads it as a single word. Strings made up entirely of characters that
+/// are never special to the shell are returned as they are.
+pub fn quote(s: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c);
+    if !s.is_empty() && s.chars().all(is_safe) {
+        s.into()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''")).into()
+    }
+}
+
+fn quote_path(path: &Path) -> String {
+    quote(&path.to_string_lossy()).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SymlinkList,
+        planner::{plan, PlanOptions},
+    };
+    use std::fs;
+
+    #[test]
+    fn quote_only_quotes_when_needed() {
+        assert_eq!(quote("/home/me/.config/nvim"), "/home/me/.config/nvim");
+        assert_eq!(quote("/home/me/My Files"), "'/home/me/My Files'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn files_in_the_way_are_backed_up_next_to_their_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        fs::write(dir.path().join(".zshrc"), "in the way").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}/.zshrc, origin: zshrc}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let options = PlanOptions {
+            backup_dir: Some(dir.path().join("backups")),
+            ..PlanOptions::default()
+        };
+
+        let script = render(&plan(&dotfiles_dir, &symlink_list, &options).unwrap()).unwrap();
+        let backup = format!("mv {0}/.zshrc {0}/.zshrc-backup-", dir.path().display());
+        assert!(script.contains(&backup), "{}", script);
+        assert!(!script.contains("backups"), "{}", script);
+    }
+}
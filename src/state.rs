@@ -0,0 +1,208 @@
+//! A record of every symlink that dotconfig has installed, and when, kept across runs. Unlike the
+//! filesystem, it tells the links that dotconfig created apart from symlinks that were made by
+//! hand, even after they are removed from the symlink list.
+
+use crate::{expand_path, log, normalize, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// The symlinks that were installed, sorted by the path they were installed at.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub links: Vec<Installed>,
+}
+
+/// A symlink that was installed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Installed {
+    /// Where the symlink was created.
+    pub link: PathBuf,
+    /// The origin it points to.
+    pub origin: PathBuf,
+    /// When it was first installed to `origin`, in RFC 3339 format.
+    pub installed: String,
+}
+
+impl Installed {
+    /// Returns whether the symlink is still at `link` and points to `origin`.
+    pub fn is_installed(&self) -> bool {
+        let Ok(target) = fs::read_link(&self.link) else {
+            return false;
+        };
+        let parent = self.link.parent().unwrap_or(Path::new("/"));
+        let target = normalize(&parent.join(target));
+        target == self.origin || fs::canonicalize(&target).ok().as_ref() == Some(&self.origin)
+    }
+}
+
+impl State {
+    /// Returns the default path of the state file, `$XDG_STATE_HOME/dotconfig/state.yml`.
+    ///
+    /// # Errors
+    /// + [`Error::UnknownHomeDir`](crate::Error::UnknownHomeDir) if the home directory cannot be
+    ///   determined.
+    pub fn path() -> Result<PathBuf> {
+        expand_path(&"$XDG_STATE_HOME/dotconfig/state.yml")
+    }
+
+    /// Read the state from a file. A state file that doesn't exist yet is empty.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the file exists but cannot be read.
+    /// + [`Error::YamlError`](crate::Error::YamlError) if the file is not a valid state file.
+    pub fn from_file<P>(path: &P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Write the state to a file, creating its directory if needed and replacing the file if it
+    /// exists.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the file cannot be written.
+    pub fn write<P>(&self, path: &P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(fs::write(path, serde_yaml::to_string(self)?)?)
+    }
+
+    /// Record that a symlink to `origin` is installed at `link`. If it was already recorded with
+    /// the same origin, it keeps the date it was first installed.
+    pub fn record(&mut self, origin: &Path, link: &Path) {
+        match self
+            .links
+            .binary_search_by(|other| other.link.as_path().cmp(link))
+        {
+            Ok(i) if self.links[i].origin == origin => {}
+            Ok(i) => {
+                self.links[i].origin = origin.to_owned();
+                self.links[i].installed = chrono::Local::now().to_rfc3339();
+            }
+            Err(i) => self.links.insert(
+                i,
+                Installed {
+                    link: link.to_owned(),
+                    origin: origin.to_owned(),
+                    installed: chrono::Local::now().to_rfc3339(),
+                },
+            ),
+        }
+    }
+
+    /// Remove the symlink at `link` from the state, if it is there.
+    pub fn forget(&mut self, link: &Path) {
+        self.links.retain(|installed| installed.link != link);
+    }
+
+    /// Remove the symlinks that have since been removed or replaced from the state.
+    pub fn retain_installed(&mut self) {
+        self.links.retain(Installed::is_installed);
+    }
+
+    /// Returns the symlinks into `dotfiles_dir` that are still installed, but aren't at any of
+    /// `links`, i.e. whose links have been removed from the symlink list since they were installed.
+    pub fn orphans(&self, dotfiles_dir: &Path, links: &[PathBuf]) -> Vec<&Installed> {
+        let canonical_dir =
+            fs::canonicalize(dotfiles_dir).unwrap_or_else(|_| dotfiles_dir.to_owned());
+        self.links
+            .iter()
+            .filter(|installed| {
+                (installed.origin.starts_with(dotfiles_dir)
+                    || installed.origin.starts_with(&canonical_dir))
+                    && !links.contains(&installed.link)
+                    && installed.is_installed()
+            })
+            .collect()
+    }
+}
+
+/// Returns the path of the state file, `path` or else the default one, and the state read from it.
+/// A state file that can't be read is only warned about, and is then neither used nor overwritten.
+pub fn read(path: Option<&Path>) -> Option<(PathBuf, State)> {
+    let path = match path {
+        Some(path) => Ok(path.to_owned()),
+        None => State::path(),
+    };
+    match path.and_then(|path| Ok((State::from_file(&path)?, path))) {
+        Ok((state, path)) => Some((path, state)),
+        Err(e) => {
+            log::warn(format!(
+                "{} {}",
+                Paint::yellow("Failed to read the state file:"),
+                e
+            ));
+            None
+        }
+    }
+}
+
+/// Write `state` to the state file at `path`. A state file that can't be written is only warned
+/// about, since the links are installed or removed all the same.
+pub fn write(state: &State, path: &Path) {
+    if let Err(e) = state.write(&path) {
+        log::warn(format!(
+            "{} {}: {}",
+            Paint::yellow("Failed to write the state file"),
+            path.display(),
+            e
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::symlink;
+
+    #[test]
+    fn symlinks_are_recorded_until_they_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        let (zshrc, vimrc) = (dotfiles_dir.join("zshrc"), dotfiles_dir.join("vimrc"));
+        fs::write(&zshrc, "").unwrap();
+        fs::write(&vimrc, "").unwrap();
+        let (zshrc_link, vimrc_link) = (dir.path().join(".zshrc"), dir.path().join(".vimrc"));
+        symlink(&zshrc, &zshrc_link).unwrap();
+        symlink("cfg/vimrc", &vimrc_link).unwrap();
+
+        let mut state = State::default();
+        state.record(&zshrc, &zshrc_link);
+        state.record(&vimrc, &vimrc_link);
+        let installed = state.links[1].installed.clone();
+        state.record(&zshrc, &zshrc_link);
+        assert_eq!(state.links[1].installed, installed);
+        assert_eq!(state.links[0].link, vimrc_link);
+        let path = dir.path().join("state/state.yml");
+        state.write(&path).unwrap();
+
+        let mut state = State::from_file(&path).unwrap();
+        assert_eq!(state.links.len(), 2);
+        let orphans = state.orphans(&dotfiles_dir, std::slice::from_ref(&zshrc_link));
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].link, vimrc_link);
+        assert!(state.orphans(&dir.path().join("other"), &[]).is_empty());
+
+        fs::remove_file(&zshrc_link).unwrap();
+        symlink(&vimrc, &zshrc_link).unwrap();
+        state.retain_installed();
+        assert_eq!(state.links.len(), 1);
+        state.forget(&vimrc_link);
+        assert_eq!(state, State::default());
+    }
+}
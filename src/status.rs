@@ -0,0 +1,392 @@
+//! Whether each link in a symlink list is installed, without changing anything.
+
+use crate::{
+    config::{Source, SymlinkList},
+    log::{self, Level, Status},
+    planner::{
+        choose_install_action, resolve_link, unmet_condition, ConflictPolicy, InstallAction,
+        LinkMode,
+    },
+    template::{self, Rendered},
+    Result, TargetRoot,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// The state of a single link on disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Health {
+    /// The link is installed.
+    Linked,
+    /// Nothing is at the link's path.
+    Missing,
+    /// A symlink to `target` is at the link's path, and nothing is at `target`.
+    Broken { target: PathBuf },
+    /// A symlink to `target` is at the link's path, rather than to the origin.
+    Elsewhere { target: PathBuf },
+    /// A file or directory that isn't the link is at the link's path.
+    Shadowed,
+    /// The link points at a rendered template whose contents are out of date, since the template
+    /// or its variables changed after it was rendered.
+    Stale,
+}
+
+impl Health {
+    /// Returns whether the link is installed.
+    pub fn is_linked(&self) -> bool {
+        *self == Health::Linked
+    }
+}
+
+/// The [`Health`] of one path of a link.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkStatus {
+    /// The path the link is installed at, with all shell variables expanded.
+    pub link: PathBuf,
+    /// The path of the origin, joined onto the dotfiles directory.
+    pub origin: PathBuf,
+    pub health: Health,
+    /// The [`Link::description`](crate::config::Link::description) of the link.
+    pub description: Option<String>,
+}
+
+/// Returns the status of each path of each link in `symlink_list`, in order, with the links whose
+/// origin is a glob or that are [`DirMode::Recurse`](crate::config::DirMode::Recurse) expanded into
+/// a link per file. Links that would be left out of an installation, because they are optional and
+/// their origin doesn't exist or because of their `if_exists` or `unless_exists`, are left out.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory the origins are in.
+/// + `root` - The directory the links are installed inside of, if any (see [`TargetRoot`]).
+/// + `mode` - How links that aren't encrypted and have no `strategy` are created.
+///
+/// # Errors
+/// + [`Error::LinkError`](crate::Error::LinkError) if a link's `path` and `rename` don't specify
+///   exactly one filename.
+/// + [`Error::UnsetVariable`](crate::Error::UnsetVariable) if a path references an unset shell
+///   variable.
+/// + [`Error::IoError`](crate::Error::IoError) if a symlink cannot be read.
+pub fn status(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    root: Option<&TargetRoot>,
+    mode: LinkMode,
+) -> Result<Vec<LinkStatus>> {
+    let symlink_list = symlink_list.expand_origins(&dotfiles_dir)?;
+    let mut statuses = vec![];
+    for entry in &symlink_list.links {
+        let origin = dotfiles_dir.join(&entry.origin);
+        if (entry.optional() && !origin.exists()) || unmet_condition(entry, root)?.is_some() {
+            continue;
+        }
+        let mode = entry.link_mode(mode);
+        let canonical_origin = fs::canonicalize(&origin).ok();
+        for path in &entry.path {
+            let link = resolve_link(path, entry.rename.as_deref(), &origin, root)?;
+            let health = match fs::symlink_metadata(&link) {
+                Err(_) => Health::Missing,
+                Ok(metadata) if metadata.file_type().is_symlink() && mode == LinkMode::Symlink => {
+                    let target = fs::read_link(&link)?;
+                    let absolute = link.parent().unwrap_or(Path::new("/")).join(&target);
+                    match fs::canonicalize(&absolute) {
+                        Err(_) => Health::Broken { target },
+                        Ok(canonical)
+                            if absolute == origin
+                                || Some(&canonical) == canonical_origin.as_ref() =>
+                        {
+                            Health::Linked
+                        }
+                        Ok(_) => Health::Elsewhere { target },
+                    }
+                }
+                // Hard links, copies and decrypted files are installed if they match the origin
+                Ok(_) => match &canonical_origin {
+                    Some(canonical_origin) => {
+                        match choose_install_action(
+                            canonical_origin,
+                            &link,
+                            mode,
+                            &ConflictPolicy::default(),
+                        ) {
+                            // Decrypting may ask for a passphrase, so a decrypted file is only
+                            // compared if its origin has already been decrypted.
+                            Ok(InstallAction::Skip | InstallAction::DecryptAndCompare) => {
+                                Health::Linked
+                            }
+                            _ => Health::Shadowed,
+                        }
+                    }
+                    None => Health::Shadowed,
+                },
+            };
+            statuses.push(LinkStatus {
+                link,
+                origin: origin.clone(),
+                health,
+                description: entry.description.clone(),
+            });
+        }
+    }
+    Ok(statuses)
+}
+
+/// Returns the status of every link in the symlink lists from `source`, as by [`status`], with
+/// those whose templates have changed since they were rendered marked as stale.
+///
+/// # Errors
+/// + Any error from [`Source::read`].
+/// + Any error from [`template::render_all`], which renders templates in memory to compare them.
+/// + Any error from [`status`].
+pub fn check(
+    source: &Source,
+    root: Option<&TargetRoot>,
+    mode: LinkMode,
+) -> Result<Vec<LinkStatus>> {
+    let (mut symlink_list, origin_dir) = source.read()?;
+    let host = source.host.as_deref().unwrap_or_default();
+    let variables = template::variables(&symlink_list.vars, host);
+    // Rendered in memory, to compare with the rendered files without writing them
+    let rendered = template::render_all(&origin_dir, &mut symlink_list, &variables)?;
+    let mut statuses = status(&origin_dir, &symlink_list, root, mode)?;
+    mark_stale(&mut statuses, &rendered);
+    Ok(statuses)
+}
+
+/// Returns `statuses` as a JSON array, with the link, origin, health and description of each link,
+/// and the target of each symlink that is broken or points elsewhere.
+///
+/// # Errors
+/// + [`Error::JsonError`](crate::Error::JsonError) if a status cannot be serialized.
+pub fn json(statuses: &[LinkStatus]) -> Result<String> {
+    let links: Vec<_> = statuses
+        .iter()
+        .map(|status| {
+            let (health, target) = match &status.health {
+                Health::Linked => ("linked", None),
+                Health::Missing => ("missing", None),
+                Health::Broken { target } => ("broken", Some(target)),
+                Health::Elsewhere { target } => ("elsewhere", Some(target)),
+                Health::Shadowed => ("shadowed", None),
+                Health::Stale => ("stale", None),
+            };
+            serde_json::json!({
+                "link": status.link,
+                "origin": status.origin,
+                "health": health,
+                "target": target,
+                "description": status.description,
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&links)?)
+}
+
+/// Mark each of `statuses` that is linked to a file in `rendered` that doesn't have its rendered
+/// contents yet as [`Health::Stale`]. Nothing is written.
+pub fn mark_stale(statuses: &mut [LinkStatus], rendered: &[Rendered]) {
+    for status in statuses
+        .iter_mut()
+        .filter(|status| status.health.is_linked())
+    {
+        if rendered
+            .iter()
+            .any(|rendered| rendered.path == status.origin && !rendered.is_current())
+        {
+            status.health = Health::Stale;
+        }
+    }
+}
+
+/// Print a line for each of `statuses`, followed by how many links aren't installed.
+///
+/// # Params
+/// + `quiet_skip` - Only print the links that are installed at the debug level.
+pub fn report(statuses: &[LinkStatus], quiet_skip: bool) {
+    for LinkStatus {
+        link,
+        origin,
+        health,
+        description,
+    } in statuses
+    {
+        let description = match description {
+            Some(description) => format!("  {}", Paint::new(description).dimmed()),
+            None => String::new(),
+        };
+        match health {
+            Health::Linked => log::log(
+                if quiet_skip {
+                    Level::Debug
+                } else {
+                    Level::Info
+                },
+                format!(
+                    "{}{} {} {} {}{}",
+                    log::symbol(Status::Skip),
+                    Paint::green("Linked:   "),
+                    link.display(),
+                    Paint::green("->"),
+                    origin.display(),
+                    description
+                ),
+            ),
+            Health::Missing => log::warn(format!(
+                "{}{} {}{}",
+                log::symbol(Status::Link),
+                Paint::yellow("Missing:  "),
+                link.display(),
+                description
+            )),
+            Health::Broken { target } => log::warn(format!(
+                "{}{} {} {} {}{}",
+                log::symbol(Status::Remove),
+                Paint::red("Broken:   "),
+                link.display(),
+                Paint::red("->"),
+                target.display(),
+                description
+            )),
+            Health::Elsewhere { target } => log::warn(format!(
+                "{}{} {} {} {} {} {}{}",
+                log::symbol(Status::Backup),
+                Paint::yellow("Elsewhere:"),
+                link.display(),
+                Paint::yellow("->"),
+                target.display(),
+                Paint::yellow("instead of"),
+                origin.display(),
+                description
+            )),
+            Health::Shadowed => log::warn(format!(
+                "{}{} {} {}{}",
+                log::symbol(Status::Keep),
+                Paint::yellow("Shadowed: "),
+                link.display(),
+                Paint::yellow("is a file that isn't linked to the origin"),
+                description
+            )),
+            Health::Stale => log::warn(format!(
+                "{}{} {} {} {} {}{}",
+                log::symbol(Status::Link),
+                Paint::yellow("Stale:    "),
+                link.display(),
+                Paint::yellow("->"),
+                origin.display(),
+                Paint::yellow("is out of date with its template"),
+                description
+            )),
+        }
+    }
+    let out_of_sync = statuses
+        .iter()
+        .filter(|status| !status.health.is_linked())
+        .count();
+    if out_of_sync == 0 {
+        log::info(Paint::green(format!(
+            "All {} are installed.",
+            log::count(statuses.len(), "link")
+        )));
+    } else {
+        log::info(format!(
+            "{} of {} not installed.",
+            out_of_sync,
+            log::count(statuses.len(), "link")
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{platform::symlink, template};
+
+    #[test]
+    fn the_health_of_each_link_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        let home = dir.path().join("home");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::create_dir(&home).unwrap();
+        for origin in ["zshrc", "vimrc", "gitconfig"] {
+            fs::write(dotfiles_dir.join(origin), "").unwrap();
+        }
+        symlink(dotfiles_dir.join("zshrc"), home.join(".zshrc")).unwrap();
+        symlink(dir.path().join("gone"), home.join(".bashrc")).unwrap();
+        symlink(dotfiles_dir.join("zshrc"), home.join(".vimrc")).unwrap();
+        fs::write(home.join(".gitconfig"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  \
+             - {{path: {0}/.zshrc, origin: zshrc, description: Shell}}\n  \
+             - {{path: {0}/.profile, origin: zshrc}}\n  \
+             - {{path: {0}/.bashrc, origin: bashrc}}\n  \
+             - {{path: {0}/.vimrc, origin: vimrc}}\n  \
+             - {{path: {0}/.gitconfig, origin: gitconfig}}\n  \
+             - {{path: {0}/.tmux.conf, origin: tmux.conf, optional: true}}\n",
+            home.display()
+        ))
+        .unwrap();
+
+        let statuses = status(&dotfiles_dir, &symlink_list, None, LinkMode::Symlink).unwrap();
+        assert_eq!(statuses[0].description.as_deref(), Some("Shell"));
+        let json: serde_json::Value = serde_json::from_str(&json(&statuses).unwrap()).unwrap();
+        assert_eq!(json[0]["health"], "linked");
+        assert_eq!(json[0]["description"], "Shell");
+        assert_eq!(json[2]["health"], "broken");
+        assert_eq!(json[2]["target"], dir.path().join("gone").to_str().unwrap());
+        let health: Vec<_> = statuses.into_iter().map(|status| status.health).collect();
+        assert_eq!(
+            health,
+            vec![
+                Health::Linked,
+                Health::Missing,
+                Health::Broken {
+                    target: dir.path().join("gone")
+                },
+                Health::Elsewhere {
+                    target: dotfiles_dir.join("zshrc")
+                },
+                Health::Shadowed,
+            ]
+        );
+    }
+
+    #[test]
+    fn links_to_out_of_date_templates_are_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::write(dotfiles_dir.join("gitconfig"), "email = {{ email }}\n").unwrap();
+        let mut symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "vars:\n  email: me@home.example\nlinks:\n  - {{path: {}/.gitconfig, origin: \
+             gitconfig, template: true}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let variables = template::variables(&symlink_list.vars, "laptop");
+        let rendered = template::render_all(&dotfiles_dir, &mut symlink_list, &variables).unwrap();
+        rendered[0].write().unwrap();
+        symlink(&rendered[0].path, dir.path().join(".gitconfig")).unwrap();
+        let health = |rendered: &[Rendered]| {
+            let mut statuses =
+                status(&dotfiles_dir, &symlink_list, None, LinkMode::Symlink).unwrap();
+            mark_stale(&mut statuses, rendered);
+            statuses[0].health.clone()
+        };
+        assert_eq!(health(&rendered), Health::Linked);
+
+        let mut variables = variables;
+        variables.insert("email".to_owned(), "me@work.example".to_owned());
+        let mut changed: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  - {{path: {}/.gitconfig, origin: gitconfig, template: true}}\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let rendered = template::render_all(&dotfiles_dir, &mut changed, &variables).unwrap();
+        assert_eq!(health(&rendered), Health::Stale);
+        // Checking the status doesn't write the rendered file
+        assert!(!rendered[0].is_current());
+    }
+}
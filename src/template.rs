@@ -0,0 +1,287 @@
+//! Origins that are templates, rendered with machine-specific variables before they are linked.
+
+use crate::{
+    config::{Hooks, Link, SymlinkList},
+    platform, Error, Result,
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, Permissions},
+    path::{Component, Path, PathBuf},
+};
+
+/// The name of the directory, in the directory the origins are in, that templates are rendered
+/// into.
+pub const DIR_NAME: &str = ".dotconfig-rendered";
+
+/// Returns the variables that templates can use: the built-ins `hostname`, `os`, `arch`, `user`
+/// and `home`, and `vars`, which take precedence over them. Environment variables are looked up
+/// as `env.<NAME>` when rendering.
+///
+/// # Params
+/// + `vars` - The variables under `vars:` in the symlink list.
+/// + `host` - The host whose links are installed.
+pub fn variables(vars: &BTreeMap<String, String>, host: &str) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::from([
+        ("hostname".to_owned(), host.to_owned()),
+        ("os".to_owned(), env::consts::OS.to_owned()),
+        ("arch".to_owned(), env::consts::ARCH.to_owned()),
+        (
+            "user".to_owned(),
+            platform::current_username().unwrap_or_default(),
+        ),
+        (
+            "home".to_owned(),
+            dirs_next::home_dir()
+                .map(|home| home.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+    ]);
+    variables.extend(vars.clone());
+    variables
+}
+
+/// Returns `template` with each `{{ name }}` replaced by the value of the variable `name`, or of
+/// the environment variable `NAME` for `{{ env.NAME }}`. Spaces inside of the braces are optional.
+/// Braces around anything other than a name are left as they are.
+///
+/// # Errors
+/// Returns the name of the first variable that is used but not set.
+pub fn render(template: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let name = after
+            .find("}}")
+            .map(|end| (after[..end].trim(), end))
+            .filter(|(name, _)| is_name(name));
+        let Some((name, end)) = name else {
+            out.push_str("{{");
+            rest = after;
+            continue;
+        };
+        let value = match name.strip_prefix("env.") {
+            Some(var) => env::var(var).ok(),
+            None => variables.get(name).cloned(),
+        };
+        out.push_str(&value.ok_or_else(|| name.to_owned())?);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Returns whether `name` can be the name of a variable in a template.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// A template rendered in memory, to be written into [`DIR_NAME`] when installing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rendered {
+    /// The path to the template.
+    pub template: PathBuf,
+    /// The path the rendered file is written to, which its link points at.
+    pub path: PathBuf,
+    /// The rendered contents.
+    pub contents: String,
+    /// The permissions of the template, which the rendered file gets.
+    pub permissions: Permissions,
+}
+
+impl Rendered {
+    /// Returns whether the file at [`Rendered::path`] already has the rendered contents.
+    pub fn is_current(&self) -> bool {
+        fs::read_to_string(&self.path).ok().as_deref() == Some(self.contents.as_str())
+    }
+
+    /// Write the rendered file, if its contents changed, and give it the permissions of its
+    /// template.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`] if the rendered file cannot be written.
+    pub fn write(&self) -> Result<()> {
+        if !self.is_current() {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&self.path, &self.contents)?;
+        }
+        fs::set_permissions(&self.path, self.permissions.clone())?;
+        Ok(())
+    }
+}
+
+/// Render the origin of each link in `symlink_list` that is [`Link::template`] in memory, and
+/// point the link at the file in [`DIR_NAME`] it is rendered into instead. Nothing is written:
+/// call [`Rendered::write`] on each returned template before installing. Globs and links that are
+/// [`DirMode::Recurse`](crate::config::DirMode::Recurse) are expanded first, so that each file is
+/// rendered. Templates that don't exist are left for planning to report.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory the origins are in.
+/// + `symlink_list` - The links whose templates to render.
+/// + `variables` - The variables templates can use (see [`variables`]).
+///
+/// # Errors
+/// + [`Error::UndefinedTemplateVariable`] if a template uses a variable that isn't set.
+/// + [`Error::IoError`] if a template cannot be read, e.g. because it isn't UTF-8 or is a
+///   directory.
+/// + Any error from [`SymlinkList::expand_origins`].
+pub fn render_all(
+    dotfiles_dir: &Path,
+    symlink_list: &mut SymlinkList,
+    variables: &BTreeMap<String, String>,
+) -> Result<Vec<Rendered>> {
+    let mut rendered = vec![];
+    if !symlink_list.links.iter().any(|link| link.template) {
+        return Ok(rendered);
+    }
+    let mut links = vec![];
+    for link in &symlink_list.links {
+        if !link.template {
+            links.push(link.clone());
+            continue;
+        }
+        let expanded = SymlinkList {
+            links: vec![link.clone()],
+            origin_base: None,
+            vars: BTreeMap::new(),
+            hooks: Hooks::default(),
+        }
+        .expand_origins(&dotfiles_dir)?;
+        for link in expanded.links {
+            links.push(match render_link(dotfiles_dir, &link, variables)? {
+                Some((origin, file)) => {
+                    rendered.push(file);
+                    Link { origin, ..link }
+                }
+                None => link,
+            });
+        }
+    }
+    symlink_list.links = links;
+    Ok(rendered)
+}
+
+/// Point each link in `symlink_list` whose file in `rendered` hasn't been written yet back at its
+/// template, so that the links can be planned without writing anything. Until then, a link is
+/// shown as linking to its template.
+pub fn unwritten_to_templates(
+    dotfiles_dir: &Path,
+    symlink_list: &mut SymlinkList,
+    rendered: &[Rendered],
+) {
+    for link in &mut symlink_list.links {
+        let origin = dotfiles_dir.join(&link.origin);
+        let unwritten = rendered
+            .iter()
+            .find(|rendered| rendered.path == origin && !rendered.path.exists());
+        if let Some(rendered) = unwritten {
+            link.origin = rendered.template.to_string_lossy().into_owned();
+        }
+    }
+}
+
+/// Render the origin of `link`. Returns the origin to point the link at instead, and the rendered
+/// file, or `None` if the template doesn't exist.
+fn render_link(
+    dotfiles_dir: &Path,
+    link: &Link,
+    variables: &BTreeMap<String, String>,
+) -> Result<Option<(String, Rendered)>> {
+    let template = dotfiles_dir.join(&link.origin);
+    let Ok(metadata) = fs::metadata(&template) else {
+        return Ok(None);
+    };
+    let contents = render(&fs::read_to_string(&template)?, variables).map_err(|name| {
+        Error::UndefinedTemplateVariable {
+            origin: template.clone(),
+            name,
+        }
+    })?;
+    // Absolute origins, e.g. relative to the symlink list, keep their whole path
+    let relative: PathBuf = template
+        .strip_prefix(dotfiles_dir)
+        .unwrap_or(&template)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    let path = dotfiles_dir.join(DIR_NAME).join(&relative);
+    let origin = if Path::new(&link.origin).is_absolute() {
+        path.clone()
+    } else {
+        Path::new(DIR_NAME).join(relative)
+    };
+    Ok(Some((
+        origin.to_string_lossy().into_owned(),
+        Rendered {
+            template,
+            path,
+            contents,
+            permissions: metadata.permissions(),
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_rendered_and_linked_in_their_place() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("git")).unwrap();
+        fs::write(
+            dir.path().join("git/gitconfig"),
+            "[user]\n  email = {{ email }}\n  name = {{name}}\n# {{ not a name }} on {{ os }}\n",
+        )
+        .unwrap();
+        let mut symlink_list: SymlinkList = serde_yaml::from_str(
+            "vars:\n  email: me@work.example\nlinks:\n  - {path: ~/.gitconfig, origin: \
+             git/gitconfig, template: true}\n  - {path: ~/.zshrc, origin: zshrc}\n",
+        )
+        .unwrap();
+        let mut variables = variables(&symlink_list.vars, "laptop");
+
+        assert_eq!(
+            render_all(dir.path(), &mut symlink_list, &variables).map_err(|e| e.to_string()),
+            Err(Error::UndefinedTemplateVariable {
+                origin: dir.path().join("git/gitconfig"),
+                name: "name".to_owned()
+            }
+            .to_string())
+        );
+        variables.insert("name".to_owned(), "Me".to_owned());
+        let rendered = render_all(dir.path(), &mut symlink_list, &variables).unwrap();
+        assert_eq!(
+            symlink_list.links[0].origin,
+            format!("{}/git/gitconfig", DIR_NAME)
+        );
+        assert_eq!(symlink_list.links[1].origin, "zshrc");
+        // Nothing is written until the rendered files are
+        assert!(!dir.path().join(DIR_NAME).exists());
+        unwritten_to_templates(dir.path(), &mut symlink_list, &rendered);
+        assert_eq!(
+            symlink_list.links[0].origin,
+            dir.path().join("git/gitconfig").to_string_lossy()
+        );
+        assert!(!rendered[0].is_current());
+        rendered[0].write().unwrap();
+        assert!(rendered[0].is_current());
+        assert_eq!(
+            fs::read_to_string(dir.path().join(DIR_NAME).join("git/gitconfig")).unwrap(),
+            format!(
+                "[user]\n  email = me@work.example\n  name = Me\n# {{{{ not a name }}}} on {}\n",
+                env::consts::OS
+            )
+        );
+    }
+}
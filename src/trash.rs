@@ -0,0 +1,317 @@
+//! A directory that files in the way of links can be moved into instead of being backed up next to
+//! the link, with an index of where each file came from. Backups go into one at
+//! `$XDG_DATA_HOME/dotconfig/backups` by default, and `--trash` uses another.
+
+use crate::{backup::BACKUP_DATE_FORMAT, expand_path, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+/// A file that was moved into the trash.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Where the file was before it was trashed.
+    pub original: PathBuf,
+    /// Where the file is in the trash.
+    pub trashed: PathBuf,
+    /// When the file was trashed, in RFC 3339 format.
+    pub date: String,
+}
+
+impl Entry {
+    /// Returns when the file was trashed, or `None` if [`Entry::date`] is not valid.
+    pub fn time(&self) -> Option<DateTime<Local>> {
+        let date = DateTime::parse_from_rfc3339(&self.date).ok()?;
+        Some(date.with_timezone(&Local))
+    }
+}
+
+/// A trash directory. Files are kept under a directory named after the date they were trashed,
+/// at their original path, e.g. `2022-08-01-13-45-07/home/me/.zshrc`. Every trashed file is also
+/// recorded in `index.json`, so that where a file came from never has to be guessed from where it
+/// is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trash {
+    pub dir: PathBuf,
+}
+
+impl Trash {
+    /// Returns the trash directory of the current user, `$XDG_DATA_HOME/dotconfig/trash`.
+    ///
+    /// # Errors
+    /// + [`Error::UnknownHomeDir`](crate::Error::UnknownHomeDir) if the home directory cannot be
+    ///   determined.
+    pub fn new() -> Result<Self> {
+        Ok(Trash::at(expand_path(&"$XDG_DATA_HOME/dotconfig/trash")?))
+    }
+
+    /// Returns the directory that backups are moved into by default,
+    /// `$XDG_DATA_HOME/dotconfig/backups`.
+    ///
+    /// # Errors
+    /// + [`Error::UnknownHomeDir`](crate::Error::UnknownHomeDir) if the home directory cannot be
+    ///   determined.
+    pub fn backups() -> Result<Self> {
+        Ok(Trash::at(expand_path(&"$XDG_DATA_HOME/dotconfig/backups")?))
+    }
+
+    /// Returns the trash directory at `dir`.
+    pub fn at(dir: PathBuf) -> Self {
+        Trash { dir }
+    }
+
+    /// Returns the path that `original` is moved to when it is trashed now.
+    pub fn path_for(&self, original: &Path) -> PathBuf {
+        let relative: PathBuf = original
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        self.dir
+            .join(chrono::Local::now().format(BACKUP_DATE_FORMAT).to_string())
+            .join(relative)
+    }
+
+    /// Returns every file recorded in the index, in the order they were trashed.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the index exists but cannot be read.
+    /// + [`Error::JsonError`](crate::Error::JsonError) if the index is not valid.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let index = self.index_path();
+        if !index.exists() {
+            return Ok(vec![]);
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(index)?)?)
+    }
+
+    /// Add a file that was moved from `original` to `trashed` to the index.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the index cannot be read or written.
+    /// + [`Error::JsonError`](crate::Error::JsonError) if the existing index is not valid.
+    pub fn record(&self, original: &Path, trashed: &Path) -> Result<()> {
+        let mut entries = self.entries()?;
+        entries.push(Entry {
+            original: original.to_owned(),
+            trashed: trashed.to_owned(),
+            date: chrono::Local::now().to_rfc3339(),
+        });
+        self.write(&entries)
+    }
+
+    /// Returns the most recent entry for a file that was at `original`, if there is one.
+    ///
+    /// # Errors
+    /// See [`Trash::entries`].
+    pub fn latest(&self, original: &Path) -> Result<Option<Entry>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .rev()
+            .find(|entry| entry.original == original))
+    }
+
+    /// Move the file of `entry` back to where it came from, and remove it from the index. A symlink
+    /// at its original path, such as a link that replaced it, is removed first.
+    ///
+    /// # Errors
+    /// + [`Error::RestoreBlocked`](crate::Error::RestoreBlocked) if something other than a symlink
+    ///   is at the original path.
+    /// + [`Error::IoError`](crate::Error::IoError) if the file cannot be moved, or the index cannot
+    ///   be written.
+    pub fn restore(&self, entry: &Entry) -> Result<()> {
+        match fs::symlink_metadata(&entry.original) {
+            Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(&entry.original)?,
+            Ok(_) => return Err(crate::Error::RestoreBlocked(entry.original.clone())),
+            Err(_) => {}
+        }
+        if let Some(parent) = entry.original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&entry.trashed, &entry.original)?;
+        self.forget(&entry.trashed)
+    }
+
+    /// Returns the entries of the files that were trashed at or after `since`, oldest first.
+    /// Entries whose [`Entry::date`] is not valid are left out.
+    ///
+    /// # Errors
+    /// See [`Trash::entries`].
+    pub fn entries_since(&self, since: DateTime<Local>) -> Result<Vec<Entry>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|entry| entry.time().is_some_and(|time| time >= since))
+            .collect())
+    }
+
+    /// Delete all but the `keep` most recent files that were at each original path, and remove
+    /// them from the index. If `before` is set, only files that were trashed before it are
+    /// deleted, and files whose [`Entry::date`] is not valid are kept. Files that were already
+    /// deleted by hand are only removed from the index.
+    ///
+    /// Returns the entries that were deleted, in the order they were trashed.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if a file cannot be deleted, or the index cannot
+    ///   be read or written.
+    /// + [`Error::JsonError`](crate::Error::JsonError) if the index is not valid.
+    pub fn prune(&self, keep: usize, before: Option<DateTime<Local>>) -> Result<Vec<Entry>> {
+        let entries = self.entries()?;
+        let mut pruned = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            let newer = entries[i + 1..]
+                .iter()
+                .filter(|newer| newer.original == entry.original)
+                .count();
+            if newer < keep {
+                continue;
+            }
+            if before.is_some_and(|before| entry.time().is_none_or(|time| time >= before)) {
+                continue;
+            }
+            let removed = match fs::symlink_metadata(&entry.trashed) {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&entry.trashed),
+                Ok(_) => fs::remove_file(&entry.trashed),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e),
+            };
+            removed?;
+            self.forget(&entry.trashed)?;
+            pruned.push(entry.clone());
+        }
+        Ok(pruned)
+    }
+
+    /// Remove the file at `trashed` from the index, along with the directories that held it in the
+    /// trash if they are now empty. The file itself is left as it is.
+    ///
+    /// # Errors
+    /// + [`Error::IoError`](crate::Error::IoError) if the index cannot be read or written.
+    /// + [`Error::JsonError`](crate::Error::JsonError) if the index is not valid.
+    pub fn forget(&self, trashed: &Path) -> Result<()> {
+        let mut entries = self.entries()?;
+        entries.retain(|entry| entry.trashed != trashed);
+        self.write(&entries)?;
+        for dir in trashed.ancestors().skip(1) {
+            if dir == self.dir || !dir.starts_with(&self.dir) || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, entries: &[Entry]) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut json = serde_json::to_string_pretty(entries)?;
+        json.push('\n');
+        Ok(fs::write(self.index_path(), json)?)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trashed_files_keep_their_original_path_and_are_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::at(dir.path().to_owned());
+        let original = Path::new("/home/me/.zshrc");
+
+        let trashed = trash.path_for(original);
+        assert!(trashed.starts_with(dir.path()));
+        assert!(trashed.ends_with("home/me/.zshrc"));
+
+        trash.record(original, &trashed).unwrap();
+        trash
+            .record(Path::new("/home/me/.vimrc"), Path::new("/x"))
+            .unwrap();
+        let entries = trash.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].original, original);
+        assert_eq!(entries[0].trashed, trashed);
+        assert_eq!(entries[1].original, Path::new("/home/me/.vimrc"));
+    }
+
+    #[test]
+    fn backups_are_pruned_and_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::at(dir.path().join("backups"));
+        let original = dir.path().join("home/.zshrc");
+        for (i, contents) in ["first", "second", "third"].iter().enumerate() {
+            let trashed = trash.dir.join(i.to_string()).join(".zshrc");
+            fs::create_dir_all(trashed.parent().unwrap()).unwrap();
+            fs::write(&trashed, contents).unwrap();
+            trash.record(&original, &trashed).unwrap();
+        }
+
+        let pruned = trash.prune(2, None).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert!(!pruned[0].trashed.exists());
+        assert!(!trash.dir.join("0").exists());
+        assert_eq!(trash.entries().unwrap().len(), 2);
+
+        fs::create_dir_all(original.parent().unwrap()).unwrap();
+        fs::write(&original, "in the way").unwrap();
+        let latest = trash.latest(&original).unwrap().unwrap();
+        assert!(matches!(
+            trash.restore(&latest),
+            Err(crate::Error::RestoreBlocked(_))
+        ));
+        fs::remove_file(&original).unwrap();
+        crate::platform::symlink("/nowhere", &original).unwrap();
+        trash.restore(&latest).unwrap();
+        assert_eq!(fs::read_to_string(&original).unwrap(), "third");
+        assert_eq!(trash.entries().unwrap().len(), 1);
+        assert_eq!(
+            trash.latest(&original).unwrap().unwrap().trashed,
+            trash.dir.join("1/.zshrc")
+        );
+    }
+
+    #[test]
+    fn backups_are_pruned_and_listed_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::at(dir.path().join("backups"));
+        let now = Local::now();
+        let mut entries = vec![];
+        for (days, name) in [(40, "old"), (10, "recent"), (1, "new")] {
+            let trashed = trash.dir.join(name).join(".zshrc");
+            fs::create_dir_all(trashed.parent().unwrap()).unwrap();
+            fs::write(&trashed, name).unwrap();
+            entries.push(Entry {
+                original: dir.path().join("home/.zshrc"),
+                trashed,
+                date: (now - chrono::Duration::days(days)).to_rfc3339(),
+            });
+        }
+        trash.write(&entries).unwrap();
+
+        let since = trash
+            .entries_since(now - chrono::Duration::days(7))
+            .unwrap();
+        assert_eq!(since, entries[2..]);
+
+        let pruned = trash
+            .prune(0, Some(now - chrono::Duration::days(30)))
+            .unwrap();
+        assert_eq!(pruned, entries[..1]);
+        assert!(!entries[0].trashed.exists());
+        assert_eq!(trash.entries().unwrap(), entries[1..]);
+
+        // The most recent backups are kept even if they are old enough
+        let pruned = trash
+            .prune(2, Some(now - chrono::Duration::days(5)))
+            .unwrap();
+        assert!(pruned.is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+//! A tree view of which files in the dotfiles directory are linked where, and the other way around,
+//! and a list of the files that aren't linked anywhere.
+
+use crate::{config::SymlinkList, expand_path, normalize, planner::resolve_link, Result};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// A file or directory in the dotfiles directory.
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<OsString, Node>,
+    /// The links whose origin this is.
+    links: Vec<PathBuf>,
+    exists: bool,
+    is_dir: bool,
+    /// Whether a link that isn't optional has this as its origin.
+    required: bool,
+}
+
+impl Node {
+    /// Returns the node at `relative`, creating it and its ancestors if they don't exist.
+    fn get_or_insert(&mut self, relative: &Path) -> &mut Node {
+        relative.components().fold(self, |node, component| {
+            node.children
+                .entry(component.as_os_str().to_owned())
+                .or_default()
+        })
+    }
+
+    /// Returns whether this node or any of its descendants is an origin.
+    fn has_links(&self) -> bool {
+        !self.links.is_empty() || self.children.values().any(Node::has_links)
+    }
+}
+
+/// A link, as shown under the directory it is created in.
+struct LinkEntry {
+    name: OsString,
+    origin: PathBuf,
+    missing: bool,
+}
+
+/// Returns two trees. The first is the dotfiles directory, with the links of each origin next to
+/// it. The second lists the directories links are created in, with the origin of each link.
+///
+/// Files in the dotfiles directory that no link refers to are marked as not linked, and origins
+/// that don't exist are marked as missing. The contents of a directory that is linked as a whole
+/// are left out. Unlike [`plan`](crate::planner::plan), this doesn't fail if an origin is missing.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory that holds the config files.
+/// + `symlink_list` - The links to show.
+/// + `ignore` - Files to leave out of the dotfiles directory, e.g. the symlink lists themselves.
+/// + `max_depth` - How many levels of the dotfiles directory to look at, or `None` for all of them.
+///   Origins are shown however deep they are.
+///
+/// # Errors
+/// + [`Error::IoError`](crate::Error::IoError) if the dotfiles directory cannot be read.
+/// + [`Error::LinkError`](crate::Error::LinkError) if a link whose origin is a glob sets `rename`.
+pub fn render(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    ignore: &[PathBuf],
+    max_depth: Option<usize>,
+) -> Result<String> {
+    let (dotfiles_dir, root, link_dirs) = build(dotfiles_dir, symlink_list, ignore, max_depth)?;
+    let mut tree = format!("{}/\n", dotfiles_dir.display());
+    render_children(&root, 1, false, &mut tree);
+    for (dir, links) in link_dirs {
+        write!(tree, "\n{}/\n", dir.display()).ok();
+        for link in links {
+            writeln!(
+                tree,
+                "  {} <- {}{}",
+                link.name.to_string_lossy(),
+                link.origin.display(),
+                if link.missing {
+                    format!(" {}", Paint::red("(missing)"))
+                } else {
+                    String::new()
+                }
+            )
+            .ok();
+        }
+    }
+    Ok(tree)
+}
+
+/// Returns the files in the dotfiles directory that no link refers to, relative to it, in
+/// alphabetical order. Files in a directory that is linked as a whole are referred to by that link.
+///
+/// `.git`, READMEs, licenses and `.gitignore` at the top of the dotfiles directory are never
+/// reported.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory that holds the config files.
+/// + `symlink_list` - The links that refer to files.
+/// + `ignore` - Files and directories to leave out, e.g. the symlink lists themselves.
+/// + `max_depth` - How many levels of the dotfiles directory to look at, or `None` for all of them.
+///   Files below that are never reported.
+///
+/// # Errors
+/// + [`Error::IoError`](crate::Error::IoError) if the dotfiles directory cannot be read.
+/// + [`Error::LinkError`](crate::Error::LinkError) if a link whose origin is a glob sets `rename`.
+pub fn orphans(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    ignore: &[PathBuf],
+    max_depth: Option<usize>,
+) -> Result<Vec<PathBuf>> {
+    let (_, mut root, _) = build(dotfiles_dir, symlink_list, ignore, max_depth)?;
+    root.children.retain(|name, _| {
+        let name = name.to_string_lossy();
+        !(name.starts_with("README") || name.starts_with("LICENSE") || name == ".gitignore")
+    });
+    let mut orphans = vec![];
+    collect_orphans(&root, Path::new(""), &mut orphans);
+    Ok(orphans)
+}
+
+/// Returns the canonical dotfiles directory, a tree of its contents down to `max_depth` and of the
+/// origins in `symlink_list`, and the links in `symlink_list` by the directory they are created in.
+fn build(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    ignore: &[PathBuf],
+    max_depth: Option<usize>,
+) -> Result<(PathBuf, Node, BTreeMap<PathBuf, Vec<LinkEntry>>)> {
+    let dotfiles_dir = fs::canonicalize(dotfiles_dir)?;
+    let symlink_list = &symlink_list.expand_origins(&dotfiles_dir)?;
+    let ignore: Vec<_> = ignore
+        .iter()
+        .filter_map(|p| fs::canonicalize(p).ok())
+        .collect();
+    let mut root = Node {
+        exists: true,
+        is_dir: true,
+        ..Node::default()
+    };
+    walk(&dotfiles_dir, &ignore, max_depth, &mut root)?;
+
+    let mut link_dirs: BTreeMap<PathBuf, Vec<LinkEntry>> = BTreeMap::new();
+    for entry in &symlink_list.links {
+        let origin = dotfiles_dir.join(&entry.origin);
+        // Origins may be absolute, if they are relative to the symlink list
+        let relative = normalize(&origin)
+            .strip_prefix(&dotfiles_dir)
+            .map(Path::to_owned)
+            .unwrap_or_else(|_| normalize(&origin));
+        let metadata = fs::symlink_metadata(&origin).ok();
+        let node = root.get_or_insert(&relative);
+        node.exists = metadata.is_some();
+        node.is_dir = metadata.is_some_and(|m| m.is_dir());
+        node.required |= !entry.optional();
+        for path in &entry.path {
+            let link = resolve_link(path, entry.rename.as_deref(), &origin, None)
+                .or_else(|_| expand_path(path))
+                .unwrap_or_else(|_| PathBuf::from(path));
+            node.links.push(link.clone());
+            let parent = link.parent().unwrap_or(Path::new("/")).to_owned();
+            link_dirs.entry(parent).or_default().push(LinkEntry {
+                name: link.file_name().unwrap_or_default().to_owned(),
+                origin: relative.clone(),
+                missing: !node.exists,
+            });
+        }
+    }
+
+    Ok((dotfiles_dir, root, link_dirs))
+}
+
+/// Add every file and directory in `dir` to `node`, except `.git` and the files in `ignore`, going
+/// at most `max_depth` levels deep. Symlinks are not followed.
+fn walk(dir: &Path, ignore: &[PathBuf], max_depth: Option<usize>, node: &mut Node) -> Result<()> {
+    if max_depth == Some(0) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == ".git" || ignore.contains(&entry.path()) {
+            continue;
+        }
+        let is_dir = entry.file_type()?.is_dir();
+        let child = node.children.entry(name.clone()).or_default();
+        child.exists = true;
+        child.is_dir = is_dir;
+        if is_dir {
+            walk(&entry.path(), ignore, max_depth.map(|d| d - 1), child)?;
+        }
+    }
+    Ok(())
+}
+
+/// Append a line for each child of `node` to `tree`, indented by `depth`, followed by its
+/// children.
+///
+/// # Params
+/// + `linked` - Whether an ancestor of `node` is linked as a whole, in which case its children are
+///   only shown if they are linked themselves.
+fn render_children(node: &Node, depth: usize, linked: bool, tree: &mut String) {
+    for (name, child) in &node.children {
+        if linked && !child.has_links() {
+            continue;
+        }
+        let mut line = format!("{}{}", "  ".repeat(depth), name.to_string_lossy());
+        if child.is_dir {
+            line.push('/');
+        }
+        if !child.links.is_empty() {
+            let links: Vec<_> = child
+                .links
+                .iter()
+                .map(|l| l.display().to_string())
+                .collect();
+            write!(line, " -> {}", links.join(", ")).ok();
+        }
+        if !child.exists {
+            if child.required {
+                write!(line, " {}", Paint::red("(missing)")).ok();
+            } else {
+                write!(line, " {}", Paint::yellow("(missing, optional)")).ok();
+            }
+        } else if !linked && !child.is_dir && child.links.is_empty() {
+            write!(line, " {}", Paint::yellow("(not linked)")).ok();
+        }
+        tree.push_str(&line);
+        tree.push('\n');
+        render_children(child, depth + 1, linked || !child.links.is_empty(), tree);
+    }
+}
+
+/// Append the path of every file under `node` that isn't an origin, and isn't in a directory that
+/// is an origin, to `orphans`.
+fn collect_orphans(node: &Node, relative: &Path, orphans: &mut Vec<PathBuf>) {
+    for (name, child) in &node.children {
+        if !child.links.is_empty() || !child.exists {
+            continue;
+        }
+        let path = relative.join(name);
+        if child.is_dir {
+            collect_orphans(child, &path, orphans);
+        } else {
+            orphans.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orphans_and_missing_origins_are_marked() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        fs::create_dir_all(dotfiles_dir.join("nvim/lua")).unwrap();
+        fs::write(dotfiles_dir.join("nvim/lua/init.lua"), "").unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        fs::write(dotfiles_dir.join("bashrc"), "").unwrap();
+        fs::write(dotfiles_dir.join("symlinks.yml"), "").unwrap();
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "links:\n  - {path: /home/me/.zshrc, origin: zshrc}\n  - {path: /home/me/.config/nvim, \
+             origin: nvim}\n  - {path: /home/me/.vimrc, origin: vimrc}\n",
+        )
+        .unwrap();
+
+        Paint::disable();
+        let tree = render(
+            &dotfiles_dir,
+            &symlink_list,
+            &[dotfiles_dir.join("symlinks.yml")],
+            None,
+        )
+        .unwrap();
+        let dotfiles_dir = fs::canonicalize(&dotfiles_dir).unwrap();
+        assert_eq!(
+            tree,
+            format!(
+                "{}/\n  bashrc (not linked)\n  nvim/ -> /home/me/.config/nvim\n  vimrc -> \
+                 /home/me/.vimrc (missing)\n  zshrc -> /home/me/.zshrc\n\n/home/me/\n  .zshrc <- \
+                 zshrc\n  .vimrc <- vimrc (missing)\n\n/home/me/.config/\n  nvim <- nvim\n",
+                dotfiles_dir.display()
+            )
+        );
+    }
+
+    #[test]
+    fn orphans_are_files_that_no_link_refers_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path();
+        for file in [
+            "zshrc",
+            "README.md",
+            "nvim/init.lua",
+            "scripts/setup.sh",
+            "alacritty/alacritty.yml",
+            "alacritty/themes/dark.yml",
+        ] {
+            let path = dotfiles_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let symlink_list: SymlinkList = serde_yaml::from_str(
+            "links:\n  - {path: /home/me/.zshrc, origin: zshrc}\n  - {path: /home/me/.config/nvim, \
+             origin: nvim}\n  - {path: /home/me/.config/alacritty/, origin: \
+             alacritty/alacritty.yml}\n",
+        )
+        .unwrap();
+
+        let ignore = [dotfiles_dir.join("scripts")];
+        let orphans_at = |max_depth| orphans(dotfiles_dir, &symlink_list, &ignore, max_depth);
+        assert_eq!(
+            orphans_at(None).unwrap(),
+            [Path::new("alacritty/themes/dark.yml")]
+        );
+        assert!(orphans_at(Some(2)).unwrap().is_empty());
+    }
+}
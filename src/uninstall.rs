@@ -0,0 +1,452 @@
+//! Removing the links in a symlink list, to undo an installation.
+
+use crate::{
+    backup::backup_time,
+    config::{Source, SymlinkList},
+    install::Outcome,
+    log::{self, count, Status},
+    planner::resolve_link,
+    platform,
+    prompt::confirm,
+    state::{self, State},
+    trash::Trash,
+    Result, TargetRoot,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+/// What uninstalling does with the path of a single link.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Removal {
+    /// A symlink to `target`, inside of the dotfiles directory, is at `link`, and is removed. If
+    /// `restore` is set, that backup is then moved back to `link`.
+    Remove {
+        link: PathBuf,
+        target: PathBuf,
+        restore: Option<PathBuf>,
+    },
+    /// Nothing is at `link`.
+    Missing { link: PathBuf },
+    /// Something other than a symlink into the dotfiles directory is at `link`, and is left alone.
+    /// This includes hard links, copies and decrypted files, which can't be told apart from files
+    /// that dotconfig didn't create.
+    Foreign { link: PathBuf },
+}
+
+impl Removal {
+    /// The path of the link.
+    pub fn link(&self) -> &Path {
+        match self {
+            Removal::Remove { link, .. }
+            | Removal::Missing { link }
+            | Removal::Foreign { link } => link,
+        }
+    }
+}
+
+/// Returns what uninstalling does with each path of each link in `symlink_list`, in order, followed
+/// by the symlinks into `dotfiles_dir` that `state` records as installed but that are no longer in
+/// `symlink_list`. Links whose origin is a glob or that are
+/// [`DirMode::Recurse`](crate::config::DirMode::Recurse) are expanded into a link per file, and the
+/// directories created for them are left in place.
+///
+/// # Params
+/// + `dotfiles_dir` - The directory the origins are in. Only symlinks that point inside of it are
+///   removed.
+/// + `root` - The directory the links were installed inside of, if any (see [`TargetRoot`]).
+/// + `restore` - Whether to restore the most recent backup of each removed link: the most recent
+///   `<filename>-backup-<date>` next to it, or the most recent one in `backups`, whichever is
+///   newer.
+/// + `backups` - The [`Trash`] that backups were moved into, if any.
+/// + `state` - The symlinks that were installed, if known.
+///
+/// # Errors
+/// + [`Error::LinkError`](crate::Error::LinkError) if a link's `path` and `rename` don't specify
+///   exactly one filename.
+/// + [`Error::ShellexpandLookupError`](crate::Error::ShellexpandLookupError) if a link's `path`
+///   references an unset shell variable.
+/// + [`Error::JsonError`](crate::Error::JsonError) if the index of `backups` is not valid.
+pub fn plan(
+    dotfiles_dir: &Path,
+    symlink_list: &SymlinkList,
+    root: Option<&TargetRoot>,
+    restore: bool,
+    backups: Option<&Trash>,
+    state: Option<&State>,
+) -> Result<Vec<Removal>> {
+    let canonical_dir = fs::canonicalize(dotfiles_dir).unwrap_or_else(|_| dotfiles_dir.to_owned());
+    let is_inside =
+        |path: &Path| path.starts_with(dotfiles_dir) || path.starts_with(&canonical_dir);
+    let symlink_list = symlink_list.expand_origins(&dotfiles_dir)?;
+    let mut removals = vec![];
+    for entry in &symlink_list.links {
+        let origin = dotfiles_dir.join(&entry.origin);
+        for path in &entry.path {
+            let link = resolve_link(path, entry.rename.as_deref(), &origin, root)?;
+            let Ok(metadata) = fs::symlink_metadata(&link) else {
+                removals.push(Removal::Missing { link });
+                continue;
+            };
+            if !metadata.file_type().is_symlink() {
+                removals.push(Removal::Foreign { link });
+                continue;
+            }
+            let target = fs::read_link(&link)?;
+            let absolute = link.parent().unwrap_or(Path::new("/")).join(&target);
+            let canonical = fs::canonicalize(&absolute).unwrap_or_else(|_| absolute.clone());
+            if !is_inside(&absolute) && !is_inside(&canonical) {
+                removals.push(Removal::Foreign { link });
+                continue;
+            }
+            let restore = if restore {
+                latest_of(&link, backups)?
+            } else {
+                None
+            };
+            removals.push(Removal::Remove {
+                link,
+                target,
+                restore,
+            });
+        }
+    }
+
+    let links: Vec<_> = removals
+        .iter()
+        .map(|removal| removal.link().to_owned())
+        .collect();
+    for installed in state.map_or(vec![], |state| state.orphans(dotfiles_dir, &links)) {
+        let restore = if restore {
+            latest_of(&installed.link, backups)?
+        } else {
+            None
+        };
+        removals.push(Removal::Remove {
+            link: installed.link.clone(),
+            target: fs::read_link(&installed.link)?,
+            restore,
+        });
+    }
+    Ok(removals)
+}
+
+/// Settings for [`uninstall`].
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Move the most recent backup of each removed link back in its place.
+    pub restore: bool,
+    /// The directory that backups were moved into, if any.
+    pub backup_dir: Option<PathBuf>,
+    /// Remove without asking for confirmation.
+    pub yes: bool,
+    /// Cancel if the confirmation prompt is answered with an empty line.
+    pub default_no: bool,
+    /// The state file that records installed symlinks, rather than the default one.
+    pub state: Option<PathBuf>,
+    /// The directory the links were installed inside of, if any.
+    pub root: Option<TargetRoot>,
+}
+
+/// Show what uninstalling the symlink lists from `source` removes (see [`plan`]), and remove it
+/// once the user confirms. Removed links are forgotten by the state file.
+///
+/// Returns [`Outcome::Failed`] if a link could not be removed.
+///
+/// # Errors
+/// + Any error from [`Source::read`].
+/// + Any error from [`plan`].
+/// + [`Error::NotATerminal`](crate::Error::NotATerminal) if confirmation is needed and stdin is not
+///   a terminal.
+pub fn uninstall(source: &Source, options: &Options) -> Result<Outcome> {
+    let (symlink_list, origin_dir) = source.read()?;
+    let backups = options.backup_dir.clone().map(Trash::at);
+    let mut state = state::read(options.state.as_deref());
+    let removals = plan(
+        &origin_dir,
+        &symlink_list,
+        options.root.as_ref(),
+        options.restore,
+        backups.as_ref(),
+        state.as_ref().map(|(_, state)| state),
+    )?;
+
+    let mut to_remove = 0;
+    for removal in &removals {
+        match removal {
+            Removal::Remove {
+                link,
+                target,
+                restore,
+            } => {
+                to_remove += 1;
+                log::info(format!(
+                    "{}{} {} {} {}",
+                    log::symbol(Status::Remove),
+                    Paint::yellow("Will remove:"),
+                    link.display(),
+                    Paint::yellow("->"),
+                    target.display()
+                ));
+                if let Some(backup) = restore {
+                    log::info(format!(
+                        "{}{} {}",
+                        log::symbol(Status::Backup),
+                        Paint::yellow("  and restore:"),
+                        backup.display()
+                    ));
+                }
+            }
+            Removal::Missing { link } => log::debug(format!(
+                "{}{} {}",
+                log::symbol(Status::Skip),
+                Paint::blue("Not installed:"),
+                link.display()
+            )),
+            Removal::Foreign { link } => log::warn(format!(
+                "{}{} {} {}",
+                log::symbol(Status::Keep),
+                Paint::yellow("Leaving"),
+                link.display(),
+                Paint::yellow("alone, since it isn't a symlink into the dotfiles directory.")
+            )),
+        }
+    }
+    if to_remove == 0 {
+        log::info("Nothing to uninstall.");
+        return Ok(Outcome::Done);
+    }
+    if !options.yes
+        && !confirm(
+            &format!("Remove {}?", count(to_remove, "link")),
+            !options.default_no,
+        )?
+    {
+        log::info("Uninstallation cancelled.");
+        return Ok(Outcome::Cancelled);
+    }
+
+    let mut outcome = Outcome::Done;
+    for removal in removals
+        .iter()
+        .filter(|removal| matches!(removal, Removal::Remove { .. }))
+    {
+        let removed = remove(removal, backups.as_ref());
+        if let (Ok(()), Some((_, state))) = (&removed, &mut state) {
+            state.forget(removal.link());
+        }
+        match removed {
+            Ok(()) => match removal {
+                Removal::Remove {
+                    link,
+                    restore: Some(backup),
+                    ..
+                } => log::info(format!(
+                    "{}{} {} {} {}",
+                    log::symbol(Status::Backup),
+                    Paint::green("Removed"),
+                    link.display(),
+                    Paint::green("and restored"),
+                    backup.display()
+                )),
+                _ => log::info(format!(
+                    "{}{} {}",
+                    log::symbol(Status::Remove),
+                    Paint::green("Removed"),
+                    removal.link().display()
+                )),
+            },
+            Err(e) => {
+                log::error(format!(
+                    "{} {}: {}",
+                    Paint::red("Failed to remove"),
+                    removal.link().display(),
+                    e
+                ));
+                outcome = Outcome::Failed;
+            }
+        }
+    }
+    if let Some((path, state)) = &state {
+        state::write(state, path);
+    }
+    Ok(outcome)
+}
+
+/// Returns the most recent backup of `link`: the most recent `<filename>-backup-<date>` next to it,
+/// or the most recent one in `backups`, whichever is newer.
+fn latest_of(link: &Path, backups: Option<&Trash>) -> Result<Option<PathBuf>> {
+    let Some(backups) = backups else {
+        return Ok(latest_backup(link));
+    };
+    let trashed = backups
+        .latest(link)?
+        .and_then(|entry| Some((entry.time()?, entry.trashed)));
+    let in_place = latest_backup(link).and_then(|backup| Some((backup_time(&backup)?, backup)));
+    Ok(trashed.max(in_place).map(|(_, backup)| backup))
+}
+
+/// Carry out a [`Removal::Remove`]: remove the symlink, then move its backup back in its place,
+/// and remove the backup from the index of `backups` if it was there. Other removals do nothing.
+///
+/// # Errors
+/// + [`Error::IoError`](crate::Error::IoError) if the symlink cannot be removed, the backup cannot
+///   be moved, or the index cannot be written.
+pub fn remove(removal: &Removal, backups: Option<&Trash>) -> Result<()> {
+    if let Removal::Remove { link, restore, .. } = removal {
+        fs::remove_file(link)?;
+        if let Some(backup) = restore {
+            fs::rename(backup, link)?;
+            if let Some(backups) = backups.filter(|backups| backup.starts_with(&backups.dir)) {
+                backups.forget(backup)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the symlink at `link`, if there is one, with a copy of `origin`, so that the file stays
+/// where it was after it is no longer linked. Symlinks inside of a directory are copied as
+/// symlinks.
+///
+/// # Errors
+/// + [`Error::IoError`](crate::Error::IoError) if the symlink cannot be removed, or `origin` cannot
+///   be copied.
+pub fn restore_origin(origin: &Path, link: &Path) -> Result<()> {
+    if fs::symlink_metadata(link).is_ok() {
+        fs::remove_file(link)?;
+    }
+    copy(origin, link)
+}
+
+/// Copy `from` to `to`, with everything in it if it is a directory.
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        platform::symlink(fs::read_link(from)?, to)?;
+    } else if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Returns the most recent `<filename>-backup-<date>` in the same directory as `link`, if there is
+/// one.
+pub fn latest_backup(link: &Path) -> Option<PathBuf> {
+    let file_name = link.file_name()?.to_str()?;
+    let prefix = format!("{}-backup-", file_name);
+    fs::read_dir(link.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .filter_map(|path| Some((backup_time(&path)?, path)))
+        .max()
+        .map(|(_, path)| path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::symlink;
+
+    #[test]
+    fn only_symlinks_into_the_dotfiles_dir_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dotfiles_dir = dir.path().join("cfg");
+        let home = dir.path().join("home");
+        fs::create_dir(&dotfiles_dir).unwrap();
+        fs::create_dir(&home).unwrap();
+        fs::write(dotfiles_dir.join("zshrc"), "").unwrap();
+        fs::write(dir.path().join("vimrc"), "").unwrap();
+        symlink(dotfiles_dir.join("zshrc"), home.join(".zshrc")).unwrap();
+        fs::write(home.join(".zshrc-backup-2023-01-01-00-00-00"), "old").unwrap();
+        fs::write(home.join(".zshrc-backup-2024-01-01-00-00-00"), "newer").unwrap();
+        symlink(dir.path().join("vimrc"), home.join(".vimrc")).unwrap();
+        fs::write(home.join(".bashrc"), "").unwrap();
+        fs::write(dotfiles_dir.join("inputrc"), "").unwrap();
+        symlink(dotfiles_dir.join("inputrc"), home.join(".inputrc")).unwrap();
+        let mut state = State::default();
+        state.record(&dotfiles_dir.join("inputrc"), &home.join(".inputrc"));
+        let symlink_list: SymlinkList = serde_yaml::from_str(&format!(
+            "links:\n  \
+             - {{path: {0}/.zshrc, origin: zshrc}}\n  \
+             - {{path: {0}/.vimrc, origin: vimrc}}\n  \
+             - {{path: {0}/.bashrc, origin: bashrc}}\n  \
+             - {{path: {0}/.profile, origin: profile}}\n",
+            home.display()
+        ))
+        .unwrap();
+
+        let removals = plan(&dotfiles_dir, &symlink_list, None, true, None, Some(&state)).unwrap();
+        assert_eq!(
+            removals,
+            vec![
+                Removal::Remove {
+                    link: home.join(".zshrc"),
+                    target: dotfiles_dir.join("zshrc"),
+                    restore: Some(home.join(".zshrc-backup-2024-01-01-00-00-00")),
+                },
+                Removal::Foreign {
+                    link: home.join(".vimrc")
+                },
+                Removal::Foreign {
+                    link: home.join(".bashrc")
+                },
+                Removal::Missing {
+                    link: home.join(".profile")
+                },
+                Removal::Remove {
+                    link: home.join(".inputrc"),
+                    target: dotfiles_dir.join("inputrc"),
+                    restore: None,
+                },
+            ]
+        );
+        for removal in &removals {
+            remove(removal, None).unwrap();
+        }
+        assert_eq!(fs::read_to_string(home.join(".zshrc")).unwrap(), "newer");
+        assert!(home.join(".zshrc-backup-2023-01-01-00-00-00").exists());
+        assert!(fs::symlink_metadata(home.join(".vimrc")).is_ok());
+        assert!(fs::symlink_metadata(home.join(".inputrc")).is_err());
+    }
+
+    #[test]
+    fn origins_are_copied_back_in_place_of_their_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("nvim");
+        let link = dir.path().join(".nvim");
+        fs::create_dir_all(origin.join("lua")).unwrap();
+        fs::write(origin.join("lua/init.lua"), "vim.o.number = true").unwrap();
+        symlink("lua/init.lua", origin.join("init.lua")).unwrap();
+        symlink(&origin, &link).unwrap();
+
+        restore_origin(&origin, &link).unwrap();
+        assert!(!fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(link.join("init.lua")).unwrap(),
+            "vim.o.number = true"
+        );
+        assert_eq!(
+            fs::read_link(link.join("init.lua")).unwrap(),
+            Path::new("lua/init.lua")
+        );
+        assert!(origin.join("lua/init.lua").exists());
+    }
+}